@@ -0,0 +1,134 @@
+use pwasm_utils::{file, logger, CallGraph, CallKind, CallNode};
+use clap::{App, Arg};
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(1)
+}
+
+fn label(index: usize, node: &CallNode) -> String {
+	match &node.name {
+		Some(name) => name.clone(),
+		None => format!("func[{}]", index),
+	}
+}
+
+/// Quote the string, escaping it the same way for DOT and JSON.
+fn quote(value: &str) -> String {
+	let mut quoted = String::from("\"");
+	for c in value.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+			c => quoted.push(c),
+		}
+	}
+	quoted.push('"');
+	quoted
+}
+
+fn kind_name(kind: CallKind) -> &'static str {
+	match kind {
+		CallKind::Direct => "direct",
+		CallKind::Indirect => "indirect",
+	}
+}
+
+fn render_dot(graph: &CallGraph) -> String {
+	let mut out = String::from("digraph callgraph {\n");
+	for (index, node) in graph.nodes().iter().enumerate() {
+		let shape = if node.is_imported() { "box" } else { "ellipse" };
+		out.push_str(&format!("\tf{} [label={}, shape={}];\n", index, quote(&label(index, node)), shape));
+	}
+	for (index, component) in graph.recursive_components().iter().enumerate() {
+		out.push_str(&format!("\tsubgraph cluster_{} {{\n\t\tlabel=\"recursion\";\n\t\tcolor=red;\n", index));
+		for func in component {
+			out.push_str(&format!("\t\tf{};\n", func));
+		}
+		out.push_str("\t}\n");
+	}
+	for edge in graph.edges() {
+		let style = match edge.kind {
+			CallKind::Direct => "",
+			CallKind::Indirect => " [style=dashed]",
+		};
+		out.push_str(&format!("\tf{} -> f{}{};\n", edge.caller, edge.callee, style));
+	}
+	out.push_str("}\n");
+	out
+}
+
+fn render_json(graph: &CallGraph) -> String {
+	let nodes = graph.nodes().iter().enumerate()
+		.map(|(index, node)| format!(
+			"{{\"index\":{},\"name\":{},\"imported\":{}}}",
+			index,
+			node.name.as_deref().map(quote).unwrap_or_else(|| "null".to_string()),
+			node.is_imported(),
+		))
+		.collect::<Vec<_>>();
+	let edges = graph.edges().iter()
+		.map(|edge| format!(
+			"{{\"caller\":{},\"callee\":{},\"kind\":\"{}\"}}",
+			edge.caller,
+			edge.callee,
+			kind_name(edge.kind),
+		))
+		.collect::<Vec<_>>();
+	let recursive = graph.recursive_components().iter()
+		.map(|component| format!(
+			"[{}]",
+			component.iter().map(|func| func.to_string()).collect::<Vec<_>>().join(","),
+		))
+		.collect::<Vec<_>>();
+	format!(
+		"{{\"nodes\":[{}],\"edges\":[{}],\"recursive\":[{}]}}\n",
+		nodes.join(","),
+		edges.join(","),
+		recursive.join(","),
+	)
+}
+
+fn main() {
+	logger::init();
+
+	let matches = App::new("wasm-callgraph")
+		.arg(Arg::with_name("input")
+			.index(1)
+			.required(true)
+			.help("Input WASM or WAT file"))
+		.arg(Arg::with_name("format")
+			.long("format")
+			.short("f")
+			.takes_value(true)
+			.possible_values(&["dot", "json"])
+			.default_value("dot")
+			.help("Output format"))
+		.arg(Arg::with_name("output")
+			.long("output")
+			.short("o")
+			.takes_value(true)
+			.value_name("file")
+			.help("Write the graph to the file instead of stdout"))
+		.get_matches();
+
+	let input = matches.value_of("input").expect("is required; qed");
+
+	let module = file::read_module(input).unwrap_or_else(|err| fail(&err.to_string()));
+	// Names are optional, so the module stays as is if the name section is malformed
+	let module = module.parse_names().unwrap_or_else(|(_, module)| module);
+
+	let graph = CallGraph::new(&module);
+	let rendered = match matches.value_of("format") {
+		Some("json") => render_json(&graph),
+		_ => render_dot(&graph),
+	};
+
+	match matches.value_of("output") {
+		Some(output) => std::fs::write(output, rendered)
+			.unwrap_or_else(|err| fail(&format!("Failed to write {}: {}", output, err))),
+		None => print!("{}", rendered),
+	}
+}
@@ -0,0 +1,13 @@
+//! Checking that the contract can run in the Parity runtime
+
+use pwasm_utils::{self as utils, Policy};
+use parity_wasm::elements;
+
+/// Check the module against the pwasm policy, returning all violations, one per line.
+pub fn check(module: &elements::Module) -> Result<(), String> {
+	let violations = utils::check(module, &Policy::pwasm());
+	if violations.is_empty() {
+		return Ok(());
+	}
+	Err(violations.iter().map(|violation| violation.to_string()).collect::<Vec<_>>().join("\n"))
+}
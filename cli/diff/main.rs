@@ -0,0 +1,123 @@
+use pwasm_utils::{self as utils, file, logger, Change, CodeLine, FunctionDiff};
+use clap::{App, Arg};
+
+/// Exit code when the modules differ, as with `diff`.
+const DIFFERENT: i32 = 1;
+/// Exit code when the modules can't be compared.
+const TROUBLE: i32 = 2;
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(TROUBLE)
+}
+
+fn load(path: &str) -> parity_wasm::elements::Module {
+	file::read_module(path)
+		.unwrap_or_else(|err| fail(&err.to_string()))
+		.parse_names()
+		.unwrap_or_else(|(_, module)| module)
+}
+
+fn print_changes(title: &str, changes: &[Change]) {
+	if changes.is_empty() {
+		return;
+	}
+	println!("{}:", title);
+	for change in changes {
+		match change {
+			Change::Added(name, description) => println!("+ {}: {}", name, description),
+			Change::Removed(name, description) => println!("- {}: {}", name, description),
+			Change::Changed(name, old, new) => println!("~ {}: {} => {}", name, old, new),
+		}
+	}
+	println!();
+}
+
+/// Print changed lines with `context` unchanged lines around them.
+fn print_code(code: &[CodeLine], context: usize) {
+	let changed = code.iter()
+		.enumerate()
+		.filter(|(_, line)| !matches!(line, CodeLine::Same(_)))
+		.map(|(index, _)| index)
+		.collect::<Vec<_>>();
+
+	let mut last_printed = None;
+	for (index, line) in code.iter().enumerate() {
+		let near_change = changed.iter().any(|changed| index + context >= *changed && index <= changed + context);
+		if !near_change {
+			continue;
+		}
+		if last_printed.map_or(index > 0, |last: usize| index > last + 1) {
+			println!("    ...");
+		}
+		match line {
+			CodeLine::Same(line) => println!("     {}", line),
+			CodeLine::Removed(line) => println!("    -{}", line),
+			CodeLine::Added(line) => println!("    +{}", line),
+		}
+		last_printed = Some(index);
+	}
+	if last_printed.is_some_and(|last| last + 1 < code.len()) {
+		println!("    ...");
+	}
+}
+
+fn main() {
+	logger::init();
+
+	let matches = App::new("wasm-diff")
+		.arg(Arg::with_name("old")
+			.index(1)
+			.required(true)
+			.help("Old WASM or WAT file"))
+		.arg(Arg::with_name("new")
+			.index(2)
+			.required(true)
+			.help("New WASM or WAT file"))
+		.arg(Arg::with_name("context")
+			.long("context")
+			.short("c")
+			.takes_value(true)
+			.value_name("lines")
+			.default_value("3")
+			.help("Number of unchanged instructions shown around changes"))
+		.get_matches();
+
+	let old = load(matches.value_of("old").expect("is required; qed"));
+	let new = load(matches.value_of("new").expect("is required; qed"));
+	let context = matches.value_of("context").expect("has default; qed");
+	let context = context.parse().unwrap_or_else(|_| fail(&format!("Invalid --context value `{}`", context)));
+
+	let diff = utils::diff_modules(&old, &new);
+	if diff.is_empty() {
+		return;
+	}
+
+	print_changes("Imports", &diff.imports);
+	print_changes("Exports", &diff.exports);
+	print_changes("Globals", &diff.globals);
+	print_changes("Memory", &diff.memory);
+	print_changes("Tables", &diff.tables);
+	print_changes("Data segments", &diff.data);
+	print_changes("Element segments", &diff.elements);
+	print_changes("Start", &diff.start);
+
+	if !diff.functions.is_empty() {
+		println!("Functions:");
+		for function in diff.functions.iter() {
+			match function {
+				FunctionDiff::Added(name) => println!("+ {}", name),
+				FunctionDiff::Removed(name) => println!("- {}", name),
+				FunctionDiff::Changed { name, signature, code } => {
+					println!("~ {}", name);
+					if let Some((old, new)) = signature {
+						println!("    signature: {} => {}", old, new);
+					}
+					print_code(code, context);
+				},
+			}
+		}
+	}
+
+	std::process::exit(DIFFERENT);
+}
@@ -0,0 +1,12 @@
+//! Replacing emscripten runtime functions with imports
+
+use pwasm_utils as utils;
+use parity_wasm::elements;
+
+/// Functions of the emscripten runtime which are provided by the host.
+const EXTERNALIZED: &[&str] = &["_free", "_malloc", "_memcpy", "_memset", "_memmove"];
+
+/// Replace calls to the runtime functions with calls to the imports.
+pub fn externalize(module: elements::Module) -> Result<elements::Module, utils::PassError> {
+	utils::try_externalize(module, EXTERNALIZED.to_vec())
+}
@@ -1,16 +1,38 @@
+use pwasm_utils::{file, logger};
+use clap::{App, Arg};
+
+mod command;
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(1)
+}
+
 fn main() {
-	pwasm_utils::logger::init();
+	logger::init();
+
+	let matches = App::new("wasm-ext")
+		.arg(Arg::with_name("input")
+			.index(1)
+			.required(true)
+			.help("Input WASM or WAT file"))
+		.arg(Arg::with_name("output")
+			.index(2)
+			.required(true)
+			.help("Output WASM or WAT file"))
+		.arg(Arg::with_name("emit")
+			.long("emit")
+			.takes_value(true)
+			.possible_values(&["wasm", "wat"])
+			.help("Output format, by default WAT for .wat output files and binary otherwise"))
+		.get_matches();
 
-	let args = std::env::args().collect::<Vec<_>>();
-	if args.len() != 3 {
-		println!("Usage: {} input_file.wasm output_file.wasm", args[0]);
-		return;
-	}
+	let input = matches.value_of("input").expect("is required; qed");
+	let output = matches.value_of("output").expect("is required; qed");
 
-	let module = pwasm_utils::externalize(
-		parity_wasm::deserialize_file(&args[1]).expect("Module to deserialize ok"),
-		vec!["_free", "_malloc", "_memcpy", "_memset", "_memmove"],
-	);
+	let module = command::externalize(file::read_module(input).unwrap_or_else(|err| fail(&err.to_string())))
+		.unwrap_or_else(|err| fail(&err.to_string()));
 
-	parity_wasm::serialize_to_file(&args[2], module).expect("Module to serialize ok");
+	file::write_module(output, module, file::Format::for_output(matches.value_of("emit"), output))
+		.unwrap_or_else(|err| fail(&err.to_string()));
 }
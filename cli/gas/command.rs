@@ -0,0 +1,61 @@
+//! Injecting gas metering
+
+use pwasm_utils::{self as utils, CanonicalizeNans, GasMetering, PassError, Pipeline};
+use clap::{Arg, ArgMatches};
+use parity_wasm::elements;
+
+use crate::number::is_u32;
+
+/// Arguments of gas metering.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+	vec![
+		Arg::with_name("gas_module")
+			.long("gas-module")
+			.takes_value(true)
+			.value_name("module")
+			.default_value("env")
+			.help("Module to import the `gas` function from"),
+		Arg::with_name("regular_cost")
+			.long("regular-cost")
+			.takes_value(true)
+			.value_name("cost")
+			.default_value("1")
+			.validator(is_u32)
+			.help("Cost of every instruction"),
+		Arg::with_name("grow_cost")
+			.long("grow-cost")
+			.takes_value(true)
+			.value_name("cost")
+			.default_value("0")
+			.validator(is_u32)
+			.help("Cost of every page allocated with memory.grow, 0 disables the charge"),
+		Arg::with_name("forbid_floats")
+			.long("forbid-floats")
+			.help("Fail if the module uses floating point instructions"),
+		Arg::with_name("canonicalize_nans")
+			.long("canonicalize-nans")
+			.conflicts_with("forbid_floats")
+			.help("Replace NaNs produced by float arithmetic with the canonical NaN, charging for it"),
+	]
+}
+
+/// Meter the module with the rules defined by `args`.
+pub fn gas(module: &elements::Module, matches: &ArgMatches) -> Result<elements::Module, PassError> {
+	let gas_module = matches.value_of("gas_module").expect("has default; qed");
+	let regular_cost = matches.value_of("regular_cost").expect("has default; qed").parse().expect("validated; qed");
+	let grow_cost = matches.value_of("grow_cost").expect("has default; qed").parse().expect("validated; qed");
+
+	let mut rules = utils::rules::Set::new(regular_cost, Default::default()).with_grow_cost(grow_cost);
+	if matches.is_present("forbid_floats") {
+		rules = rules.with_forbidden_floats();
+	}
+
+	let mut pipeline = Pipeline::new();
+	if matches.is_present("canonicalize_nans") {
+		pipeline = pipeline.with_pass(CanonicalizeNans);
+	}
+	let result = pipeline
+		.with_pass(GasMetering::new(&rules, gas_module))
+		.run(module);
+	result
+}
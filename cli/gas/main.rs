@@ -1,21 +1,49 @@
-use pwasm_utils::{self as utils, logger};
-use std::env;
+use pwasm_utils::{file, logger};
+use clap::{App, Arg};
+
+mod command;
+#[path = "../utils/number.rs"]
+mod number;
+
+/// Exit code when the module can't be read or written.
+const EXIT_IO: i32 = 2;
+/// Exit code when the module can't be metered.
+const EXIT_METERING: i32 = 3;
+
+fn fail(code: i32, msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(code)
+}
 
 fn main() {
 	logger::init();
 
-	let args = env::args().collect::<Vec<_>>();
-	if args.len() != 3 {
-		println!("Usage: {} input_file.wasm output_file.wasm", args[0]);
-		return;
-	}
+	let matches = App::new("wasm-gas")
+		.arg(Arg::with_name("input")
+			.index(1)
+			.required(true)
+			.help("Input WASM or WAT file"))
+		.arg(Arg::with_name("output")
+			.index(2)
+			.required(true)
+			.help("Output WASM or WAT file"))
+		.arg(Arg::with_name("emit")
+			.long("emit")
+			.takes_value(true)
+			.possible_values(&["wasm", "wat"])
+			.help("Output format, by default WAT for .wat output files and binary otherwise"))
+		.args(&command::args())
+		.get_matches();
+
+	let input = matches.value_of("input").expect("is required; qed");
+	let output = matches.value_of("output").expect("is required; qed");
 
 	// Loading module
-	let module = parity_wasm::deserialize_file(&args[1]).expect("Module deserialization to succeed");
+	let module = file::read_module(input).unwrap_or_else(|err| fail(EXIT_IO, &err.to_string()));
 
-	let result = utils::inject_gas_counter(
-		module, &utils::rules::Set::default(), "env"
-	).expect("Failed to inject gas. Some forbidden opcodes?");
+	let result = command::gas(&module, &matches)
+		.unwrap_or_else(|err| fail(EXIT_METERING, &format!("Failed to inject gas: {}", err)));
 
-	parity_wasm::serialize_to_file(&args[2], result).expect("Module serialization to succeed")
+	file::write_module(output, result, file::Format::for_output(matches.value_of("emit"), output))
+		.unwrap_or_else(|err| fail(EXIT_IO, &err.to_string()));
 }
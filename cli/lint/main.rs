@@ -0,0 +1,38 @@
+use pwasm_utils::{self as utils, file, logger};
+use clap::{App, Arg};
+
+/// Exit code when something non-deterministic is found.
+const FOUND: i32 = 1;
+/// Exit code when the module can't be linted.
+const TROUBLE: i32 = 2;
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(TROUBLE)
+}
+
+fn main() {
+	logger::init();
+
+	let matches = App::new("wasm-lint")
+		.arg(Arg::with_name("input")
+			.index(1)
+			.required(true)
+			.help("Input WASM or WAT file"))
+		.get_matches();
+
+	let input = matches.value_of("input").expect("is required; qed");
+
+	let module = file::read_module(input).unwrap_or_else(|err| fail(&err.to_string()));
+	// Names only improve the locations, so the module stays as is if the name section is malformed
+	let module = module.parse_names().unwrap_or_else(|(_, module)| module);
+
+	let findings = utils::lint(&module);
+	if findings.is_empty() {
+		return;
+	}
+	for finding in findings.iter() {
+		println!("{}", finding);
+	}
+	std::process::exit(FOUND);
+}
@@ -0,0 +1,18 @@
+//! Packing the contract into its constructor
+
+use pwasm_utils as utils;
+use parity_wasm::elements;
+
+/// Pack the module into its own constructor for the pwasm runtime and optimize the constructor.
+pub fn pack(module: elements::Module) -> Result<elements::Module, String> {
+	let target_runtime = utils::TargetRuntime::pwasm();
+	let ctor_module = module.clone();
+	let raw_module = parity_wasm::serialize(module).map_err(|err| format!("Serialization failed: {}", err))?;
+
+	let mut result_module = utils::pack_instance(raw_module, ctor_module, &target_runtime)
+		.map_err(|err| format!("Packing failed: {}", err))?;
+	// Optimize constructor, since it does not need everything
+	utils::optimize(&mut result_module, vec![target_runtime.symbols().call])
+		.map_err(|err| format!("Optimization failed: {:?}", err))?;
+	Ok(result_module)
+}
@@ -1,33 +1,38 @@
-use pwasm_utils::{self as utils, logger};
+use pwasm_utils::{file, logger};
 use clap::{App, Arg};
 
+mod command;
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(1)
+}
+
 fn main() {
 	logger::init();
 
-	let target_runtime = utils::TargetRuntime::pwasm();
-
 	let matches = App::new("wasm-pack")
 		.arg(Arg::with_name("input")
 			.index(1)
 			.required(true)
-			.help("Input WASM file"))
+			.help("Input WASM or WAT file"))
 		.arg(Arg::with_name("output")
 			.index(2)
 			.required(true)
-			.help("Output WASM file"))
+			.help("Output WASM or WAT file"))
+		.arg(Arg::with_name("emit")
+			.long("emit")
+			.takes_value(true)
+			.possible_values(&["wasm", "wat"])
+			.help("Output format, by default WAT for .wat output files and binary otherwise"))
 		.get_matches();
 
 	let input = matches.value_of("input").expect("is required; qed");
 	let output = matches.value_of("output").expect("is required; qed");
 
-	let module = parity_wasm::deserialize_file(&input).expect("Input module deserialization failed");
-	let ctor_module = module.clone();
-	let raw_module = parity_wasm::serialize(module).expect("Serialization failed");
-
-	// Invoke packer
-	let mut result_module = utils::pack_instance(raw_module, ctor_module, &utils::TargetRuntime::pwasm()).expect("Packing failed");
-	// Optimize constructor, since it does not need everything
-	utils::optimize(&mut result_module, vec![target_runtime.symbols().call]).expect("Optimization failed");
+	let module = file::read_module(input).unwrap_or_else(|err| fail(&err.to_string()));
+	let result_module = command::pack(module).unwrap_or_else(|err| fail(&err));
 
-	parity_wasm::serialize_to_file(&output, result_module).expect("Serialization failed");
+	file::write_module(output, result_module, file::Format::for_output(matches.value_of("emit"), output))
+		.unwrap_or_else(|err| fail(&err.to_string()));
 }
@@ -0,0 +1,111 @@
+//! Removing symbols not used by the exports
+
+use pwasm_utils::{self as utils, OptimizerReport, OptimizerRoot, Symbol};
+use clap::{Arg, ArgMatches};
+use parity_wasm::elements;
+
+use crate::strip;
+
+/// Arguments of the prune pass.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+	let mut args = vec![
+		Arg::with_name("exports")
+			.long("exports")
+			.short("e")
+			.takes_value(true)
+			.value_name("functions")
+			.help("Comma-separated list of exported functions to keep. Default: 'call'"),
+		Arg::with_name("precise_indirect_calls")
+			.long("precise-indirect-calls")
+			.help("Keep table functions only if their signature is used by reachable call_indirect"),
+		Arg::with_name("report")
+			.long("report")
+			.help("Print all removed symbols"),
+		Arg::with_name("why")
+			.long("why")
+			.takes_value(true)
+			.multiple(true)
+			.number_of_values(1)
+			.value_name("name")
+			.help("Print the chain of references that kept the symbol with this name"),
+	];
+	args.extend(strip::args());
+	args
+}
+
+/// Remove symbols not used by the exports, printing the report and retaining paths if requested.
+pub fn prune(mut module: elements::Module, matches: &ArgMatches) -> Result<elements::Module, String> {
+	let target_runtime = utils::TargetRuntime::pwasm();
+	let exports = matches
+		.value_of("exports")
+		.unwrap_or(target_runtime.symbols().call)
+		.split(',')
+		.collect();
+
+	let strip = strip::options(matches);
+	let mut options = utils::OptimizerOptions::default();
+	if matches.is_present("precise_indirect_calls") {
+		options = options.with_precise_indirect_calls();
+	}
+	if strip.is_some() {
+		options = options.with_custom_sections_kept();
+	}
+	let report = utils::optimize_with_options(&mut module, exports, &options)
+		.map_err(|err| format!("Optimizer failed: {:?}", err))?;
+	if let Some(strip) = strip {
+		utils::strip_custom_sections(&mut module, &strip);
+	}
+
+	if matches.is_present("report") {
+		for symbol in report.removed.iter() {
+			println!("removed {}", describe(&report, *symbol));
+		}
+	}
+	for name in matches.values_of("why").into_iter().flatten() {
+		print_why(&report, name);
+	}
+	Ok(module)
+}
+
+fn describe(report: &OptimizerReport, symbol: Symbol) -> String {
+	let kind = match symbol {
+		Symbol::Type(index) => format!("type #{}", index),
+		Symbol::Import(index) => format!("import #{}", index),
+		Symbol::Global(index) => format!("global #{}", index),
+		Symbol::Function(index) => format!("function #{}", index),
+		Symbol::Export(index) => format!("export #{}", index),
+	};
+	match report.name(symbol) {
+		Some(name) => format!("{} `{}`", kind, name),
+		None => kind,
+	}
+}
+
+fn print_why(report: &OptimizerReport, name: &str) {
+	let symbols = report.lookup(name);
+	if symbols.is_empty() {
+		println!("No symbol named `{}` in the module", name);
+	}
+
+	for symbol in symbols {
+		let (path, root) = match report.retaining_path(symbol) {
+			Some(retained) => retained,
+			None => {
+				println!("{} was removed", describe(report, symbol));
+				continue;
+			}
+		};
+
+		println!("{}", describe(report, symbol));
+		let reason = match root {
+			OptimizerRoot::Export => "(requested export)".to_string(),
+			OptimizerRoot::Start => "(start function)".to_string(),
+			OptimizerRoot::ElementSegment(index) => format!("(element segment #{})", index),
+			OptimizerRoot::DataSegment(index) => format!("(data segment #{})", index),
+		};
+		let lines = path.iter().skip(1).map(|s| describe(report, *s)).chain(Some(reason));
+		for (depth, line) in lines.enumerate() {
+			println!("{}└── {}", "    ".repeat(depth), line);
+		}
+	}
+}
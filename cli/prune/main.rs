@@ -1,43 +1,40 @@
-use pwasm_utils::{self as utils, logger};
+use pwasm_utils::{file, logger};
 use clap::{App, Arg};
 
+mod command;
+mod strip;
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(1)
+}
+
 fn main() {
 	logger::init();
 
-	let target_runtime = utils::TargetRuntime::pwasm();
-
 	let matches = App::new("wasm-prune")
 		.arg(Arg::with_name("input")
 			.index(1)
 			.required(true)
-			.help("Input WASM file"))
+			.help("Input WASM or WAT file"))
 		.arg(Arg::with_name("output")
 			.index(2)
 			.required(true)
-			.help("Output WASM file"))
-		.arg(Arg::with_name("exports")
-			.long("exports")
-			.short("e")
+			.help("Output WASM or WAT file"))
+		.arg(Arg::with_name("emit")
+			.long("emit")
 			.takes_value(true)
-			.value_name("functions")
-			.help(&format!("Comma-separated list of exported functions to keep. Default: '{}'", target_runtime.symbols().call)))
+			.possible_values(&["wasm", "wat"])
+			.help("Output format, by default WAT for .wat output files and binary otherwise"))
+		.args(&command::args())
 		.get_matches();
 
-	let exports = matches
-					.value_of("exports")
-					.unwrap_or(target_runtime.symbols().call)
-					.split(',')
-					.collect();
-
 	let input = matches.value_of("input").expect("is required; qed");
 	let output = matches.value_of("output").expect("is required; qed");
 
-	let mut module = parity_wasm::deserialize_file(&input).unwrap();
-
-	// Invoke optimizer
-	//   Contract is supposed to have only these functions as public api
-	//   All other symbols not usable by this list is optimized away
-	utils::optimize(&mut module, exports).expect("Optimizer failed");
+	let module = file::read_module(input).unwrap_or_else(|err| fail(&err.to_string()));
+	let module = command::prune(module, &matches).unwrap_or_else(|err| fail(&err));
 
-	parity_wasm::serialize_to_file(&output, module).expect("Serialization failed");
+	file::write_module(output, module, file::Format::for_output(matches.value_of("emit"), output))
+		.unwrap_or_else(|err| fail(&err.to_string()));
 }
@@ -0,0 +1,51 @@
+//! Arguments selecting custom sections to strip, shared by the tools which prune modules
+
+use pwasm_utils::StripOptions;
+use clap::{Arg, ArgMatches};
+
+/// Arguments of custom section stripping.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+	vec![
+		Arg::with_name("strip")
+			.long("strip")
+			.help("Strip custom sections, except the ones kept with --keep-section"),
+		Arg::with_name("keep_section")
+			.long("keep-section")
+			.takes_value(true)
+			.multiple(true)
+			.number_of_values(1)
+			.value_name("pattern")
+			.requires("strip")
+			.help("Keep custom sections with this name when stripping, trailing '*' matches any suffix"),
+		Arg::with_name("strip_section")
+			.long("strip-section")
+			.takes_value(true)
+			.multiple(true)
+			.number_of_values(1)
+			.value_name("pattern")
+			.requires("strip")
+			.help("Strip custom sections with this name even if they match --keep-section"),
+		Arg::with_name("keep_names")
+			.long("keep-names")
+			.requires("strip")
+			.help("Keep the name section when stripping"),
+	]
+}
+
+/// Strip options from the arguments, if stripping is requested.
+pub fn options(matches: &ArgMatches) -> Option<StripOptions> {
+	if !matches.is_present("strip") {
+		return None;
+	}
+	let mut options = StripOptions::default();
+	for pattern in matches.values_of("keep_section").into_iter().flatten() {
+		options = options.with_allowed(pattern);
+	}
+	for pattern in matches.values_of("strip_section").into_iter().flatten() {
+		options = options.with_denied(pattern);
+	}
+	if matches.is_present("keep_names") {
+		options = options.with_names_kept();
+	}
+	Some(options)
+}
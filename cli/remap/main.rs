@@ -0,0 +1,55 @@
+use pwasm_utils::{self as utils, file, logger};
+use clap::{App, Arg};
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(1)
+}
+
+fn main() {
+	logger::init();
+
+	let matches = App::new("wasm-remap")
+		.arg(Arg::with_name("input")
+			.index(1)
+			.required(true)
+			.help("Input WASM or WAT file"))
+		.arg(Arg::with_name("output")
+			.index(2)
+			.required(true)
+			.help("Output WASM or WAT file"))
+		.arg(Arg::with_name("emit")
+			.long("emit")
+			.takes_value(true)
+			.possible_values(&["wasm", "wat"])
+			.help("Output format, by default WAT for .wat output files and binary otherwise"))
+		.arg(Arg::with_name("mapping")
+			.long("mapping")
+			.short("m")
+			.takes_value(true)
+			.required(true)
+			.value_name("file")
+			.help("Mapping file with lines like `env.ext_return = seal0.seal_return : (i32, i32)`"))
+		.arg(Arg::with_name("strict")
+			.long("strict")
+			.help("Fail if some import is not in the mapping"))
+		.get_matches();
+
+	let input = matches.value_of("input").expect("is required; qed");
+	let output = matches.value_of("output").expect("is required; qed");
+	let mapping_path = matches.value_of("mapping").expect("is required; qed");
+
+	let source = std::fs::read_to_string(mapping_path)
+		.unwrap_or_else(|err| fail(&format!("Failed to read {}: {}", mapping_path, err)));
+	let mut mapping = utils::ImportMapping::parse(&source)
+		.unwrap_or_else(|err| fail(&err.to_string()));
+	if matches.is_present("strict") {
+		mapping = mapping.strict();
+	}
+
+	let mut module = file::read_module(input).unwrap_or_else(|err| fail(&err.to_string()));
+	utils::remap_imports(&mut module, &mapping).unwrap_or_else(|err| fail(&err.to_string()));
+
+	file::write_module(output, module, file::Format::for_output(matches.value_of("emit"), output))
+		.unwrap_or_else(|err| fail(&err.to_string()));
+}
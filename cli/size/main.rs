@@ -0,0 +1,88 @@
+use pwasm_utils::{self as utils, file, logger, ItemSize};
+use clap::{App, Arg};
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(1)
+}
+
+fn percent(size: usize, total: usize) -> f64 {
+	if total == 0 { 0.0 } else { size as f64 * 100.0 / total as f64 }
+}
+
+fn print_items(title: &str, mut items: Vec<ItemSize>, total: usize, top: usize) {
+	if items.is_empty() {
+		return;
+	}
+	items.sort_by_key(|item| std::cmp::Reverse(item.size));
+
+	println!();
+	println!("{}:", title);
+	println!("{:>10} {:>8}  name", "bytes", "%");
+	for item in items.iter().take(top) {
+		println!("{:>10} {:>7.2}%  {}", item.size, percent(item.size, total), item.name);
+	}
+	if items.len() > top {
+		let rest = &items[top..];
+		let size = rest.iter().map(|item| item.size).sum::<usize>();
+		println!("{:>10} {:>7.2}%  ... and {} more", size, percent(size, total), rest.len());
+	}
+}
+
+fn main() {
+	logger::init();
+
+	let matches = App::new("wasm-size")
+		.arg(Arg::with_name("input")
+			.index(1)
+			.required(true)
+			.help("Input WASM or WAT file"))
+		.arg(Arg::with_name("top")
+			.long("top")
+			.short("n")
+			.takes_value(true)
+			.value_name("count")
+			.help("List only the given number of the largest functions, data segments, imports and exports"))
+		.get_matches();
+
+	let input = matches.value_of("input").expect("is required; qed");
+	let top = match matches.value_of("top") {
+		Some(top) => top.parse().unwrap_or_else(|_| fail(&format!("Invalid --top value `{}`", top))),
+		None => usize::MAX,
+	};
+
+	let module = file::read_module(input).unwrap_or_else(|err| fail(&err.to_string()));
+	let module = module.parse_names().unwrap_or_else(|(_, module)| module);
+
+	let profile = utils::size_profile(&module);
+	let total = profile.total;
+	println!("Total: {} bytes", total);
+
+	println!();
+	println!("Sections:");
+	println!("{:>10} {:>8}  name", "bytes", "%");
+	for section in profile.sections.iter() {
+		println!("{:>10} {:>7.2}%  {}", section.size, percent(section.size, total), section.name);
+	}
+
+	print_items("Functions", profile.functions, total, top);
+	print_items("Data segments", profile.data, total, top);
+	print_items("Imports", profile.imports, total, top);
+
+	let mut exports = profile.exports;
+	if !exports.is_empty() {
+		exports.sort_by_key(|export| std::cmp::Reverse(export.retained));
+		println!();
+		println!("Exports (retained: bytes removed by wasm-prune if the export is dropped):");
+		println!("{:>10} {:>10} {:>8}  name", "bytes", "retained", "%");
+		for export in exports.iter().take(top) {
+			println!(
+				"{:>10} {:>10} {:>7.2}%  {}",
+				export.size,
+				export.retained,
+				percent(export.retained, total),
+				export.name,
+			);
+		}
+	}
+}
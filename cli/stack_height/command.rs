@@ -0,0 +1,26 @@
+//! Injecting the stack height limiter
+
+use pwasm_utils::stack_height;
+use clap::{Arg, ArgMatches};
+use parity_wasm::elements;
+
+use crate::number::is_u32;
+
+/// Arguments of the stack height limiter.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+	vec![
+		Arg::with_name("stack_limit")
+			.long("stack-limit")
+			.takes_value(true)
+			.value_name("height")
+			.default_value("1024")
+			.validator(is_u32)
+			.help("Maximum stack height, in values"),
+	]
+}
+
+/// Inject the limiter with the limit defined by `args`.
+pub fn inject(module: elements::Module, matches: &ArgMatches) -> Result<elements::Module, stack_height::Error> {
+	let stack_limit = matches.value_of("stack_limit").expect("has default; qed").parse().expect("validated; qed");
+	stack_height::inject_limiter(module, stack_limit)
+}
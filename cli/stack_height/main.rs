@@ -1,24 +1,49 @@
-use pwasm_utils::{logger, stack_height};
-use std::env;
+use pwasm_utils::{file, logger};
+use clap::{App, Arg};
+
+mod command;
+#[path = "../utils/number.rs"]
+mod number;
+
+/// Exit code when the module can't be read or written.
+const EXIT_IO: i32 = 2;
+/// Exit code when the limiter can't be injected.
+const EXIT_INSTRUMENTATION: i32 = 3;
+
+fn fail(code: i32, msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(code)
+}
 
 fn main() {
 	logger::init();
 
-	let args = env::args().collect::<Vec<_>>();
-	if args.len() != 3 {
-		println!("Usage: {} input_file.wasm output_file.wasm", args[0]);
-		return;
-	}
+	let matches = App::new("wasm-stack-height")
+		.arg(Arg::with_name("input")
+			.index(1)
+			.required(true)
+			.help("Input WASM or WAT file"))
+		.arg(Arg::with_name("output")
+			.index(2)
+			.required(true)
+			.help("Output WASM or WAT file"))
+		.arg(Arg::with_name("emit")
+			.long("emit")
+			.takes_value(true)
+			.possible_values(&["wasm", "wat"])
+			.help("Output format, by default WAT for .wat output files and binary otherwise"))
+		.args(&command::args())
+		.get_matches();
 
-	let input_file = &args[1];
-	let output_file = &args[2];
+	let input_file = matches.value_of("input").expect("is required; qed");
+	let output_file = matches.value_of("output").expect("is required; qed");
 
 	// Loading module
-	let module = parity_wasm::deserialize_file(&input_file).expect("Module deserialization to succeed");
+	let module = file::read_module(input_file).unwrap_or_else(|err| fail(EXIT_IO, &err.to_string()));
 
-	let result = stack_height::inject_limiter(
-		module, 1024
-	).expect("Failed to inject stack height counter");
+	let result = command::inject(module, &matches)
+		.unwrap_or_else(|err| fail(EXIT_INSTRUMENTATION, &format!("Failed to inject stack height counter: {}", err)));
 
-	parity_wasm::serialize_to_file(&output_file, result).expect("Module serialization to succeed")
+	file::write_module(output_file, result, file::Format::for_output(matches.value_of("emit"), output_file))
+		.unwrap_or_else(|err| fail(EXIT_IO, &err.to_string()));
 }
@@ -0,0 +1,175 @@
+//! Single entry point to the wasm utilities
+//!
+//! Every tool is a subcommand, e.g. `wasm-utils prune -i in.wasm -o out.wasm`, while
+//! `wasm-utils --passes prune,gas,stack-height -i in.wasm -o out.wasm` loads the module once,
+//! runs the passes in order and writes the result once.
+
+use pwasm_utils::{file, logger};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand, crate_version};
+use log::LevelFilter;
+
+#[path = "../build/command.rs"]
+mod build;
+#[path = "../check/command.rs"]
+mod check;
+#[path = "../ext/command.rs"]
+mod ext;
+#[path = "../gas/command.rs"]
+mod gas;
+mod number;
+#[path = "../pack/command.rs"]
+mod pack;
+mod passes;
+#[path = "../prune/command.rs"]
+mod prune;
+mod soft_float;
+#[path = "../build/source.rs"]
+mod source;
+#[path = "../stack_height/command.rs"]
+mod stack_height;
+#[path = "../prune/strip.rs"]
+mod strip;
+
+fn fail(msg: &str) -> ! {
+	eprintln!("{}", msg);
+	std::process::exit(1)
+}
+
+/// Split comma-separated pass names, checking that every pass exists.
+fn parse_passes(list: &str) -> Result<Vec<&str>, String> {
+	list.split(',')
+		.map(|name| name.trim())
+		.map(|name| if passes::PASSES.contains(&name) {
+			Ok(name)
+		} else {
+			Err(format!("Unknown pass `{}`, expected one of: {}", name, passes::PASSES.join(", ")))
+		})
+		.collect()
+}
+
+fn log_level(matches: &ArgMatches) -> LevelFilter {
+	if matches.is_present("quiet") {
+		return LevelFilter::Error;
+	}
+	match matches.occurrences_of("verbose") {
+		0 => LevelFilter::Info,
+		1 => LevelFilter::Debug,
+		_ => LevelFilter::Trace,
+	}
+}
+
+/// Load the input module, run the passes and write the output module.
+///
+/// Output can be omitted only if the passes do not change the module.
+fn run_pipeline(names: &[&str], matches: &ArgMatches) {
+	let input = matches.value_of("input").unwrap_or_else(|| fail("--input is required"));
+	let output = matches.value_of("output");
+	if output.is_none() && names.iter().any(|name| *name != "check") {
+		fail("--output is required");
+	}
+
+	let mut module = file::read_module(input).unwrap_or_else(|err| fail(&err.to_string()));
+	for name in names {
+		log::debug!("Running {}", name);
+		module = passes::run(name, module, matches)
+			.unwrap_or_else(|err| fail(&format!("Pass `{}` failed: {}", name, err)));
+	}
+
+	if let Some(output) = output {
+		file::write_module(output, module, file::Format::for_output(matches.value_of("emit"), output))
+			.unwrap_or_else(|err| fail(&err.to_string()));
+	}
+}
+
+fn pass_command(name: &'static str, about: &'static str) -> App<'static, 'static> {
+	SubCommand::with_name(name).about(about)
+}
+
+fn main() {
+	let matches = App::new("wasm-utils")
+		.version(crate_version!())
+		.about("WASM utilities for contract development")
+		.setting(AppSettings::SubcommandsNegateReqs)
+		.arg(Arg::with_name("input")
+			.long("input")
+			.short("i")
+			.takes_value(true)
+			.value_name("file")
+			.global(true)
+			.help("Input WASM or WAT file"))
+		.arg(Arg::with_name("output")
+			.long("output")
+			.short("o")
+			.takes_value(true)
+			.value_name("file")
+			.global(true)
+			.help("Output WASM or WAT file"))
+		.arg(Arg::with_name("emit")
+			.long("emit")
+			.takes_value(true)
+			.possible_values(&["wasm", "wat"])
+			.global(true)
+			.help("Output format, by default WAT for .wat output files and binary otherwise"))
+		.arg(Arg::with_name("verbose")
+			.long("verbose")
+			.short("v")
+			.multiple(true)
+			.global(true)
+			.help("Log more details, repeat for even more"))
+		.arg(Arg::with_name("quiet")
+			.long("quiet")
+			.short("q")
+			.global(true)
+			.conflicts_with("verbose")
+			.help("Log only errors"))
+		.arg(Arg::with_name("passes")
+			.long("passes")
+			.short("p")
+			.takes_value(true)
+			.value_name("list")
+			.required(true)
+			.help(&format!("Comma-separated passes to run in order: {}", passes::PASSES.join(", "))))
+		.args(&prune::args())
+		.args(&soft_float::args())
+		.args(&gas::args())
+		.args(&stack_height::args())
+		.subcommand(pass_command("prune", "Remove symbols not used by the exports").args(&prune::args()))
+		.subcommand(pass_command("ext", "Externalize allocator functions"))
+		.subcommand(pass_command("soft-float", "Replace float instructions with calls of software float routines").args(&soft_float::args()))
+		.subcommand(pass_command("gas", "Inject gas counters").args(&gas::args()))
+		.subcommand(pass_command("stack-height", "Inject stack height limiter").args(&stack_height::args()))
+		.subcommand(pass_command("pack", "Pack the module into a constructor"))
+		.subcommand(pass_command("check", "Check that the module can run in the Parity runtime"))
+		.subcommand(build::args(SubCommand::with_name("build").about("Build the final wasm binary from cargo output")))
+		.get_matches();
+
+	logger::init_with_level(log_level(&matches));
+
+	match matches.subcommand() {
+		("build", Some(build_matches)) => {
+			if let Err(err) = build::run(build_matches) {
+				fail(&err.to_string());
+			}
+		},
+		(name, Some(pass_matches)) => run_pipeline(&[name], pass_matches),
+		_ => {
+			let list = matches.value_of("passes").expect("is required without a subcommand; qed");
+			let names = parse_passes(list).unwrap_or_else(|err| fail(&err));
+			run_pipeline(&names, &matches);
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::parse_passes;
+
+	#[test]
+	fn passes() {
+		assert_eq!(parse_passes("prune, gas,stack-height"), Ok(vec!["prune", "gas", "stack-height"]));
+		assert_eq!(
+			parse_passes("prune,optimize"),
+			Err("Unknown pass `optimize`, expected one of: prune, ext, soft-float, gas, stack-height, pack, check".to_string()),
+		);
+	}
+}
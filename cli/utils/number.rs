@@ -0,0 +1,6 @@
+//! Numeric arguments
+
+/// Validator of the argument which has to be `u32`.
+pub fn is_u32(value: String) -> Result<(), String> {
+	value.parse::<u32>().map(|_| ()).map_err(|err| format!("`{}` is not a valid number: {}", value, err))
+}
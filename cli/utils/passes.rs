@@ -0,0 +1,33 @@
+//! Passes which can be chained with `--passes`
+
+use pwasm_utils as utils;
+use clap::ArgMatches;
+use parity_wasm::elements;
+
+use crate::{check, ext, gas, pack, prune, soft_float, stack_height};
+
+/// Names of the passes, in the order they are usually run.
+pub const PASSES: &[&str] = &["prune", "ext", "soft-float", "gas", "stack-height", "pack", "check"];
+
+/// Run the pass with options from the matches, which may lack arguments of the pass.
+pub fn run(name: &str, module: elements::Module, matches: &ArgMatches) -> Result<elements::Module, String> {
+	match name {
+		"prune" => prune::prune(module, matches),
+		"ext" => ext::externalize(module).map_err(reason),
+		"soft-float" => soft_float::lower(module, matches),
+		"gas" => gas::gas(&module, matches).map_err(reason),
+		"stack-height" => stack_height::inject(module, matches)
+			.map_err(|err| format!("Failed to inject stack height counter: {}", err)),
+		"pack" => pack::pack(module),
+		"check" => check::check(&module).map(|_| module),
+		_ => Err(format!("Unknown pass `{}`, expected one of: {}", name, PASSES.join(", "))),
+	}
+}
+
+/// The pipeline names the pass in the error, which the caller already does.
+pub fn reason(err: utils::PassError) -> String {
+	match err {
+		utils::PassError::Pass(_, reason) => reason,
+		err => err.to_string(),
+	}
+}
@@ -0,0 +1,39 @@
+//! Lowering floats to calls of software float routines
+
+use pwasm_utils::{self as utils, file, Pipeline, SoftFloat};
+use clap::{Arg, ArgMatches};
+use parity_wasm::elements;
+
+use crate::passes::reason;
+
+/// Arguments of the soft-float lowering.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+	vec![
+		Arg::with_name("soft_float_module")
+			.long("soft-float-module")
+			.takes_value(true)
+			.value_name("module")
+			.default_value("env")
+			.help("Module to import the float routines from"),
+		Arg::with_name("soft_float_library")
+			.long("soft-float-library")
+			.takes_value(true)
+			.value_name("file")
+			.help("WASM or WAT library exporting the float routines, linked instead of importing them"),
+	]
+}
+
+/// Lower floats, linking the library or importing the routines as defined by `args`.
+pub fn lower(module: elements::Module, matches: &ArgMatches) -> Result<elements::Module, String> {
+	let library = match matches.value_of("soft_float_library") {
+		Some(path) => file::read_module(path).map_err(|err| err.to_string())?,
+		None => {
+			let module_name = matches.value_of("soft_float_module").expect("has default; qed");
+			return utils::lower_floats(module, module_name).map_err(reason);
+		},
+	};
+	let library = utils::Module::from_elements(&library)
+		.map_err(|err| format!("Invalid library: {:?}", err))?;
+	let pipeline = Pipeline::new().with_pass(SoftFloat::linked(&library));
+	pipeline.run(&module).map_err(reason)
+}
@@ -1,5 +1,6 @@
 use super::{
-	optimize,
+	optimize_with_options,
+	strip_custom_sections,
 	pack_instance,
 	ununderscore_funcs,
 	externalize_mem,
@@ -7,6 +8,8 @@ use super::{
 	inject_runtime_type,
 	PackingError,
 	OptimizerError,
+	OptimizerOptions,
+	StripOptions,
 	TargetRuntime,
 	std::fmt,
 };
@@ -58,6 +61,31 @@ fn has_ctor(module: &elements::Module, target_runtime: &TargetRuntime) -> bool {
 
 #[allow(clippy::too_many_arguments)]
 pub fn build(
+	module: elements::Module,
+	source_target: SourceTarget,
+	runtime_type_version: Option<([u8; 4], u32)>,
+	public_api_entries: &[&str],
+	enforce_stack_adjustment: bool,
+	stack_size: u32,
+	skip_optimization: bool,
+	target_runtime: &TargetRuntime,
+) -> Result<(elements::Module, Option<elements::Module>), Error> {
+	build_with_strip(
+		module,
+		source_target,
+		runtime_type_version,
+		public_api_entries,
+		enforce_stack_adjustment,
+		stack_size,
+		skip_optimization,
+		target_runtime,
+		None,
+	)
+}
+
+/// Same as `build`, but custom sections of the resulting modules are stripped with `strip`, if given.
+#[allow(clippy::too_many_arguments)]
+pub fn build_with_strip(
 	mut module: elements::Module,
 	source_target: SourceTarget,
 	runtime_type_version: Option<([u8; 4], u32)>,
@@ -66,6 +94,7 @@ pub fn build(
 	stack_size: u32,
 	skip_optimization: bool,
 	target_runtime: &TargetRuntime,
+	strip: Option<&StripOptions>,
 ) -> Result<(elements::Module, Option<elements::Module>), Error> {
 
 	if let SourceTarget::Emscripten = source_target {
@@ -93,10 +122,19 @@ pub fn build(
 
 	let mut ctor_module = module.clone();
 
+	// Custom sections are left to the strip pass, if there is one
+	let optimizer_options = match strip {
+		Some(_) => OptimizerOptions::default().with_custom_sections_kept(),
+		None => OptimizerOptions::default(),
+	};
+
 	let mut public_api_entries = public_api_entries.to_vec();
 	public_api_entries.push(target_runtime.symbols().call);
 	if !skip_optimization {
-		optimize(&mut module, public_api_entries)?;
+		optimize_with_options(&mut module, public_api_entries, &optimizer_options)?;
+	}
+	if let Some(strip) = strip {
+		strip_custom_sections(&mut module, strip);
 	}
 
 	if !has_ctor(&ctor_module, target_runtime) {
@@ -108,7 +146,10 @@ pub fn build(
 			TargetRuntime::PWasm(_) => vec![target_runtime.symbols().create],
 			TargetRuntime::Substrate(_) => vec![target_runtime.symbols().call, target_runtime.symbols().create],
 		};
-		optimize(&mut ctor_module, preserved_exports)?;
+		optimize_with_options(&mut ctor_module, preserved_exports, &optimizer_options)?;
+	}
+	if let Some(strip) = strip {
+		strip_custom_sections(&mut ctor_module, strip);
 	}
 
 	if let TargetRuntime::PWasm(_) = target_runtime {
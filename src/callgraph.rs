@@ -0,0 +1,280 @@
+//! Call graph of the module functions.
+//!
+//! Functions are identified by their index in the function space (imports first).
+//! Besides direct calls, the graph has synthetic edges for `call_indirect`: an indirect call
+//! can reach every function placed in a table which has the called signature.
+
+use crate::std::borrow::ToOwned;
+use crate::std::collections::{BTreeMap, BTreeSet};
+use crate::std::string::String;
+use crate::std::vec::Vec;
+
+use parity_wasm::elements;
+
+use crate::symbols::{Symbol, function_type, push_code_symbols, resolve_function};
+
+/// Kind of the call.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub enum CallKind {
+	/// `call` instruction.
+	Direct,
+	/// `call_indirect` instruction which can reach the function through a table.
+	Indirect,
+}
+
+/// Call from one function to another.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub struct CallEdge {
+	/// Index of the calling function.
+	pub caller: u32,
+	/// Index of the called function.
+	pub callee: u32,
+	/// How the function is called.
+	pub kind: CallKind,
+}
+
+/// Function of the module.
+#[derive(Clone, Debug)]
+pub struct CallNode {
+	/// Import or declared function.
+	pub symbol: Symbol,
+	/// `module.field` of the import, otherwise the name from the name section or the export.
+	pub name: Option<String>,
+}
+
+impl CallNode {
+	/// Whether the function is imported.
+	pub fn is_imported(&self) -> bool {
+		matches!(self.symbol, Symbol::Import(_))
+	}
+}
+
+/// Call graph of the module.
+#[derive(Clone, Debug)]
+pub struct CallGraph {
+	nodes: Vec<CallNode>,
+	edges: Vec<CallEdge>,
+}
+
+impl CallGraph {
+	/// Build the call graph of the module.
+	pub fn new(module: &elements::Module) -> Self {
+		let funcs = module.functions_space() as u32;
+		let nodes = (0..funcs).map(|index| CallNode {
+			symbol: resolve_function(module, index),
+			name: function_name(module, index),
+		}).collect::<Vec<_>>();
+
+		let mut table_members = BTreeSet::new();
+		for segment in module.elements_section().iter().flat_map(|section| section.entries()) {
+			table_members.extend(segment.members().iter().cloned());
+		}
+
+		let indices = nodes.iter().enumerate()
+			.map(|(index, node)| (node.symbol, index as u32))
+			.collect::<BTreeMap<_, _>>();
+		let types = module.type_section().map(|section| section.types()).unwrap_or(&[]);
+		let bodies = module.code_section().map(|section| section.bodies()).unwrap_or(&[]);
+		let imported = module.import_count(elements::ImportCountType::Function) as u32;
+
+		let mut edges = BTreeSet::new();
+		let mut symbols = Vec::new();
+		for (index, body) in bodies.iter().enumerate() {
+			let caller = imported + index as u32;
+			symbols.clear();
+			push_code_symbols(module, body.code().elements(), &mut symbols);
+			for symbol in symbols.iter() {
+				match symbol {
+					// imported globals are not in the function space and have no index
+					Symbol::Import(_) | Symbol::Function(_) => {
+						if let Some(callee) = indices.get(symbol) {
+							edges.insert(CallEdge { caller, callee: *callee, kind: CallKind::Direct });
+						}
+					},
+					Symbol::Type(type_index) => {
+						let elements::Type::Function(called_type) = match types.get(*type_index) {
+							Some(called_type) => called_type,
+							None => continue,
+						};
+						for callee in table_members.iter() {
+							if function_type(module, *callee) == Some(called_type) {
+								edges.insert(CallEdge { caller, callee: *callee, kind: CallKind::Indirect });
+							}
+						}
+					},
+					_ => { },
+				}
+			}
+		}
+
+		CallGraph { nodes, edges: edges.into_iter().collect() }
+	}
+
+	/// Functions of the module, in the function space order.
+	pub fn nodes(&self) -> &[CallNode] {
+		&self.nodes
+	}
+
+	/// Calls, ordered by the caller.
+	pub fn edges(&self) -> &[CallEdge] {
+		&self.edges
+	}
+
+	/// Calls made by the function.
+	pub fn callees(&self, caller: u32) -> impl Iterator<Item = &CallEdge> {
+		self.edges.iter().filter(move |edge| edge.caller == caller)
+	}
+
+	/// Strongly connected components of the graph, callees before callers.
+	///
+	/// Every function belongs to exactly one component, members are sorted by index.
+	pub fn components(&self) -> Vec<Vec<u32>> {
+		let count = self.nodes.len();
+		let mut successors = vec![Vec::new(); count];
+		for edge in self.edges.iter() {
+			successors[edge.caller as usize].push(edge.callee as usize);
+		}
+
+		// Tarjan's algorithm with an explicit stack, so deep call chains don't overflow
+		let mut order = vec![None; count];
+		let mut low = vec![0; count];
+		let mut on_stack = vec![false; count];
+		let mut stack = Vec::new();
+		let mut next_order = 0;
+		let mut components = Vec::new();
+		for root in 0..count {
+			if order[root].is_some() {
+				continue;
+			}
+
+			// (node, position of the next successor to visit)
+			let mut work = vec![(root, 0)];
+			while let Some((node, position)) = work.pop() {
+				if position == 0 {
+					order[node] = Some(next_order);
+					low[node] = next_order;
+					next_order += 1;
+					stack.push(node);
+					on_stack[node] = true;
+				}
+
+				if let Some(&successor) = successors[node].get(position) {
+					work.push((node, position + 1));
+					match order[successor] {
+						None => work.push((successor, 0)),
+						Some(successor_order) if on_stack[successor] => {
+							low[node] = low[node].min(successor_order);
+						},
+						Some(_) => { },
+					}
+					continue;
+				}
+
+				if Some(low[node]) == order[node] {
+					let mut component = Vec::new();
+					while let Some(member) = stack.pop() {
+						on_stack[member] = false;
+						component.push(member as u32);
+						if member == node {
+							break;
+						}
+					}
+					component.sort_unstable();
+					components.push(component);
+				}
+				if let Some(&(parent, _)) = work.last() {
+					low[parent] = low[parent].min(low[node]);
+				}
+			}
+		}
+		components
+	}
+
+	/// Components of functions which can (mutually) recurse.
+	pub fn recursive_components(&self) -> Vec<Vec<u32>> {
+		self.components()
+			.into_iter()
+			.filter(|component| match component[..] {
+				[func] => self.callees(func).any(|edge| edge.callee == func),
+				_ => true,
+			})
+			.collect()
+	}
+}
+
+/// Name of the function with the given index in the function space.
+pub(crate) fn function_name(module: &elements::Module, index: u32) -> Option<String> {
+	if let Symbol::Import(import_index) = resolve_function(module, index) {
+		let entry = &module.import_section()?.entries()[import_index];
+		return Some(format!("{}.{}", entry.module(), entry.field()));
+	}
+
+	let name = module.names_section()
+		.and_then(|section| section.functions())
+		.and_then(|functions| functions.names().get(index));
+	if let Some(name) = name {
+		return Some(name.to_owned());
+	}
+
+	module.export_section()?.entries().iter()
+		.find(|entry| matches!(entry.internal(), elements::Internal::Function(func) if *func == index))
+		.map(|entry| entry.field().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements;
+	use super::*;
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	#[test]
+	fn call_graph() {
+		let module = parse_wat(r#"
+			(module
+				(type (func))
+				(type (func (param i32)))
+				(import "env" "ext" (func (type 0)))
+				(table 2 anyfunc)
+				(elem (i32.const 0) 2 3)
+				(func (export "call") (type 0)
+					call 0
+					call 2
+					i32.const 0
+					call_indirect (type 0))
+				(func (type 0)
+					i32.const 0
+					call 3
+					call 2)
+				(func (type 1)
+					call 2)
+				(func (type 0)
+					call 4))
+		"#);
+
+		let graph = CallGraph::new(&module);
+		assert_eq!(graph.nodes().len(), 5);
+		assert_eq!(graph.nodes()[0].name.as_deref(), Some("env.ext"));
+		assert!(graph.nodes()[0].is_imported());
+		assert_eq!(graph.nodes()[1].name.as_deref(), Some("call"));
+		assert_eq!(graph.nodes()[1].symbol, Symbol::Function(0));
+		assert_eq!(graph.nodes()[2].name, None);
+
+		let edges = graph.edges().iter().map(|edge| (edge.caller, edge.callee, edge.kind)).collect::<Vec<_>>();
+		assert_eq!(edges, vec![
+			(1, 0, CallKind::Direct),
+			(1, 2, CallKind::Direct),
+			(1, 2, CallKind::Indirect),
+			(2, 2, CallKind::Direct),
+			(2, 3, CallKind::Direct),
+			(3, 2, CallKind::Direct),
+			(4, 4, CallKind::Direct),
+		]);
+
+		assert_eq!(graph.components(), vec![vec![0], vec![2, 3], vec![1], vec![4]]);
+		assert_eq!(graph.recursive_components(), vec![vec![2, 3], vec![4]]);
+	}
+}
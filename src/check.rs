@@ -0,0 +1,482 @@
+//! Checking that the contract can run in the target runtime.
+//!
+//! Every runtime has its own `Policy`: which host functions it provides, how much memory it
+//! allows, which instructions it can't execute deterministically and which entry points it calls.
+//! `check` reports every violation of the policy instead of stopping at the first one.
+
+use crate::std::collections::BTreeSet as Set;
+use crate::std::fmt;
+use crate::std::string::{String, ToString};
+use crate::std::vec::Vec;
+
+use parity_wasm::elements::{self, FunctionType, ImportCountType};
+
+use crate::rules::InstructionType;
+use crate::symbols::{function_type, signature};
+
+/// Host functions of the pwasm runtime.
+const PWASM_IMPORTS: &[&str] = &[
+	"ret",
+	"storage_read",
+	"storage_write",
+	"balance",
+	"sender",
+	"origin",
+	"fetch_input",
+	"input_length",
+	"ccall",
+	"dcall",
+	"scall",
+	"create",
+	"blockhash",
+	"blocknumber",
+	"coinbase",
+	"timestamp",
+	"difficulty",
+	"gaslimit",
+	"address",
+	"value",
+	"suicide",
+	"panic",
+	"elog",
+	"abort",
+];
+
+/// Host functions of the substrate contracts runtime.
+const SUBSTRATE_IMPORTS: &[&str] = &[
+	"ext_gas",
+	"ext_set_storage",
+	"ext_clear_storage",
+	"ext_get_storage",
+	"ext_get_runtime_storage",
+	"ext_transfer",
+	"ext_call",
+	"ext_instantiate",
+	"ext_terminate",
+	"ext_return",
+	"ext_caller",
+	"ext_address",
+	"ext_gas_price",
+	"ext_gas_left",
+	"ext_balance",
+	"ext_value_transferred",
+	"ext_random",
+	"ext_now",
+	"ext_minimum_balance",
+	"ext_tombstone_deposit",
+	"ext_dispatch_call",
+	"ext_restore_to",
+	"ext_scratch_size",
+	"ext_scratch_read",
+	"ext_scratch_write",
+	"ext_deposit_event",
+	"ext_set_rent_allowance",
+	"ext_rent_allowance",
+	"ext_println",
+	"ext_block_number",
+];
+
+/// Requirements of the runtime to the contract.
+#[derive(Clone, Debug)]
+pub struct Policy {
+	module: String,
+	imports: Vec<(String, Option<FunctionType>)>,
+	imported_memory: Option<String>,
+	max_memory_pages: Option<u32>,
+	imported_globals: bool,
+	forbidden: Set<InstructionType>,
+	exports: Vec<(String, FunctionType)>,
+}
+
+impl Policy {
+	/// Policy allowing imports only from the `module`, and none of its functions yet.
+	pub fn new(module: &str) -> Self {
+		Policy {
+			module: module.to_string(),
+			imports: Vec::new(),
+			imported_memory: None,
+			max_memory_pages: None,
+			imported_globals: false,
+			forbidden: Set::new(),
+			exports: Vec::new(),
+		}
+	}
+
+	/// Policy of the pwasm runtime: its host functions, memory imported as `env.memory`
+	/// and limited to 16 pages, no imported globals.
+	pub fn pwasm() -> Self {
+		PWASM_IMPORTS.iter()
+			.fold(Policy::new("env"), |policy, name| policy.with_import(name, None))
+			.with_imported_memory("memory")
+			.with_max_memory_pages(16)
+	}
+
+	/// Policy of the substrate contracts runtime: its host functions, memory imported as
+	/// `env.memory` and limited to 16 pages, no floats and `call` and `deploy` exported.
+	pub fn substrate() -> Self {
+		SUBSTRATE_IMPORTS.iter()
+			.fold(Policy::new("env"), |policy, name| policy.with_import(name, None))
+			.with_imported_memory("memory")
+			.with_max_memory_pages(16)
+			.with_forbidden_floats()
+			.with_export("call", FunctionType::new(vec![], vec![]))
+			.with_export("deploy", FunctionType::new(vec![], vec![]))
+	}
+
+	/// Allow importing the function, with any signature if `signature` is `None`.
+	pub fn with_import(mut self, name: &str, signature: Option<FunctionType>) -> Self {
+		self.imports.push((name.to_string(), signature));
+		self
+	}
+
+	/// Require the memory to be imported with the name.
+	pub fn with_imported_memory(mut self, name: &str) -> Self {
+		self.imported_memory = Some(name.to_string());
+		self
+	}
+
+	/// Require the memory to declare the maximum of at most `pages`.
+	pub fn with_max_memory_pages(mut self, pages: u32) -> Self {
+		self.max_memory_pages = Some(pages);
+		self
+	}
+
+	/// Allow importing globals.
+	pub fn with_imported_globals(mut self) -> Self {
+		self.imported_globals = true;
+		self
+	}
+
+	/// Forbid instructions of the type.
+	pub fn with_forbidden(mut self, instruction_type: InstructionType) -> Self {
+		self.forbidden.insert(instruction_type);
+		self
+	}
+
+	/// Forbid all floating point instructions, the same as `rules::Set::with_forbidden_floats`.
+	pub fn with_forbidden_floats(self) -> Self {
+		self.with_forbidden(InstructionType::Float)
+			.with_forbidden(InstructionType::FloatComparison)
+			.with_forbidden(InstructionType::FloatConst)
+			.with_forbidden(InstructionType::FloatConversion)
+	}
+
+	/// Require the function to be exported with the signature.
+	pub fn with_export(mut self, name: &str, signature: FunctionType) -> Self {
+		self.exports.push((name.to_string(), signature));
+		self
+	}
+}
+
+/// Violation of the policy.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+	/// Import (module, field) is not from the allowed module.
+	ForeignImport(String, String),
+	/// Imported function is not provided by the runtime.
+	UnknownImport(String),
+	/// Imported function has a different signature than the runtime provides.
+	ImportSignature {
+		/// Name of the import.
+		name: String,
+		/// Signature provided by the runtime.
+		expected: FunctionType,
+		/// Signature of the import.
+		actual: FunctionType,
+	},
+	/// Global is imported, while the runtime provides none.
+	ImportedGlobal(String),
+	/// Memory is not imported with the required name.
+	MissingMemory(String),
+	/// Memory declares no maximum.
+	UnlimitedMemory,
+	/// Memory maximum (in pages) is above the limit.
+	MemoryLimit {
+		/// Declared maximum.
+		maximum: u32,
+		/// Maximum allowed by the policy.
+		limit: u32,
+	},
+	/// Instruction of the forbidden type.
+	ForbiddenInstruction {
+		/// Index of the function in the function space.
+		function: u32,
+		/// Index of the instruction in the function body.
+		offset: usize,
+		/// Type of the instruction.
+		instruction_type: InstructionType,
+	},
+	/// Required function is not exported.
+	MissingExport(String),
+	/// Exported function has a different signature than required.
+	ExportSignature {
+		/// Name of the export.
+		name: String,
+		/// Required signature.
+		expected: FunctionType,
+		/// Signature of the export.
+		actual: FunctionType,
+	},
+}
+
+impl fmt::Display for Violation {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match self {
+			Violation::ForeignImport(module, field) => write!(f, "Import {}.{} is not from the runtime module", module, field),
+			Violation::UnknownImport(name) => write!(f, "'{}' is not supported by the runtime", name),
+			Violation::ImportSignature { name, expected, actual } => write!(
+				f, "Import '{}' has signature {}, but the runtime provides {}", name, signature(actual), signature(expected),
+			),
+			Violation::ImportedGlobal(name) => write!(f, "Global '{}' is imported, but the runtime does not provide any globals", name),
+			Violation::MissingMemory(name) => write!(f, "No imported memory named '{}' in the contract", name),
+			Violation::UnlimitedMemory => write!(f, "There is a limit on memory in the runtime, and this program does not limit memory"),
+			Violation::MemoryLimit { maximum, limit } => write!(
+				f, "Runtime has a limit of {} pages on max contract memory, this program specifies {}", limit, maximum,
+			),
+			Violation::ForbiddenInstruction { function, offset, instruction_type } => write!(
+				f, "Function {} uses forbidden {:?} instruction at offset {}", function, instruction_type, offset,
+			),
+			Violation::MissingExport(name) => write!(f, "Function '{}' is not exported", name),
+			Violation::ExportSignature { name, expected, actual } => write!(
+				f, "Export '{}' has signature {}, but {} is required", name, signature(actual), signature(expected),
+			),
+		}
+	}
+}
+
+/// Check the module against the policy, returning all violations in the module order.
+pub fn check(module: &elements::Module, policy: &Policy) -> Vec<Violation> {
+	let mut violations = Vec::new();
+	let mut has_memory = false;
+
+	for entry in module.import_section().map(|section| section.entries()).unwrap_or(&[]) {
+		if entry.module() != policy.module {
+			violations.push(Violation::ForeignImport(entry.module().to_string(), entry.field().to_string()));
+			continue;
+		}
+		match entry.external() {
+			elements::External::Function(type_index) => {
+				let allowed = policy.imports.iter().find(|(name, _)| name == entry.field());
+				match allowed {
+					None => violations.push(Violation::UnknownImport(entry.field().to_string())),
+					Some((_, Some(expected))) => {
+						let actual = match module.type_section().and_then(|section| section.types().get(*type_index as usize)) {
+							Some(elements::Type::Function(actual)) => actual,
+							None => continue,
+						};
+						if actual != expected {
+							violations.push(Violation::ImportSignature {
+								name: entry.field().to_string(),
+								expected: expected.clone(),
+								actual: actual.clone(),
+							});
+						}
+					},
+					Some((_, None)) => {},
+				}
+			},
+			elements::External::Memory(memory) => {
+				has_memory |= policy.imported_memory.as_deref() == Some(entry.field());
+				check_memory(memory.limits(), policy, &mut violations);
+			},
+			elements::External::Global(_) => {
+				if !policy.imported_globals {
+					violations.push(Violation::ImportedGlobal(entry.field().to_string()));
+				}
+			},
+			elements::External::Table(_) => {},
+		}
+	}
+
+	if let Some(name) = &policy.imported_memory {
+		if module.import_section().is_some() && !has_memory {
+			violations.push(Violation::MissingMemory(name.clone()));
+		}
+	}
+	for memory in module.memory_section().map(|section| section.entries()).unwrap_or(&[]) {
+		check_memory(memory.limits(), policy, &mut violations);
+	}
+
+	if !policy.forbidden.is_empty() {
+		let imported_functions = module.import_count(ImportCountType::Function) as u32;
+		for (index, body) in module.code_section().map(|section| section.bodies()).unwrap_or(&[]).iter().enumerate() {
+			for (offset, instruction) in body.code().elements().iter().enumerate() {
+				let instruction_type = InstructionType::op(instruction);
+				if policy.forbidden.contains(&instruction_type) {
+					violations.push(Violation::ForbiddenInstruction {
+						function: imported_functions + index as u32,
+						offset,
+						instruction_type,
+					});
+				}
+			}
+		}
+	}
+
+	let exports = module.export_section().map(|section| section.entries()).unwrap_or(&[]);
+	for (name, expected) in policy.exports.iter() {
+		let func_index = exports.iter()
+			.find(|entry| entry.field() == name)
+			.and_then(|entry| match entry.internal() {
+				elements::Internal::Function(index) => Some(*index),
+				_ => None,
+			});
+		match func_index.and_then(|index| function_type(module, index)) {
+			None => violations.push(Violation::MissingExport(name.clone())),
+			Some(actual) if actual != expected => violations.push(Violation::ExportSignature {
+				name: name.clone(),
+				expected: expected.clone(),
+				actual: actual.clone(),
+			}),
+			Some(_) => {},
+		}
+	}
+
+	violations
+}
+
+fn check_memory(limits: &elements::ResizableLimits, policy: &Policy, violations: &mut Vec<Violation>) {
+	let limit = match policy.max_memory_pages {
+		Some(limit) => limit,
+		None => return,
+	};
+	match limits.maximum() {
+		None => violations.push(Violation::UnlimitedMemory),
+		Some(maximum) if maximum > limit => violations.push(Violation::MemoryLimit { maximum, limit }),
+		Some(_) => {},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements::ValueType;
+	use super::*;
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	#[test]
+	fn pwasm_policy() {
+		let module = parse_wat(r#"
+			(module
+				(import "env" "ret" (func (param i32 i32)))
+				(import "env" "memory" (memory 1 16))
+				(func (export "call")
+					i32.const 0
+					i32.const 0
+					call 0
+				)
+			)
+		"#);
+		assert_eq!(check(&module, &Policy::pwasm()), vec![]);
+
+		let module = parse_wat(r#"
+			(module
+				(import "env" "random" (func))
+				(import "ext" "ret" (func))
+				(import "env" "gas" (global i32))
+				(memory 1)
+			)
+		"#);
+		assert_eq!(
+			check(&module, &Policy::pwasm()),
+			vec![
+				Violation::UnknownImport("random".to_string()),
+				Violation::ForeignImport("ext".to_string(), "ret".to_string()),
+				Violation::ImportedGlobal("gas".to_string()),
+				Violation::MissingMemory("memory".to_string()),
+				Violation::UnlimitedMemory,
+			],
+		);
+	}
+
+	#[test]
+	fn custom_policy() {
+		let ret = FunctionType::new(vec![ValueType::I32, ValueType::I32], vec![]);
+		let policy = Policy::new("host")
+			.with_import("ret", Some(ret.clone()))
+			.with_max_memory_pages(2)
+			.with_forbidden_floats()
+			.with_export("call", FunctionType::new(vec![], vec![]))
+			.with_export("deploy", FunctionType::new(vec![], vec![]));
+
+		let module = parse_wat(r#"
+			(module
+				(import "host" "ret" (func (param i64)))
+				(import "host" "memory" (memory 1 4))
+				(func (export "call") (param i32)
+					f32.const 1
+					drop
+				)
+			)
+		"#);
+		assert_eq!(
+			check(&module, &policy),
+			vec![
+				Violation::ImportSignature {
+					name: "ret".to_string(),
+					expected: ret,
+					actual: FunctionType::new(vec![ValueType::I64], vec![]),
+				},
+				Violation::MemoryLimit { maximum: 4, limit: 2 },
+				Violation::ForbiddenInstruction { function: 1, offset: 0, instruction_type: InstructionType::FloatConst },
+				Violation::ExportSignature {
+					name: "call".to_string(),
+					expected: FunctionType::new(vec![], vec![]),
+					actual: FunctionType::new(vec![ValueType::I32], vec![]),
+				},
+				Violation::MissingExport("deploy".to_string()),
+			],
+		);
+	}
+
+	#[test]
+	fn declared_memory() {
+		let policy = Policy::new("host").with_max_memory_pages(2);
+
+		assert_eq!(check(&parse_wat("(module (memory 1 2))"), &policy), vec![]);
+		assert_eq!(check(&parse_wat("(module (memory 1))"), &policy), vec![Violation::UnlimitedMemory]);
+		assert_eq!(
+			check(&parse_wat("(module (memory 1 4))"), &policy),
+			vec![Violation::MemoryLimit { maximum: 4, limit: 2 }],
+		);
+	}
+
+	#[test]
+	fn substrate_policy() {
+		let module = parse_wat(r#"
+			(module
+				(import "env" "ext_return" (func (param i32 i32)))
+				(import "env" "memory" (memory 1 16))
+				(func (export "call"))
+				(func (export "deploy")
+					i32.const 0
+					i32.const 0
+					call 0
+				)
+			)
+		"#);
+		assert_eq!(check(&module, &Policy::substrate()), vec![]);
+
+		let module = parse_wat(r#"
+			(module
+				(import "env" "ret" (func))
+				(import "env" "memory" (memory 1 16))
+				(func (export "call")
+					f32.const 1
+					drop
+				)
+			)
+		"#);
+		assert_eq!(
+			check(&module, &Policy::substrate()),
+			vec![
+				Violation::UnknownImport("ret".to_string()),
+				Violation::ForbiddenInstruction { function: 1, offset: 0, instruction_type: InstructionType::FloatConst },
+				Violation::MissingExport("deploy".to_string()),
+			],
+		);
+	}
+}
@@ -0,0 +1,246 @@
+//! Compaction of data segments.
+//!
+//! Linear memory starts zeroed, so zero bytes in data segments do not need to be stored.
+//! Segments are laid out into a memory image, which is cut into new segments
+//! around long runs of zeros.
+
+use crate::std::collections::BTreeMap;
+use crate::std::vec::Vec;
+
+use log::trace;
+use parity_wasm::elements::{self, Instruction};
+
+/// Constant offset of the active segment in the default memory.
+fn constant_offset(segment: &elements::DataSegment) -> Option<u64> {
+	if segment.index() != 0 {
+		return None;
+	}
+	match segment.offset().as_ref()?.code() {
+		[Instruction::I32Const(offset), Instruction::End] => Some(*offset as u32 as u64),
+		_ => None,
+	}
+}
+
+/// Write `data` at `offset` over the image, later writes win.
+///
+/// The image is kept as a map of disjoint and non-adjacent chunks by their offset.
+fn write(image: &mut BTreeMap<u64, Vec<u8>>, offset: u64, data: &[u8]) {
+	let end = offset + data.len() as u64;
+	let touched = image.range(..=end)
+		.filter(|(start, chunk)| **start + chunk.len() as u64 >= offset)
+		.map(|(start, _)| *start)
+		.collect::<Vec<_>>();
+
+	let mut start = offset;
+	let mut merged_end = end;
+	for chunk_start in touched.iter() {
+		start = start.min(*chunk_start);
+		merged_end = merged_end.max(chunk_start + image[chunk_start].len() as u64);
+	}
+
+	let mut merged = vec![0u8; (merged_end - start) as usize];
+	for chunk_start in touched {
+		let chunk = image.remove(&chunk_start).expect("Key is taken from the map");
+		let at = (chunk_start - start) as usize;
+		merged[at..at + chunk.len()].copy_from_slice(&chunk);
+	}
+	let at = (offset - start) as usize;
+	merged[at..at + data.len()].copy_from_slice(data);
+	image.insert(start, merged);
+}
+
+/// Cut the chunk into pieces without leading and trailing zeros and without
+/// zero runs of `min_zero_run` bytes or longer.
+fn split(offset: u64, chunk: &[u8], min_zero_run: usize, pieces: &mut Vec<(u64, Vec<u8>)>) {
+	let mut position = 0;
+	while position < chunk.len() {
+		// Skip zeros before the piece
+		match chunk[position..].iter().position(|byte| *byte != 0) {
+			Some(skip) => position += skip,
+			None => break,
+		}
+
+		// Piece ends where a long enough zero run (or the chunk) starts
+		let piece_start = position;
+		let mut piece_end = position;
+		while position < chunk.len() {
+			if chunk[position] != 0 {
+				position += 1;
+				piece_end = position;
+				continue;
+			}
+			let zeros = chunk[position..].iter().take_while(|byte| **byte == 0).count();
+			if zeros >= min_zero_run || position + zeros == chunk.len() {
+				position += zeros;
+				break;
+			}
+			position += zeros;
+		}
+
+		pieces.push((offset + piece_start as u64, chunk[piece_start..piece_end].to_vec()));
+	}
+}
+
+/// Compact data segments of the module.
+///
+/// All segments are merged where they are adjacent or overlapping, leading and trailing
+/// zeros are dropped and segments are split at runs of at least `min_zero_run` zeros.
+/// Splitting only pays off if the run is longer than the overhead of a new segment
+/// (about 8 bytes).
+///
+/// Only modules where every data segment is placed with the constant `i32.const` offset
+/// are compacted, since the order of segments can change. Modules with the imported memory,
+/// which may be not zeroed, and with the data count section, where bulk memory instructions
+/// refer to segments by index, are left untouched too.
+///
+/// Returns number of bytes removed from segments.
+pub fn compact_data_segments(module: &mut elements::Module, min_zero_run: usize) -> usize {
+	if module.import_count(elements::ImportCountType::Memory) > 0 {
+		trace!("Memory is imported, skipping compaction");
+		return 0;
+	}
+	if module.sections().iter().any(|section| matches!(section, elements::Section::DataCount(_))) {
+		trace!("Data count section is present, skipping compaction");
+		return 0;
+	}
+
+	let data_section = match module.data_section_mut() {
+		Some(data_section) => data_section,
+		None => return 0,
+	};
+
+	let mut image = BTreeMap::new();
+	let mut before = 0;
+	for segment in data_section.entries() {
+		let offset = match constant_offset(segment) {
+			Some(offset) => offset,
+			None => {
+				trace!("Data segment with non-constant offset, skipping compaction");
+				return 0;
+			},
+		};
+		before += segment.value().len();
+		write(&mut image, offset, segment.value());
+	}
+
+	let mut pieces = Vec::new();
+	for (offset, chunk) in image.iter() {
+		split(*offset, chunk, min_zero_run.max(1), &mut pieces);
+	}
+	let after = pieces.iter().map(|(_, piece)| piece.len()).sum::<usize>();
+	trace!("Compacted {} data segments into {}", data_section.entries().len(), pieces.len());
+
+	*data_section.entries_mut() = pieces.into_iter()
+		.map(|(offset, piece)| elements::DataSegment::new(
+			0,
+			Some(elements::InitExpr::new(vec![Instruction::I32Const(offset as u32 as i32), Instruction::End])),
+			piece,
+		))
+		.collect();
+
+	if data_section.entries().is_empty() {
+		module.sections_mut().retain(|section| !matches!(section, elements::Section::Data(_)));
+	}
+
+	before - after
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements::{self, Instruction};
+	use super::compact_data_segments;
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	fn segments(module: &elements::Module) -> Vec<(i32, Vec<u8>)> {
+		module.data_section().map(|section| section.entries()).unwrap_or(&[]).iter()
+			.map(|segment| {
+				let offset = match segment.offset().as_ref().expect("Segment is active").code() {
+					[Instruction::I32Const(offset), Instruction::End] => *offset,
+					_ => panic!("Offset is expected to be constant"),
+				};
+				(offset, segment.value().to_vec())
+			})
+			.collect()
+	}
+
+	#[test]
+	fn compacts() {
+		let mut module = parse_wat(r#"
+			(module
+				(memory 1)
+				(data (i32.const 0) "\00\00\01\02\00\00\00\00\00\00\00\00\03\00\04\00\00")
+				(data (i32.const 17) "\05")
+				(data (i32.const 14) "\06\07")
+				(data (i32.const 100) "\00\00\00\00")
+				(data (i32.const 200) "\08\00\00\00\00\00\00\00\00\00\00"))
+		"#);
+
+		assert_eq!(compact_data_segments(&mut module, 4), 35 - 9);
+		assert_eq!(segments(&module), vec![
+			(2, vec![1, 2]),
+			(12, vec![3, 0, 6, 7, 0, 5]),
+			(200, vec![8]),
+		]);
+
+		let binary = elements::serialize(module).expect("Failed to serialize");
+		wabt::Module::read_binary(&binary, &Default::default())
+			.expect("Wabt failed to read final binary")
+			.validate()
+			.expect("Invalid module");
+	}
+
+	#[test]
+	fn zeros_only() {
+		let mut module = parse_wat(r#"
+			(module
+				(memory 1)
+				(data (i32.const 8) "\00\00\00\00"))
+		"#);
+
+		assert_eq!(compact_data_segments(&mut module, 8), 4);
+		assert!(module.data_section().is_none());
+	}
+
+	#[test]
+	fn global_offset() {
+		let mut module = parse_wat(r#"
+			(module
+				(import "env" "base" (global i32))
+				(memory 1)
+				(data (i32.const 0) "\00\00\01")
+				(data (global.get 0) "\00\00\02"))
+		"#);
+
+		assert_eq!(compact_data_segments(&mut module, 1), 0);
+		assert_eq!(module.data_section().expect("Data section to stay").entries().len(), 2);
+	}
+
+	#[test]
+	fn imported_memory() {
+		let mut module = parse_wat(r#"
+			(module
+				(import "env" "memory" (memory 1))
+				(data (i32.const 0) "\00\00\01"))
+		"#);
+
+		assert_eq!(compact_data_segments(&mut module, 1), 0);
+		assert_eq!(segments(&module), vec![(0, vec![0, 0, 1])]);
+	}
+
+	#[test]
+	fn data_count() {
+		let mut module = parse_wat(r#"
+			(module
+				(memory 1)
+				(data (i32.const 0) "\00\00\01"))
+		"#);
+		module.sections_mut().insert(1, elements::Section::DataCount(1));
+
+		assert_eq!(compact_data_segments(&mut module, 1), 0);
+		assert_eq!(segments(&module), vec![(0, vec![0, 0, 1])]);
+	}
+}
@@ -0,0 +1,295 @@
+//! Merging of structurally identical function types and function bodies.
+//!
+//! Compilers often emit the same signature several times in the type section
+//! and byte-identical functions (e.g. monomorphized generics or trivial trampolines).
+//! Duplicates are merged into the first occurrence: all references are pointed to it
+//! first, and then the unreferenced duplicates are deleted with indices updated
+//! the same way the optimizer does.
+
+use crate::std::collections::BTreeMap;
+use crate::std::vec::Vec;
+
+use log::trace;
+use parity_wasm::elements;
+
+use crate::optimizer::{
+	code_section, function_section, parse_names, type_section, update_call_index,
+	update_name_section, update_type_index,
+};
+
+/// Merge identical function types and identical function bodies.
+///
+/// Function bodies are merged only if they have the same signature. Merging functions
+/// can make their callers identical, so functions are merged until no duplicates are left.
+///
+/// Function and local names are updated if the name section can be parsed,
+/// otherwise the name section is dropped once any function is removed.
+///
+/// Returns total number of removed types and functions.
+pub fn deduplicate(module: &mut elements::Module) -> usize {
+	parse_names(module);
+
+	let mut removed = dedup_types(module);
+	loop {
+		let removed_funcs = dedup_funcs(module);
+		if removed_funcs == 0 { break; }
+		removed += removed_funcs;
+	}
+	removed
+}
+
+/// Returns index of the first occurrence for every duplicate in the list.
+fn duplicates<T: Ord>(keys: Vec<T>) -> BTreeMap<usize, usize> {
+	let mut first_seen = BTreeMap::new();
+	let mut duplicates = BTreeMap::new();
+	for (index, key) in keys.into_iter().enumerate() {
+		match first_seen.get(&key) {
+			Some(first) => { duplicates.insert(index, *first); },
+			None => { first_seen.insert(key, index); },
+		}
+	}
+	duplicates
+}
+
+fn canonical(duplicates: &BTreeMap<usize, usize>, index: &mut u32) {
+	if let Some(first) = duplicates.get(&(*index as usize)) {
+		*index = *first as u32;
+	}
+}
+
+fn shift(eliminated: &[usize], index: &mut u32) {
+	let totalle = eliminated.iter().take_while(|i| (**i as u32) < *index).count();
+	*index -= totalle as u32;
+}
+
+fn dedup_types(module: &mut elements::Module) -> usize {
+	let keys = match module.type_section() {
+		Some(section) => section.types().iter()
+			.map(|t| elements::serialize(t.clone()).expect("Type serialization never fails"))
+			.collect(),
+		None => return 0,
+	};
+	let duplicates = duplicates::<Vec<u8>>(keys);
+	if duplicates.is_empty() { return 0; }
+
+	let eliminated = duplicates.keys().cloned().collect::<Vec<_>>();
+	for index in eliminated.iter().rev() {
+		type_section(module).expect("Type section exists, since there are duplicates").types_mut().remove(*index);
+		trace!("Eliminated duplicate type({})", index);
+	}
+
+	for section in module.sections_mut() {
+		match section {
+			elements::Section::Import(import_section) => {
+				for import_entry in import_section.entries_mut() {
+					if let elements::External::Function(type_ref) = import_entry.external_mut() {
+						canonical(&duplicates, type_ref);
+						shift(&eliminated, type_ref);
+					}
+				}
+			},
+			elements::Section::Function(function_section) => {
+				for func_signature in function_section.entries_mut() {
+					canonical(&duplicates, func_signature.type_ref_mut());
+					shift(&eliminated, func_signature.type_ref_mut());
+				}
+			},
+			elements::Section::Code(code_section) => {
+				for func_body in code_section.bodies_mut() {
+					for instruction in func_body.code_mut().elements_mut() {
+						if let elements::Instruction::CallIndirect(type_ref, _) = instruction {
+							canonical(&duplicates, type_ref);
+						}
+					}
+					update_type_index(func_body.code_mut(), &eliminated);
+				}
+			},
+			_ => { },
+		}
+	}
+
+	eliminated.len()
+}
+
+fn dedup_funcs(module: &mut elements::Module) -> usize {
+	let imported_funcs = module.import_count(elements::ImportCountType::Function);
+	let keys = match (module.function_section(), module.code_section()) {
+		(Some(functions), Some(code)) => functions.entries().iter()
+			.zip(code.bodies())
+			.map(|(func, body)| (
+				func.type_ref(),
+				elements::serialize(body.clone()).expect("Function body serialization never fails"),
+			))
+			.collect(),
+		_ => return 0,
+	};
+	let declared_duplicates = duplicates::<(u32, Vec<u8>)>(keys);
+	if declared_duplicates.is_empty() { return 0; }
+
+	for index in declared_duplicates.keys().rev() {
+		function_section(module).expect("Function section exists, since there are duplicates").entries_mut().remove(*index);
+		code_section(module).expect("Code section exists, since there are duplicates").bodies_mut().remove(*index);
+		trace!("Eliminated duplicate function({})", imported_funcs + index);
+	}
+
+	// Indices in the function space
+	let duplicates = declared_duplicates.iter()
+		.map(|(index, first)| (imported_funcs + index, imported_funcs + first))
+		.collect::<BTreeMap<_, _>>();
+	let eliminated = duplicates.keys().cloned().collect::<Vec<_>>();
+
+	for section in module.sections_mut() {
+		match section {
+			elements::Section::Start(func_index) => {
+				canonical(&duplicates, func_index);
+				shift(&eliminated, func_index);
+			},
+			elements::Section::Export(export_section) => {
+				for export in export_section.entries_mut() {
+					if let elements::Internal::Function(func_index) = export.internal_mut() {
+						canonical(&duplicates, func_index);
+						shift(&eliminated, func_index);
+					}
+				}
+			},
+			elements::Section::Element(elements_section) => {
+				for segment in elements_section.entries_mut() {
+					for func_index in segment.members_mut() {
+						canonical(&duplicates, func_index);
+						shift(&eliminated, func_index);
+					}
+				}
+			},
+			elements::Section::Code(code_section) => {
+				for func_body in code_section.bodies_mut() {
+					for instruction in func_body.code_mut().elements_mut() {
+						if let elements::Instruction::Call(func_index) = instruction {
+							canonical(&duplicates, func_index);
+						}
+					}
+					update_call_index(func_body.code_mut(), &eliminated);
+				}
+			},
+			elements::Section::Name(name_section) => {
+				update_name_section(name_section, &eliminated);
+			},
+			_ => { },
+		}
+	}
+
+	// Unparsed name section would refer to the wrong functions now
+	module.sections_mut().retain(|section| match section {
+		elements::Section::Custom(custom) => custom.name() != "name",
+		_ => true,
+	});
+
+	eliminated.len()
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements;
+	use super::deduplicate;
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	fn validate_module(module: elements::Module) {
+		let binary = elements::serialize(module).expect("Failed to serialize");
+		wabt::Module::read_binary(&binary, &Default::default())
+			.expect("Wabt failed to read final binary")
+			.validate()
+			.expect("Invalid module");
+	}
+
+	#[test]
+	fn types() {
+		let mut module = parse_wat(r#"
+			(module
+				(type (func (param i32)))
+				(type (func))
+				(type (func (param i32)))
+				(import "env" "foo" (func (type 2)))
+				(func (type 2)
+					get_local 0
+					i32.const 0
+					call_indirect (type 2))
+				(table 1 anyfunc))
+		"#);
+
+		assert_eq!(deduplicate(&mut module), 1);
+
+		assert_eq!(module.type_section().expect("Type section to stay").types().len(), 2);
+		assert_eq!(module.function_section().expect("Function section to stay").entries()[0].type_ref(), 0);
+		assert_eq!(
+			module.code_section().expect("Code section to stay").bodies()[0].code().elements()[2],
+			elements::Instruction::CallIndirect(0, 0),
+		);
+		validate_module(module);
+	}
+
+	#[test]
+	fn funcs() {
+		let mut module = parse_wat(r#"
+			(module
+				(import "env" "foo" (func (param i32)))
+				(func $a (param i32) (result i32)
+					get_local 0
+					i32.const 1
+					i32.add)
+				(func $b (param i32) (result i32)
+					get_local 0
+					i32.const 1
+					i32.add)
+				(func $call_a (result i32)
+					i32.const 0
+					call $a)
+				(func $call_b (result i32)
+					i32.const 0
+					call $b)
+				(func $main
+					call $call_a
+					call $call_b
+					i32.add
+					call 0)
+				(table 2 anyfunc)
+				(elem (i32.const 0) $b $call_b)
+				(export "a" (func $a))
+				(export "b" (func $b))
+				(export "main" (func $main))
+				(start $main))
+		"#);
+
+		// $b is merged into $a, and after that $call_b becomes identical to $call_a
+		assert_eq!(deduplicate(&mut module), 2);
+
+		assert_eq!(module.function_section().expect("Function section to stay").entries().len(), 3);
+		assert_eq!(
+			module.code_section().expect("Code section to stay").bodies()[2].code().elements(),
+			&[
+				elements::Instruction::Call(2),
+				elements::Instruction::Call(2),
+				elements::Instruction::I32Add,
+				elements::Instruction::Call(0),
+				elements::Instruction::End,
+			],
+		);
+		assert_eq!(module.elements_section().expect("Element section to stay").entries()[0].members(), &[1, 2]);
+		let exports = module.export_section().expect("Export section to stay").entries()
+			.iter()
+			.map(|e| *e.internal())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			exports,
+			vec![
+				elements::Internal::Function(1),
+				elements::Internal::Function(1),
+				elements::Internal::Function(3),
+			],
+		);
+		assert_eq!(module.start_section(), Some(3));
+		validate_module(module);
+	}
+}
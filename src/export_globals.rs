@@ -1,39 +1,68 @@
 use parity_wasm::elements;
 
-use crate::optimizer::{global_section, export_section};
+use crate::graph::{self, Export, ExportLocal, ImportedOrDeclared};
+use crate::pass::{self, Pass, Pipeline};
 
 /// Export all declared mutable globals.
 ///
 /// This will export all internal mutable globals under the name of
 /// concat(`prefix`, i) where i is the index inside the range of
 /// [0..<total number of internal mutable globals>].
+///
+/// # Panics
+///
+/// Panics if the module references missing items, use `try_export_mutable_globals` to get the error instead.
 pub fn export_mutable_globals(
 	module: &mut elements::Module,
 	prefix: impl Into<String>,
 ) {
+	try_export_mutable_globals(module, prefix).expect("Failed to export mutable globals")
+}
+
+/// Same as `export_mutable_globals`, but returns the error instead of panicking.
+pub fn try_export_mutable_globals(
+	module: &mut elements::Module,
+	prefix: impl Into<String>,
+) -> Result<(), pass::Error> {
+	*module = Pipeline::new()
+		.with_pass(ExportMutableGlobals::new(prefix))
+		.run(module)?;
+	Ok(())
+}
+
+/// Pass exporting declared mutable globals, see `export_mutable_globals`.
+pub struct ExportMutableGlobals {
+	prefix: String,
+}
 
-	let exports = global_section(module).map(
-		|section| section.entries().iter().enumerate().filter_map(
-			|(index, global)| if global.global_type().is_mutable() { Some(index) } else { None }
-		).collect::<Vec<_>>()
-	).unwrap_or_default();
+impl ExportMutableGlobals {
+	/// New pass exporting globals with names starting with `prefix`.
+	pub fn new(prefix: impl Into<String>) -> Self {
+		ExportMutableGlobals { prefix: prefix.into() }
+	}
+}
 
-	if module.export_section().is_none() {
-		module.sections_mut().push(elements::Section::Export(elements::ExportSection::default()));
+impl Pass for ExportMutableGlobals {
+	fn name(&self) -> &str {
+		"export-globals"
 	}
 
-	let prefix: String = prefix.into();
-	for (symbol_index, export) in exports.into_iter().enumerate() {
-		let new_entry = elements::ExportEntry::new(
-			format!("{}_{}", prefix, symbol_index),
-			elements::Internal::Global(
-				(module.import_count(elements::ImportCountType::Global) + export) as _
-			),
-		);
-		export_section(module)
-			.expect("added above if does not exists")
-			.entries_mut()
-			.push(new_entry);
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		let globals = module.globals.iter()
+			.filter(|global| {
+				let global = global.read();
+				global.is_mut && matches!(global.origin, ImportedOrDeclared::Declared(_))
+			})
+			.cloned()
+			.collect::<Vec<_>>();
+
+		for (symbol_index, global_ref) in globals.into_iter().enumerate() {
+			module.exports.push(Export {
+				name: format!("{}_{}", self.prefix, symbol_index),
+				local: ExportLocal::Global(global_ref),
+			});
+		}
+		Ok(())
 	}
 }
 
@@ -5,22 +5,9 @@ use crate::std::borrow::ToOwned;
 use parity_wasm::{elements, builder};
 use byteorder::{LittleEndian, ByteOrder};
 
+use crate::graph::{self, ExportLocal, Instruction};
 use crate::optimizer::{import_section, export_section};
-
-type Insertion = (usize, u32, u32, String);
-
-pub fn update_call_index(instructions: &mut elements::Instructions, original_imports: usize, inserts: &[Insertion]) {
-	use parity_wasm::elements::Instruction::*;
-	for instruction in instructions.elements_mut().iter_mut() {
-		if let Call(call_index) = instruction {
-			if let Some(pos) = inserts.iter().position(|x| x.1 == *call_index) {
-				*call_index = (original_imports + pos) as u32;
-			} else if *call_index as usize > original_imports {
-				*call_index += inserts.len() as u32;
-			}
-		}
-	}
-}
+use crate::pass::{self, Pass, Pipeline};
 
 pub fn memory_section(module: &mut elements::Module) -> Option<&mut elements::MemorySection> {
 	for section in module.sections_mut() {
@@ -99,12 +86,8 @@ pub fn shrink_unknown_stack(
 		match section {
 			elements::Section::Data(data_section) => {
 				for data_segment in data_section.entries_mut() {
-					if *data_segment
-						.offset()
-						.as_ref()
-						.expect("parity-wasm is compiled without bulk-memory operations")
-						.code() == [elements::Instruction::I32Const(4), elements::Instruction::End]
-					{
+					let offset = data_segment.offset().as_ref().map(|offset| offset.code());
+					if offset == Some(&[elements::Instruction::I32Const(4), elements::Instruction::End][..]) {
 						assert_eq!(data_segment.value().len(), 4);
 						let current_val = LittleEndian::read_u32(data_segment.value());
 						let new_val = current_val - shrink_amount;
@@ -119,86 +102,80 @@ pub fn shrink_unknown_stack(
 	(module, new_stack_top)
 }
 
+/// Replace calls to the exported functions with calls to imports `env.<export name>`.
+///
+/// # Panics
+///
+/// Panics if some of the functions is not exported or the module references missing items,
+/// use `try_externalize` to get the error instead.
 pub fn externalize(
 	module: elements::Module,
 	replaced_funcs: Vec<&str>,
 ) -> elements::Module {
-   // Save import functions number for later
-	let import_funcs_total = module
-		.import_section().expect("Import section to exist")
-		.entries()
-		.iter()
-		.filter(|e| matches!(e.external(), &elements::External::Function(_)))
-		.count();
-
-	// First, we find functions indices that are to be rewired to externals
-	//   Triple is (function_index (callable), type_index, function_name)
-	let mut replaces: Vec<Insertion> = replaced_funcs
-		.into_iter()
-		.filter_map(|f| {
-			let export = module
-				.export_section().expect("Export section to exist")
-				.entries().iter().enumerate()
-				.find(|&(_, entry)| entry.field() == f)
-				.expect("All functions of interest to exist");
-
-			if let elements::Internal::Function(func_idx) = *export.1.internal() {
-				let type_ref = module
-					.function_section().expect("Functions section to exist")
-					.entries()[func_idx as usize - import_funcs_total]
-					.type_ref();
-
-				Some((export.0, func_idx, type_ref, export.1.field().to_owned()))
-			} else {
-				None
-			}
-		})
-		.collect();
-
-	replaces.sort_by_key(|e| e.0);
-
-	// Second, we duplicate them as import definitions
-	let mut mbuilder = builder::from_module(module);
-	for (_, _, type_ref, field) in replaces.iter() {
-		mbuilder.push_import(
-			builder::import()
-				.module("env")
-				.field(field)
-				.external().func(*type_ref)
-				.build()
-		);
+	try_externalize(module, replaced_funcs).expect("Failed to externalize functions")
+}
+
+/// Same as `externalize`, but returns the error instead of panicking.
+pub fn try_externalize(
+	module: elements::Module,
+	replaced_funcs: Vec<&str>,
+) -> Result<elements::Module, pass::Error> {
+	Pipeline::new()
+		.with_pass(Externalize::new(replaced_funcs))
+		.run(&module)
+}
+
+/// Pass replacing calls to exported functions with calls to imports `env.<export name>`.
+///
+/// Exports and table elements keep referencing the original functions.
+pub struct Externalize<'a> {
+	replaced_funcs: Vec<&'a str>,
+}
+
+impl<'a> Externalize<'a> {
+	/// New pass replacing functions exported under `replaced_funcs` names.
+	pub fn new(replaced_funcs: Vec<&'a str>) -> Self {
+		Externalize { replaced_funcs }
 	}
+}
 
-	// Back to mutable access
-	let mut module = mbuilder.build();
+impl<'a> Pass for Externalize<'a> {
+	fn name(&self) -> &str {
+		"externalize"
+	}
 
-	// Third, rewire all calls to imported functions and update all other calls indices
-	for section in module.sections_mut() {
-		match section {
-			elements::Section::Code(code_section) => {
-				for func_body in code_section.bodies_mut() {
-					update_call_index(func_body.code_mut(), import_funcs_total, &replaces);
-				}
-			},
-			elements::Section::Export(export_section) => {
-				for export in export_section.entries_mut() {
-					if let elements::Internal::Function(func_index) = export.internal_mut() {
-						if *func_index >= import_funcs_total as u32 { *func_index += replaces.len() as u32; }
-					}
-				}
-			},
-			elements::Section::Element(elements_section) => {
-				for segment in elements_section.entries_mut() {
-					// update all indirect call addresses initial values
-					for func_index in segment.members_mut() {
-						if *func_index >= import_funcs_total as u32 { *func_index += replaces.len() as u32; }
-					}
-				}
-			},
-			_ => { }
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		for name in self.replaced_funcs.iter() {
+			if !module.exports.iter().any(|export| export.name == *name) {
+				return Err(format!("No exported function `{}`", name));
+			}
 		}
-	}
 
-	module
+		// Imports are added in the order of exports
+		let replaced = module.exports.iter()
+			.filter(|export| self.replaced_funcs.contains(&export.name.as_str()))
+			.filter_map(|export| match &export.local {
+				ExportLocal::Func(func_ref) => Some((export.name.clone(), func_ref.clone())),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+
+		let mut replaces = Vec::new();
+		for (name, func_ref) in replaced {
+			let elements::Type::Function(signature) = (**func_ref.read().type_ref.read()).clone();
+			replaces.push((func_ref, module.add_import_func("env", &name, signature)));
+		}
 
+		pass::for_each_body(module, |_, code| {
+			for instruction in code.iter_mut() {
+				if let Instruction::Call(call_ref) = instruction {
+					let order = call_ref.order();
+					if let Some((_, import_ref)) = replaces.iter().find(|(func_ref, _)| func_ref.order() == order) {
+						*call_ref = import_ref.clone();
+					}
+				}
+			}
+			Ok(())
+		})
+	}
 }
@@ -0,0 +1,109 @@
+//! Reading and writing modules in the binary or the text format
+
+use std::{fmt, fs, io};
+use std::path::Path;
+use std::str::FromStr;
+
+use parity_wasm::elements;
+
+/// Error of reading or writing the module file.
+#[derive(Debug)]
+pub enum Error {
+	/// File (with the path) can't be read or written.
+	Io(String, io::Error),
+	/// Text of the file (with the path) is not valid WAT.
+	Wat(String, String),
+	/// Module (from the file with the path) can't be decoded or encoded.
+	Wasm(String, elements::Error),
+	/// Module can't be printed as WAT.
+	Print(String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match self {
+			Error::Io(path, err) => write!(f, "Failed to access {}: {}", path, err),
+			Error::Wat(path, err) => write!(f, "Failed to parse {}: {}", path, err),
+			Error::Wasm(path, err) => write!(f, "Invalid module {}: {}", path, err),
+			Error::Print(err) => write!(f, "Failed to print the module: {}", err),
+		}
+	}
+}
+
+/// Format of the output file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+	/// Binary format.
+	Wasm,
+	/// Text format.
+	Wat,
+}
+
+impl Format {
+	/// Format of the output path: text for `.wat` files, otherwise binary.
+	pub fn of_path(path: &str) -> Self {
+		match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+			Some("wat") => Format::Wat,
+			_ => Format::Wasm,
+		}
+	}
+
+	/// Format requested with `--emit`, falling back to the format of the output path.
+	pub fn for_output(emit: Option<&str>, path: &str) -> Self {
+		emit.and_then(|emit| emit.parse().ok()).unwrap_or_else(|| Format::of_path(path))
+	}
+}
+
+impl FromStr for Format {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"wasm" => Ok(Format::Wasm),
+			"wat" => Ok(Format::Wat),
+			_ => Err(format!("Unknown format `{}`, expected `wasm` or `wat`", s)),
+		}
+	}
+}
+
+/// Read the module from the file, either binary or WAT.
+///
+/// The format is detected from the content, so `.wat` files need no special handling.
+pub fn read_module(path: &str) -> Result<elements::Module, Error> {
+	let bytes = fs::read(path).map_err(|err| Error::Io(path.to_owned(), err))?;
+	let binary = wat::parse_bytes(&bytes).map_err(|err| Error::Wat(path.to_owned(), err.to_string()))?;
+	elements::deserialize_buffer(&binary).map_err(|err| Error::Wasm(path.to_owned(), err))
+}
+
+/// Write the module to the file in the given format.
+pub fn write_module(path: &str, module: elements::Module, format: Format) -> Result<(), Error> {
+	let binary = elements::serialize(module).map_err(|err| Error::Wasm(path.to_owned(), err))?;
+	let contents = match format {
+		Format::Wasm => binary,
+		Format::Wat => wasmprinter::print_bytes(&binary).map_err(|err| Error::Print(err.to_string()))?.into_bytes(),
+	};
+	fs::write(path, contents).map_err(|err| Error::Io(path.to_owned(), err))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn wat_round_trip() {
+		let dir = tempdir::TempDir::new("file").expect("Failed to create temp dir");
+		let wat_path = dir.path().join("module.wat").to_string_lossy().to_string();
+		let wasm_path = dir.path().join("module.wasm").to_string_lossy().to_string();
+		fs::write(&wat_path, r#"(module (func (export "call")))"#).expect("Failed to write");
+
+		let module = read_module(&wat_path).expect("WAT to be read");
+		assert_eq!(Format::of_path(&wat_path), Format::Wat);
+		write_module(&wasm_path, module.clone(), Format::of_path(&wasm_path)).expect("Binary to be written");
+		write_module(&wat_path, module.clone(), Format::Wat).expect("WAT to be written");
+
+		assert_eq!(read_module(&wasm_path).expect("Binary to be read"), module);
+		assert_eq!(read_module(&wat_path).expect("WAT to be read again"), module);
+		assert!(fs::read_to_string(&wat_path).expect("WAT to be text").contains("(export \"call\""));
+		assert!(matches!(read_module(&wat_path.replace(".wat", ".missing")), Err(Error::Io(..))));
+	}
+}
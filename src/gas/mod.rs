@@ -9,20 +9,15 @@ mod validation;
 
 use crate::std::cmp::min;
 use crate::std::mem;
+use crate::std::string::String;
 use crate::std::vec::Vec;
 
-use parity_wasm::{elements, elements::ValueType, builder};
+use parity_wasm::{elements, elements::ValueType};
+use crate::graph::{self, Instruction};
+use crate::pass::{self, Pass, Pipeline};
+use crate::ref_list::EntryRef;
 use crate::rules::Rules;
 
-pub fn update_call_index(instructions: &mut elements::Instructions, inserted_index: u32) {
-	use parity_wasm::elements::Instruction::*;
-	for instruction in instructions.elements_mut().iter_mut() {
-		if let Call(call_index) = instruction {
-			if *call_index >= inserted_index { *call_index += 1}
-		}
-	}
-}
-
 /// A control flow block is opened with the `block`, `loop`, and `if` instructions and is closed
 /// with `end`. Each block implicitly defines a new label. The control blocks form a stack during
 /// program execution.
@@ -234,39 +229,19 @@ fn inject_grow_counter(instructions: &mut elements::Instructions, grow_counter_f
 	counter
 }
 
-fn add_grow_counter<R: Rules>(
-	module: elements::Module,
-	rules: &R,
-	gas_func: u32
-) -> elements::Module {
+/// Function charging gas for `memory.grow` and growing the memory.
+fn grow_counter(cost: u32, gas_func: &EntryRef<graph::Func>, memory: &EntryRef<graph::Memory>) -> Vec<Instruction> {
 	use parity_wasm::elements::Instruction::*;
-	use crate::rules::MemoryGrowCost;
-
-	let cost = match rules.memory_grow_cost() {
-		None => return module,
-		Some(MemoryGrowCost::Linear(val)) => val.get(),
-	};
-
-	let mut b = builder::from_module(module);
-	b.push_function(
-		builder::function()
-			.signature().with_param(ValueType::I32).with_result(ValueType::I32).build()
-			.body()
-				.with_instructions(elements::Instructions::new(vec![
-					GetLocal(0),
-					GetLocal(0),
-					I32Const(cost as i32),
-					I32Mul,
-					// todo: there should be strong guarantee that it does not return anything on stack?
-					Call(gas_func),
-					GrowMemory(0),
-					End,
-				]))
-				.build()
-			.build()
-	);
-
-	b.build()
+	vec![
+		Instruction::Plain(GetLocal(0)),
+		Instruction::Plain(GetLocal(0)),
+		Instruction::Plain(I32Const(cost as i32)),
+		Instruction::Plain(I32Mul),
+		// todo: there should be strong guarantee that it does not return anything on stack?
+		Instruction::Call(gas_func.clone()),
+		Instruction::GrowMemory(memory.clone()),
+		Instruction::Plain(End),
+	]
 }
 
 pub(crate) fn determine_metered_blocks<R: Rules>(
@@ -437,77 +412,78 @@ pub fn inject_gas_counter<R: Rules>(
 )
 	-> Result<elements::Module, elements::Module>
 {
-	// Injecting gas counting external
-	let mut mbuilder = builder::from_module(module);
-	let import_sig = mbuilder.push_signature(
-		builder::signature()
-			.with_param(ValueType::I32)
-			.build_sig()
-		);
+	Pipeline::new()
+		.with_pass(GasMetering::new(rules, gas_module_name))
+		.run(&module)
+		.map_err(|_| module)
+}
+
+/// Pass injecting gas metering, see `inject_gas_counter`.
+pub struct GasMetering<'a, R> {
+	rules: &'a R,
+	gas_module_name: &'a str,
+}
+
+impl<'a, R: Rules> GasMetering<'a, R> {
+	/// New pass charging gas by `rules` with the function imported from `gas_module_name`.
+	pub fn new(rules: &'a R, gas_module_name: &'a str) -> Self {
+		GasMetering { rules, gas_module_name }
+	}
+}
 
-	mbuilder.push_import(
-		builder::import()
-			.module(gas_module_name)
-			.field("gas")
-			.external().func(import_sig)
-			.build()
+impl<'a, R: Rules> Pass for GasMetering<'a, R> {
+	fn name(&self) -> &str {
+		"gas"
+	}
+
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		use crate::rules::MemoryGrowCost;
+
+		let gas_func = module.add_import_func(
+			self.gas_module_name,
+			"gas",
+			elements::FunctionType::new(vec![ValueType::I32], vec![]),
 		);
 
-	// back to plain module
-	let mut module = mbuilder.build();
-
-	// calculate actual function index of the imported definition
-	//    (subtract all imports that are NOT functions)
-
-	let gas_func = module.import_count(elements::ImportCountType::Function) as u32 - 1;
-	let total_func = module.functions_space() as u32;
-	let mut need_grow_counter = false;
-	let mut error = false;
-
-	// Updating calling addresses (all calls to function index >= `gas_func` should be incremented)
-	for section in module.sections_mut() {
-		match section {
-			elements::Section::Code(code_section) => {
-				for func_body in code_section.bodies_mut() {
-					update_call_index(func_body.code_mut(), gas_func);
-					if inject_counter(func_body.code_mut(), rules, gas_func).is_err() {
-						error = true;
-						break;
-					}
-					if rules.memory_grow_cost().is_some()
-						&& inject_grow_counter(func_body.code_mut(), total_func) > 0
-					{
-						need_grow_counter = true;
-					}
-				}
-			},
-			elements::Section::Export(export_section) => {
-				for export in export_section.entries_mut() {
-					if let elements::Internal::Function(func_index) = export.internal_mut() {
-						if *func_index >= gas_func { *func_index += 1}
-					}
-				}
-			},
-			elements::Section::Element(elements_section) => {
-				// Note that we do not need to check the element type referenced because in the
-				// WebAssembly 1.0 spec, the only allowed element type is funcref.
-				for segment in elements_section.entries_mut() {
-					// update all indirect call addresses initial values
-					for func_index in segment.members_mut() {
-						if *func_index >= gas_func { *func_index += 1}
-					}
-				}
+		// The graph resolves `memory.grow` to the memory it grows, so a module without one is rejected earlier
+		let grown_memory = module.funcs.iter().find_map(|func| match &func.read().origin {
+			graph::ImportedOrDeclared::Declared(body) => body.code.iter().find_map(|instruction| match instruction {
+				Instruction::GrowMemory(memory) => Some(memory.clone()),
+				_ => None,
+			}),
+			graph::ImportedOrDeclared::Imported(..) => None,
+		});
+		let grow_func = match (self.rules.memory_grow_cost(), grown_memory) {
+			(Some(MemoryGrowCost::Linear(cost)), Some(memory)) => {
+				Some(module.add_func(
+					elements::FunctionType::new(vec![ValueType::I32], vec![ValueType::I32]),
+					Vec::new(),
+					grow_counter(cost.get(), &gas_func, &memory),
+				))
 			},
-			elements::Section::Start(start_idx) => {
-				if *start_idx >= gas_func { *start_idx += 1}
-			},
-			_ => { }
-		}
-	}
+			_ => None,
+		};
 
-	if error { return Err(module); }
+		let gas_index = gas_func.order().expect("Gas function is just inserted") as u32;
+		let grow_index = grow_func.as_ref().and_then(|func| func.order());
+		pass::for_each_body(module, |index, code| {
+			if Some(index) == grow_index {
+				return Ok(());
+			}
 
-	if need_grow_counter { Ok(add_grow_counter(module, rules, gas_func)) } else { Ok(module) }
+			let mut instructions = elements::Instructions::new(
+				module.generate_instructions(code).map_err(|error| format!("{:?}", error))?
+			);
+			inject_counter(&mut instructions, self.rules, gas_index)
+				.map_err(|_| format!("Function {} can't be metered", index))?;
+			if let Some(grow_index) = grow_index {
+				inject_grow_counter(&mut instructions, grow_index as u32);
+			}
+			*code = module.map_instructions(instructions.elements())
+				.map_err(|error| format!("{:?}", error))?;
+			Ok(())
+		})
+	}
 }
 
 #[cfg(test)]
@@ -531,6 +507,7 @@ mod tests {
 			.global()
 				.value_type().i32()
 				.build()
+			.memory().build()
 			.function()
 				.signature().param().i32().build()
 				.body()
@@ -584,6 +561,7 @@ mod tests {
 			.global()
 				.value_type().i32()
 				.build()
+			.memory().build()
 			.function()
 				.signature().param().i32().build()
 				.body()
@@ -617,6 +595,24 @@ mod tests {
 		wabt::wasm2wat(&binary).unwrap();
 	}
 
+	#[test]
+	fn grow_without_memory_is_rejected() {
+		let module = builder::module()
+			.function()
+				.signature().build()
+				.body()
+					.with_instructions(elements::Instructions::new(vec![I32Const(1), GrowMemory(0), Drop, End]))
+					.build()
+				.build()
+			.build();
+
+		let error = crate::Pipeline::new()
+			.with_pass(GasMetering::new(&rules::Set::default().with_grow_cost(10000), "env"))
+			.run(&module)
+			.unwrap_err();
+		assert!(matches!(error, crate::PassError::Graph(_)));
+	}
+
 	#[test]
 	fn call_index() {
 		let module = builder::module()
@@ -735,6 +731,7 @@ mod tests {
 		name = simple;
 		input = r#"
 		(module
+			(global i32 (i32.const 0))
 			(func (result i32)
 				(get_global 0)))
 		"#;
@@ -750,6 +747,7 @@ mod tests {
 		name = nested;
 		input = r#"
 		(module
+			(global i32 (i32.const 0))
 			(func (result i32)
 				(get_global 0)
 				(block
@@ -775,6 +773,7 @@ mod tests {
 		name = ifelse;
 		input = r#"
 		(module
+			(global i32 (i32.const 0))
 			(func (result i32)
 				(get_global 0)
 				(if
@@ -810,6 +809,7 @@ mod tests {
 		name = branch_innermost;
 		input = r#"
 		(module
+			(global i32 (i32.const 0))
 			(func (result i32)
 				(get_global 0)
 				(block
@@ -840,6 +840,7 @@ mod tests {
 		name = branch_outer_block;
 		input = r#"
 		(module
+			(global i32 (i32.const 0))
 			(func (result i32)
 				(get_global 0)
 				(block
@@ -879,6 +880,7 @@ mod tests {
 		name = branch_outer_loop;
 		input = r#"
 		(module
+			(global i32 (i32.const 0))
 			(func (result i32)
 				(get_global 0)
 				(loop
@@ -925,6 +927,7 @@ mod tests {
 		name = return_from_func;
 		input = r#"
 		(module
+			(global i32 (i32.const 0))
 			(func (result i32)
 				(get_global 0)
 				(if
@@ -950,6 +953,7 @@ mod tests {
 		name = branch_from_if_not_else;
 		input = r#"
 		(module
+			(global i32 (i32.const 0))
 			(func (result i32)
 				(get_global 0)
 				(block
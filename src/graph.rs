@@ -38,6 +38,8 @@ pub enum Error {
 	Format(elements::Error),
 	/// Detached entry
 	DetachedEntry,
+	/// Reference which can't be encoded in the instruction
+	UnencodableReference,
 }
 
 /// Function origin (imported or internal).
@@ -55,7 +57,9 @@ pub type TableOrigin = ImportedOrDeclared;
 /// and the actual code. This part is the actual code.
 #[derive(Debug)]
 pub struct FuncBody {
+	/// Local variables, besides parameters.
 	pub locals: Vec<elements::Local>,
+	/// Instructions, including the trailing `end`.
 	pub code: Vec<Instruction>,
 }
 
@@ -77,8 +81,11 @@ pub struct Func {
 /// within the module - `origin` field is handling this.
 #[derive(Debug)]
 pub struct Global {
+	/// Type of the global value.
 	pub content: elements::ValueType,
+	/// Whether the global is mutable.
 	pub is_mut: bool,
+	/// Where this global comes from (imported or declared).
 	pub origin: GlobalOrigin,
 }
 
@@ -86,18 +93,38 @@ pub struct Global {
 ///
 /// Some instructions don't reference any entities within the WebAssembly module,
 /// while others do. This enum is for tracking references when required.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Instruction {
 	/// WebAssembly instruction that does not reference any module entities.
 	Plain(elements::Instruction),
 	/// Call instruction which references the function.
 	Call(EntryRef<Func>),
-	/// Indirect call instruction which references function type (function signature).
-	CallIndirect(EntryRef<elements::Type>, u8),
+	/// Indirect call instruction which references function type (function signature) and the table.
+	CallIndirect(EntryRef<elements::Type>, EntryRef<Table>),
 	/// get_global instruction which references the global.
 	GetGlobal(EntryRef<Global>),
 	/// set_global instruction which references the global.
 	SetGlobal(EntryRef<Global>),
+	/// Load or store instruction which references the memory.
+	///
+	/// parity-wasm can't encode memory index in loads and stores, so the memory must be the first one.
+	Memory(elements::Instruction, EntryRef<Memory>),
+	/// memory.size instruction which references the memory.
+	CurrentMemory(EntryRef<Memory>),
+	/// memory.grow instruction which references the memory.
+	GrowMemory(EntryRef<Memory>),
+	/// memory.init instruction which references the data segment.
+	#[cfg(feature = "bulk")]
+	MemoryInit(EntryRef<DataSegment>),
+	/// data.drop instruction which references the data segment.
+	#[cfg(feature = "bulk")]
+	DataDrop(EntryRef<DataSegment>),
+	/// table.init instruction which references the element segment.
+	#[cfg(feature = "bulk")]
+	TableInit(EntryRef<ElementSegment>),
+	/// elem.drop instruction which references the element segment.
+	#[cfg(feature = "bulk")]
+	ElemDrop(EntryRef<ElementSegment>),
 }
 
 /// Memory instance decriptor.
@@ -126,14 +153,14 @@ pub struct Table {
 
 /// Segment location.
 ///
-/// Reserved for future use. Currenty only `Default` variant is supported.
+/// Passive segments are only supported with the `bulk` feature.
 #[derive(Debug)]
 pub enum SegmentLocation {
-	/// Not used currently.
+	/// Passive segment, which is copied with `memory.init` or `table.init`.
 	Passive,
 	/// Default segment location with index `0`.
 	Default(Vec<Instruction>),
-	/// Not used currently.
+	/// Segment location in the memory or table with the index.
 	WithIndex(u32, Vec<Instruction>),
 }
 
@@ -197,42 +224,157 @@ pub struct Module {
 	pub start: Option<EntryRef<Func>>,
 	/// References to exported objects.
 	pub exports: Vec<Export>,
-	/// List of element segments.
-	pub elements: Vec<ElementSegment>,
-	/// List of data segments.
-	pub data: Vec<DataSegment>,
+	/// Refence-tracking list of element segments.
+	pub elements: RefList<ElementSegment>,
+	/// Refence-tracking list of data segments.
+	pub data: RefList<DataSegment>,
 	/// Other module functions that are not decoded or processed.
 	pub other: BTreeMap<usize, elements::Section>,
+	/// Kinds of the imports in the order of the source import section.
+	import_order: Vec<elements::ImportCountType>,
 }
 
 impl Module {
 
-	fn map_instructions(&self, instructions: &[elements::Instruction]) -> Vec<Instruction> {
+	pub(crate) fn map_instructions(&self, instructions: &[elements::Instruction]) -> Result<Vec<Instruction>, Error> {
 		use parity_wasm::elements::Instruction::*;
-		instructions.iter().map(|instruction|  match instruction {
-			Call(func_idx) => Instruction::Call(self.funcs.clone_ref(*func_idx as usize)),
-			CallIndirect(type_idx, arg2) =>
+		#[cfg(feature = "bulk")]
+		use parity_wasm::elements::BulkInstruction;
+		instructions.iter().map(|instruction| Ok(match instruction {
+			Call(func_idx) => Instruction::Call(entry(&self.funcs, *func_idx)?),
+			CallIndirect(type_idx, table_idx) =>
 				Instruction::CallIndirect(
-					self.types.clone_ref(*type_idx as usize),
-					*arg2,
+					entry(&self.types, *type_idx)?,
+					entry(&self.tables, *table_idx as u32)?,
 				),
+			CurrentMemory(memory_idx) =>
+				Instruction::CurrentMemory(entry(&self.memory, *memory_idx as u32)?),
+			GrowMemory(memory_idx) =>
+				Instruction::GrowMemory(entry(&self.memory, *memory_idx as u32)?),
+			memory_instruction if is_memory_access(memory_instruction) =>
+				Instruction::Memory(memory_instruction.clone(), entry(&self.memory, 0)?),
 			SetGlobal(global_idx) =>
-				Instruction::SetGlobal(self.globals.clone_ref(*global_idx as usize)),
+				Instruction::SetGlobal(entry(&self.globals, *global_idx)?),
 			GetGlobal(global_idx) =>
-				Instruction::GetGlobal(self.globals.clone_ref(*global_idx as usize)),
+				Instruction::GetGlobal(entry(&self.globals, *global_idx)?),
+			#[cfg(feature = "bulk")]
+			Bulk(BulkInstruction::MemoryInit(data_idx)) =>
+				Instruction::MemoryInit(entry(&self.data, *data_idx)?),
+			#[cfg(feature = "bulk")]
+			Bulk(BulkInstruction::MemoryDrop(data_idx)) =>
+				Instruction::DataDrop(entry(&self.data, *data_idx)?),
+			#[cfg(feature = "bulk")]
+			Bulk(BulkInstruction::TableInit(element_idx)) =>
+				Instruction::TableInit(entry(&self.elements, *element_idx)?),
+			#[cfg(feature = "bulk")]
+			Bulk(BulkInstruction::TableDrop(element_idx)) =>
+				Instruction::ElemDrop(entry(&self.elements, *element_idx)?),
 			other_instruction => Instruction::Plain(other_instruction.clone()),
-		}).collect()
+		})).collect()
 	}
 
-	fn generate_instructions(&self, instructions: &[Instruction]) -> Vec<elements::Instruction> {
+	pub(crate) fn generate_instructions(&self, instructions: &[Instruction]) -> Result<Vec<elements::Instruction>, Error> {
 		use parity_wasm::elements::Instruction::*;
-		instructions.iter().map(|instruction| match instruction {
-			Instruction::Call(func_ref) => Call(func_ref.order().expect("detached instruction!") as u32),
-			Instruction::CallIndirect(type_ref, arg2) => CallIndirect(type_ref.order().expect("detached instruction!") as u32, *arg2),
-			Instruction::SetGlobal(global_ref) => SetGlobal(global_ref.order().expect("detached instruction!") as u32),
-			Instruction::GetGlobal(global_ref) => GetGlobal(global_ref.order().expect("detached instruction!") as u32),
+		#[cfg(feature = "bulk")]
+		use parity_wasm::elements::BulkInstruction;
+		instructions.iter().map(|instruction| Ok(match instruction {
+			Instruction::Call(func_ref) => Call(index(func_ref)?),
+			Instruction::CallIndirect(type_ref, table_ref) => CallIndirect(index(type_ref)?, byte_index(table_ref)?),
+			Instruction::SetGlobal(global_ref) => SetGlobal(index(global_ref)?),
+			Instruction::GetGlobal(global_ref) => GetGlobal(index(global_ref)?),
+			Instruction::Memory(plain, memory_ref) => {
+				// Memory argument of loads and stores has no memory index in parity-wasm
+				if index(memory_ref)? != 0 {
+					return Err(Error::UnencodableReference);
+				}
+				plain.clone()
+			},
+			Instruction::CurrentMemory(memory_ref) => CurrentMemory(byte_index(memory_ref)?),
+			Instruction::GrowMemory(memory_ref) => GrowMemory(byte_index(memory_ref)?),
+			#[cfg(feature = "bulk")]
+			Instruction::MemoryInit(data_ref) => Bulk(BulkInstruction::MemoryInit(index(data_ref)?)),
+			#[cfg(feature = "bulk")]
+			Instruction::DataDrop(data_ref) => Bulk(BulkInstruction::MemoryDrop(index(data_ref)?)),
+			#[cfg(feature = "bulk")]
+			Instruction::TableInit(element_ref) => Bulk(BulkInstruction::TableInit(index(element_ref)?)),
+			#[cfg(feature = "bulk")]
+			Instruction::ElemDrop(element_ref) => Bulk(BulkInstruction::TableDrop(index(element_ref)?)),
 			Instruction::Plain(plain) => plain.clone(),
-		}).collect()
+		})).collect()
+	}
+
+	/// Reference to the function type with the signature.
+	///
+	/// The type is added to the end of the types, unless the module already has it.
+	pub fn add_type(&mut self, signature: elements::FunctionType) -> EntryRef<elements::Type> {
+		let func_type = elements::Type::Function(signature);
+		match self.types.iter().position(|existing| **existing.read() == func_type) {
+			Some(index) => self.types.clone_ref(index),
+			None => self.types.push(func_type),
+		}
+	}
+
+	/// Add imported function after all other imported functions.
+	///
+	/// Declared functions are shifted, references to them are updated.
+	pub fn add_import_func(&mut self, module: &str, field: &str, signature: elements::FunctionType) -> EntryRef<Func> {
+		let type_ref = self.add_type(signature);
+		let mut tx = self.funcs.begin_insert_not_until(
+			|func| matches!(func.origin, ImportedOrDeclared::Imported(..))
+		);
+		let func_ref = tx.push(Func {
+			type_ref,
+			origin: ImportedOrDeclared::Imported(module.to_owned(), field.to_owned()),
+		});
+		tx.done();
+		func_ref
+	}
+
+	/// Add declared function to the end of the functions.
+	pub fn add_func(
+		&mut self,
+		signature: elements::FunctionType,
+		locals: Vec<elements::Local>,
+		code: Vec<Instruction>,
+	) -> EntryRef<Func> {
+		let type_ref = self.add_type(signature);
+		self.funcs.push(Func {
+			type_ref,
+			origin: ImportedOrDeclared::Declared(FuncBody { locals, code }),
+		})
+	}
+
+	/// Add imported global after all other imported globals.
+	///
+	/// Declared globals are shifted, references to them are updated.
+	pub fn add_import_global(
+		&mut self,
+		module: &str,
+		field: &str,
+		content: elements::ValueType,
+		is_mut: bool,
+	) -> EntryRef<Global> {
+		let mut tx = self.globals.begin_insert_not_until(
+			|global| matches!(global.origin, ImportedOrDeclared::Imported(..))
+		);
+		let global_ref = tx.push(Global {
+			content,
+			is_mut,
+			origin: ImportedOrDeclared::Imported(module.to_owned(), field.to_owned()),
+		});
+		tx.done();
+		global_ref
+	}
+
+	/// Add declared global to the end of the globals.
+	///
+	/// `init` is the initializer expression, including the trailing `end`.
+	pub fn add_global(&mut self, content: elements::ValueType, is_mut: bool, init: Vec<Instruction>) -> EntryRef<Global> {
+		self.globals.push(Global {
+			content,
+			is_mut,
+			origin: ImportedOrDeclared::Declared(init),
+		})
 	}
 
 	/// Initialize module from parity-wasm `Module`.
@@ -250,6 +392,7 @@ impl Module {
 					for entry in import_section.entries() {
 						match *entry.external() {
 							elements::External::Function(f) => {
+								res.import_order.push(elements::ImportCountType::Function);
 								res.funcs.push(Func {
 									type_ref: res.types.get(f as usize).ok_or(Error::InconsistentSource)?.clone(),
 									origin: entry.into(),
@@ -257,12 +400,14 @@ impl Module {
 								imported_functions += 1;
 							},
 							elements::External::Memory(m) => {
+								res.import_order.push(elements::ImportCountType::Memory);
 								res.memory.push(Memory {
 									limits: *m.limits(),
 									origin: entry.into(),
 								});
 							},
 							elements::External::Global(g) => {
+								res.import_order.push(elements::ImportCountType::Global);
 								res.globals.push(Global {
 									content: g.content_type(),
 									is_mut: g.is_mutable(),
@@ -270,6 +415,7 @@ impl Module {
 								});
 							},
 							elements::External::Table(t) => {
+								res.import_order.push(elements::ImportCountType::Table);
 								res.tables.push(Table {
 									limits: *t.limits(),
 									origin: entry.into(),
@@ -309,7 +455,7 @@ impl Module {
 				},
 				elements::Section::Global(global_section) => {
 					for g in global_section.entries() {
-						let init_code = res.map_instructions(g.init_expr().code());
+						let init_code = res.map_instructions(g.init_expr().code())?;
 						res.globals.push(Global {
 							content: g.global_type().content_type(),
 							is_mut: g.global_type().is_mutable(),
@@ -321,16 +467,16 @@ impl Module {
 					for e in export_section.entries() {
 						let local = match e.internal() {
 							elements::Internal::Function(func_idx) => {
-								ExportLocal::Func(res.funcs.clone_ref(*func_idx as usize))
+								ExportLocal::Func(entry(&res.funcs, *func_idx)?)
 							},
 							elements::Internal::Global(global_idx) => {
-								ExportLocal::Global(res.globals.clone_ref(*global_idx as usize))
+								ExportLocal::Global(entry(&res.globals, *global_idx)?)
 							},
 							elements::Internal::Memory(mem_idx) => {
-								ExportLocal::Memory(res.memory.clone_ref(*mem_idx as usize))
+								ExportLocal::Memory(entry(&res.memory, *mem_idx)?)
 							},
 							elements::Internal::Table(table_idx) => {
-								ExportLocal::Table(res.tables.clone_ref(*table_idx as usize))
+								ExportLocal::Table(entry(&res.tables, *table_idx)?)
 							},
 						};
 
@@ -338,31 +484,16 @@ impl Module {
 					}
 				},
 				elements::Section::Start(start_func) => {
-					res.start = Some(res.funcs.clone_ref(*start_func as usize));
+					res.start = Some(entry(&res.funcs, *start_func)?);
 				},
 				elements::Section::Element(element_section) => {
 					for element_segment in element_section.entries() {
-
-						// let location = if element_segment.passive() {
-						// 	SegmentLocation::Passive
-						// } else if element_segment.index() == 0 {
-						// 	SegmentLocation::Default(Vec::new())
-						// } else {
-						// 	SegmentLocation::WithIndex(element_segment.index(), Vec::new())
-						// };
-
-						// TODO: update parity-wasm and uncomment the above instead
-						let init_expr = element_segment
-							.offset()
-							.as_ref()
-							.expect("parity-wasm is compiled without bulk-memory operations")
-							.code();
-						let location = SegmentLocation::Default(res.map_instructions(init_expr));
+						let location = res.map_location(element_segment.index(), element_segment.offset())?;
 
 						let funcs_map = element_segment
 							.members().iter()
-							.map(|idx| res.funcs.clone_ref(*idx as usize))
-							.collect::<Vec<EntryRef<Func>>>();
+							.map(|idx| entry(&res.funcs, *idx))
+							.collect::<Result<Vec<EntryRef<Func>>, Error>>()?;
 
 						res.elements.push(ElementSegment {
 							value: funcs_map,
@@ -370,29 +501,12 @@ impl Module {
 						});
 					}
 				},
-				elements::Section::Code(code_section) => {
-					for (idx, func_body) in code_section.bodies().iter().enumerate() {
-						let code = res.map_instructions(func_body.code().elements());
-						let mut func = res.funcs.get_ref(imported_functions + idx).write();
-						match &mut func.origin {
-							ImportedOrDeclared::Declared(body) => {
-								body.code = code;
-								body.locals = func_body.locals().to_vec();
-							},
-							_ => { return Err(Error::InconsistentSource); }
-						}
-					}
+				elements::Section::Code(_) => {
+					// Code is mapped when all segments are known, since the data section comes after
 				},
 				elements::Section::Data(data_section) => {
 					for data_segment in data_section.entries() {
-						// TODO: update parity-wasm and use the same logic as in
-						// commented element segment branch
-						let init_expr = data_segment
-							.offset()
-							.as_ref()
-							.expect("parity-wasm is compiled without bulk-memory operations")
-							.code();
-						let location = SegmentLocation::Default(res.map_instructions(init_expr));
+						let location = res.map_location(data_segment.index(), data_segment.offset())?;
 
 						res.data.push(DataSegment {
 							value: data_segment.value().to_vec(),
@@ -400,15 +514,72 @@ impl Module {
 						});
 					}
 				},
+				elements::Section::DataCount(_) => {
+					// Regenerated from the data segments
+				},
 				_ => {
 					res.other.insert(idx, section.clone());
 				}
 			}
 		}
 
+		if let Some(code_section) = module.code_section() {
+			for (idx, func_body) in code_section.bodies().iter().enumerate() {
+				let code = res.map_instructions(func_body.code().elements())?;
+				let func = res.funcs.get(imported_functions + idx).ok_or(Error::InconsistentSource)?;
+				let mut func = func.write();
+				match &mut func.origin {
+					ImportedOrDeclared::Declared(body) => {
+						body.code = code;
+						body.locals = func_body.locals().to_vec();
+					},
+					_ => { return Err(Error::InconsistentSource); }
+				}
+			}
+		}
+
 		Ok(res)
 	}
 
+	/// Location of the segment with the `index` and `offset`, which is `None` for passive segments.
+	fn map_location(&self, index: u32, offset: &Option<elements::InitExpr>) -> Result<SegmentLocation, Error> {
+		Ok(match offset {
+			None => SegmentLocation::Passive,
+			Some(offset) if index == 0 => SegmentLocation::Default(self.map_instructions(offset.code())?),
+			Some(offset) => SegmentLocation::WithIndex(index, self.map_instructions(offset.code())?),
+		})
+	}
+
+	/// Index and offset of the segment at the location, offset is `None` for passive segments.
+	fn generate_location(&self, location: &SegmentLocation) -> Result<(u32, Option<elements::InitExpr>), Error> {
+		match location {
+			#[cfg(feature = "bulk")]
+			SegmentLocation::Passive => Ok((0, None)),
+			#[cfg(not(feature = "bulk"))]
+			SegmentLocation::Passive => Err(Error::InconsistentSource),
+			SegmentLocation::Default(offset) =>
+				Ok((0, Some(elements::InitExpr::new(self.generate_instructions(&offset[..])?)))),
+			SegmentLocation::WithIndex(index, offset) =>
+				Ok((*index, Some(elements::InitExpr::new(self.generate_instructions(&offset[..])?)))),
+		}
+	}
+
+	/// Whether any function references data segments, so the module needs the data count section.
+	fn uses_data_count(&self) -> bool {
+		#[cfg(feature = "bulk")]
+		{
+			self.funcs.iter().any(|func| match &func.read().origin {
+				ImportedOrDeclared::Declared(body) => body.code.iter()
+					.any(|instruction| matches!(instruction, Instruction::MemoryInit(_) | Instruction::DataDrop(_))),
+				ImportedOrDeclared::Imported(..) => false,
+			})
+		}
+		#[cfg(not(feature = "bulk"))]
+		{
+			false
+		}
+	}
+
 	/// Generate raw format representation.
 	pub fn generate(&self) -> Result<elements::Module, Error> {
 		use self::ImportedOrDeclared::*;
@@ -438,11 +609,14 @@ impl Module {
 		let mut import_section = elements::ImportSection::default();
 
 		let add = {
-			let imports = import_section.entries_mut();
+			let mut imported_funcs = Vec::new();
+			let mut imported_globals = Vec::new();
+			let mut imported_memory = Vec::new();
+			let mut imported_tables = Vec::new();
 			for func in self.funcs.iter() {
 				match &func.read().origin {
 					Imported(module, field) => {
-						imports.push(
+						imported_funcs.push(
 							elements::ImportEntry::new(
 								module.to_owned(),
 								field.to_owned(),
@@ -459,7 +633,7 @@ impl Module {
 			for global in self.globals.iter() {
 				match &global.read().origin {
 					Imported(module, field) => {
-						imports.push(
+						imported_globals.push(
 							elements::ImportEntry::new(
 								module.to_owned(),
 								field.to_owned(),
@@ -479,7 +653,7 @@ impl Module {
 			for memory in self.memory.iter() {
 				match &memory.read().origin {
 					Imported(module, field) => {
-						imports.push(
+						imported_memory.push(
 							elements::ImportEntry::new(
 								module.to_owned(),
 								field.to_owned(),
@@ -499,7 +673,7 @@ impl Module {
 			for table in self.tables.iter() {
 				match &table.read().origin {
 					Imported(module, field) => {
-						imports.push(
+						imported_tables.push(
 							elements::ImportEntry::new(
 								module.to_owned(),
 								field.to_owned(),
@@ -515,6 +689,21 @@ impl Module {
 					_ => continue,
 				}
 			}
+
+			// Imports of the source module keep their order, added ones follow grouped by kind
+			let imports = import_section.entries_mut();
+			let (mut funcs, mut globals, mut memory, mut tables) =
+				(imported_funcs.into_iter(), imported_globals.into_iter(), imported_memory.into_iter(), imported_tables.into_iter());
+			for kind in self.import_order.iter() {
+				let entry = match kind {
+					elements::ImportCountType::Function => funcs.next(),
+					elements::ImportCountType::Global => globals.next(),
+					elements::ImportCountType::Memory => memory.next(),
+					elements::ImportCountType::Table => tables.next(),
+				};
+				imports.extend(entry);
+			}
+			imports.extend(funcs.chain(globals).chain(memory).chain(tables));
 			!imports.is_empty()
 		};
 
@@ -606,7 +795,7 @@ impl Module {
 						Declared(init_code) => {
 							globals.push(elements::GlobalEntry::new(
 								elements::GlobalType::new(global.read().content, global.read().is_mut),
-								elements::InitExpr::new(self.generate_instructions(&init_code[..])),
+								elements::InitExpr::new(self.generate_instructions(&init_code[..])?),
 							));
 						},
 						_ => continue,
@@ -664,23 +853,18 @@ impl Module {
 				let element_segments = element_section.entries_mut();
 
 				for element in self.elements.iter() {
-					match &element.location {
-						SegmentLocation::Default(offset_expr) => {
-							let mut elements_map = Vec::new();
-							for f in element.value.iter() {
-								elements_map.push(f.order().ok_or(Error::DetachedEntry)? as u32);
-							}
-
-							element_segments.push(
-								elements::ElementSegment::new(
-									0,
-									Some(elements::InitExpr::new(self.generate_instructions(&offset_expr[..]))),
-									elements_map,
-								)
-							);
-						},
-						_ => unreachable!("Other segment location types are never added"),
+					let element = element.read();
+					let mut elements_map = Vec::new();
+					for f in element.value.iter() {
+						elements_map.push(f.order().ok_or(Error::DetachedEntry)? as u32);
 					}
+
+					let (index, offset) = self.generate_location(&element.location)?;
+					#[allow(unused_mut)]
+					let mut segment = elements::ElementSegment::new(index, offset, elements_map);
+					#[cfg(feature = "bulk")]
+					segment.set_passive(matches!(element.location, SegmentLocation::Passive));
+					element_segments.push(segment);
 				}
 			}
 
@@ -690,6 +874,14 @@ impl Module {
 			custom_round(&self.other, &mut idx, &mut sections);
 		}
 
+		if self.uses_data_count() {
+			// DATA COUNT SECTION (12)
+			sections.push(elements::Section::DataCount(self.data.len() as u32));
+			idx += 1;
+
+			custom_round(&self.other, &mut idx, &mut sections);
+		}
+
 		if !self.funcs.is_empty() {
 			// CODE SECTION (10)
 			let mut code_section = elements::CodeSection::default();
@@ -701,7 +893,7 @@ impl Module {
 						Declared(body) => {
 							funcs.push(elements::FuncBody::new(
 								body.locals.clone(),
-								elements::Instructions::new(self.generate_instructions(&body.code[..])),
+								elements::Instructions::new(self.generate_instructions(&body.code[..])?),
 							));
 						},
 						_ => continue,
@@ -722,18 +914,13 @@ impl Module {
 				let data_segments = data_section.entries_mut();
 
 				for data_entry in self.data.iter() {
-					match &data_entry.location {
-						SegmentLocation::Default(offset_expr) => {
-							data_segments.push(
-								elements::DataSegment::new(
-									0,
-									Some(elements::InitExpr::new(self.generate_instructions(&offset_expr[..]))),
-									data_entry.value.clone(),
-								)
-							);
-						},
-						_ => unreachable!("Other segment location types are never added"),
-					}
+					let data_entry = data_entry.read();
+					let (index, offset) = self.generate_location(&data_entry.location)?;
+					#[allow(unused_mut)]
+					let mut segment = elements::DataSegment::new(index, offset, data_entry.value.clone());
+					#[cfg(feature = "bulk")]
+					segment.set_passive(matches!(data_entry.location, SegmentLocation::Passive));
+					data_segments.push(segment);
 				}
 			}
 
@@ -747,6 +934,40 @@ impl Module {
 	}
 }
 
+/// Index of the referenced entry.
+fn index<T>(entry: &EntryRef<T>) -> Result<u32, Error> {
+	entry.order().map(|order| order as u32).ok_or(Error::DetachedEntry)
+}
+
+/// Index of the referenced entry, which is encoded as a single byte.
+fn byte_index<T>(entry: &EntryRef<T>) -> Result<u8, Error> {
+	let order = entry.order().ok_or(Error::DetachedEntry)?;
+	if order > u8::MAX as usize {
+		return Err(Error::UnencodableReference);
+	}
+	Ok(order as u8)
+}
+
+/// Whether the instruction loads from or stores to the memory.
+fn is_memory_access(instruction: &elements::Instruction) -> bool {
+	use parity_wasm::elements::Instruction::*;
+	matches!(
+		instruction,
+		I32Load(..) | I64Load(..) | F32Load(..) | F64Load(..)
+			| I32Load8S(..) | I32Load8U(..) | I32Load16S(..) | I32Load16U(..)
+			| I64Load8S(..) | I64Load8U(..) | I64Load16S(..) | I64Load16U(..)
+			| I64Load32S(..) | I64Load32U(..)
+			| I32Store(..) | I64Store(..) | F32Store(..) | F64Store(..)
+			| I32Store8(..) | I32Store16(..)
+			| I64Store8(..) | I64Store16(..) | I64Store32(..)
+	)
+}
+
+/// Entry with the index, which the module must have.
+fn entry<T>(list: &RefList<T>, index: u32) -> Result<EntryRef<T>, Error> {
+	list.get(index as usize).ok_or(Error::InconsistentSource)
+}
+
 fn custom_round(
 	map: &BTreeMap<usize, elements::Section>,
 	idx: &mut usize,
@@ -807,6 +1028,23 @@ mod tests {
 		assert_eq!(sample.funcs.get_ref(0).link_count(), 1);
 	}
 
+	#[test]
+	fn import_order() {
+		let mut sample = load_sample(indoc!(r#"
+			(module
+				(import "env" "memory" (memory 1))
+				(import "env" "print" (func (param i32)))
+				(import "env" "base" (global i32)))"#
+		));
+		sample.add_import_func("env", "gas", elements::FunctionType::new(vec![elements::ValueType::I32], vec![]));
+
+		let module = sample.generate().expect("Failed to generate module");
+		let imports = module.import_section().expect("Import section to exist").entries().iter()
+			.map(|entry| entry.field())
+			.collect::<Vec<_>>();
+		assert_eq!(imports, vec!["memory", "print", "base", "gas"]);
+	}
+
 	#[test]
 	fn table() {
 		let mut sample = load_sample(indoc!(r#"
@@ -831,7 +1069,7 @@ mod tests {
 		));
 
 		{
-			let element_func = &sample.elements[0].value[1];
+			let element_func = sample.elements.get_ref(0).read().value[1].clone();
 			let rfunc = element_func.read();
 			let rtype = &**rfunc.type_ref.read();
 			let elements::Type::Function(ftype) = rtype;
@@ -845,7 +1083,7 @@ mod tests {
 		sample.funcs.begin_delete().push(0).done();
 
 		{
-			let element_func = &sample.elements[0].value[1];
+			let element_func = sample.elements.get_ref(0).read().value[1].clone();
 			let rfunc = element_func.read();
 			let rtype = &**rfunc.type_ref.read();
 			let elements::Type::Function(ftype) = rtype;
@@ -975,4 +1213,169 @@ mod tests {
 			"Call should be recalculated to 1"
 		);
 	}
+
+	#[test]
+	fn builder() {
+		use parity_wasm::elements::{FunctionType, Instruction::*, ValueType};
+		use super::Instruction;
+
+		let mut sample = load_sample(indoc!(r#"
+			(module
+				(import "env" "foo" (func (param i32)))
+				(global (mut i32) (i32.const 0))
+				(func (export "main")
+					get_global 0
+					call 0
+				)
+			)"#
+		));
+
+		let main = sample.funcs.clone_ref(1);
+		let bar = sample.add_import_func("env", "bar", FunctionType::new(vec![ValueType::I32], vec![]));
+		let base = sample.add_import_global("env", "base", ValueType::I32, false);
+		let counter = sample.add_global(ValueType::I32, true, vec![
+			Instruction::GetGlobal(base.clone()),
+			Instruction::Plain(End),
+		]);
+		let helper = sample.add_func(FunctionType::default(), Vec::new(), vec![
+			Instruction::GetGlobal(counter.clone()),
+			Instruction::Call(bar.clone()),
+			Instruction::Plain(End),
+		]);
+
+		assert_eq!(bar.order(), Some(1));
+		assert_eq!(main.order(), Some(2));
+		assert_eq!(helper.order(), Some(3));
+		assert_eq!(base.order(), Some(0));
+		assert_eq!(counter.order(), Some(2));
+		// Both functions have the same signature, the type is shared
+		assert_eq!(sample.types.len(), 2);
+
+		validate_sample(&sample);
+	}
+
+	#[test]
+	fn segment_index() {
+		use parity_wasm::elements::{Instruction::*, InitExpr};
+
+		let module = elements::Module::new(vec![
+			elements::Section::Data(elements::DataSection::with_entries(vec![
+				elements::DataSegment::new(0, Some(InitExpr::new(vec![I32Const(0), End])), vec![1]),
+				elements::DataSegment::new(1, Some(InitExpr::new(vec![I32Const(8), End])), vec![2]),
+			])),
+		]);
+
+		let sample = super::Module::from_elements(&module).expect("Failed to make representation");
+		assert!(matches!(sample.data.get_ref(0).read().location, super::SegmentLocation::Default(_)));
+		assert!(matches!(sample.data.get_ref(1).read().location, super::SegmentLocation::WithIndex(1, _)));
+
+		let module = sample.generate().expect("Failed to generate module");
+		let indices = module.data_section().expect("Data section to stay").entries().iter()
+			.map(|segment| segment.index())
+			.collect::<Vec<_>>();
+		assert_eq!(indices, vec![0, 1]);
+	}
+
+	#[test]
+	fn memory_and_table_refs() {
+		let mut sample = load_sample(indoc!(r#"
+			(module
+				(type (;0;) (func))
+				(table 1 anyfunc)
+				(memory 1)
+				(func (type 0)
+					i32.const 0
+					i32.const 0
+					i32.load
+					i32.store
+					memory.size
+					memory.grow
+					drop
+					i32.const 0
+					call_indirect (type 0)
+				)
+			)"#
+		));
+
+		match &sample.funcs.get_ref(0).read().origin {
+			super::ImportedOrDeclared::Declared(body) => {
+				assert!(matches!(&body.code[2], super::Instruction::Memory(_, memory) if memory.order() == Some(0)));
+				assert!(matches!(&body.code[3], super::Instruction::Memory(_, memory) if memory.order() == Some(0)));
+				assert!(matches!(&body.code[4], super::Instruction::CurrentMemory(memory) if memory.order() == Some(0)));
+				assert!(matches!(&body.code[5], super::Instruction::GrowMemory(memory) if memory.order() == Some(0)));
+				assert!(matches!(&body.code[8], super::Instruction::CallIndirect(_, table) if table.order() == Some(0)));
+			},
+			_ => panic!("func #0 should be declared!"),
+		}
+		validate_sample(&sample);
+
+		sample.memory.begin_delete().push(0).done();
+		assert!(matches!(sample.generate(), Err(super::Error::DetachedEntry)));
+	}
+
+	#[cfg(feature = "bulk")]
+	#[test]
+	fn bulk_segments() {
+		use parity_wasm::elements::{BulkInstruction::*, Instruction::*, InitExpr};
+
+		let mut passive_data = elements::DataSegment::new(0, None, vec![1, 2, 3]);
+		passive_data.set_passive(true);
+		let mut passive_element = elements::ElementSegment::new(0, None, vec![0]);
+		passive_element.set_passive(true);
+		let module = elements::Module::new(vec![
+			elements::Section::Type(elements::TypeSection::with_types(vec![
+				elements::Type::Function(elements::FunctionType::default()),
+			])),
+			elements::Section::Function(elements::FunctionSection::with_entries(vec![elements::Func::new(0)])),
+			elements::Section::Table(elements::TableSection::with_entries(vec![elements::TableType::new(1, None)])),
+			elements::Section::Memory(elements::MemorySection::with_entries(vec![elements::MemoryType::new(1, None)])),
+			elements::Section::Element(elements::ElementSection::with_entries(vec![passive_element])),
+			elements::Section::DataCount(1),
+			elements::Section::Code(elements::CodeSection::with_bodies(vec![
+				elements::FuncBody::new(vec![], elements::Instructions::new(vec![
+					I32Const(0), I32Const(0), I32Const(3), Bulk(MemoryInit(0)),
+					Bulk(MemoryDrop(0)),
+					I32Const(0), I32Const(0), I32Const(1), Bulk(TableInit(0)),
+					Bulk(TableDrop(0)),
+					End,
+				])),
+			])),
+			elements::Section::Data(elements::DataSection::with_entries(vec![passive_data])),
+		]);
+
+		let mut sample = super::Module::from_elements(&module).expect("Failed to make representation");
+		assert!(matches!(sample.data.get_ref(0).read().location, super::SegmentLocation::Passive));
+		assert!(matches!(sample.elements.get_ref(0).read().location, super::SegmentLocation::Passive));
+
+		// Segments inserted before shift references in the code
+		let mut tx = sample.data.begin_insert(0);
+		tx.push(super::DataSegment {
+			location: super::SegmentLocation::Default(vec![
+				super::Instruction::Plain(I32Const(16)),
+				super::Instruction::Plain(End),
+			]),
+			value: vec![4],
+		});
+		tx.done();
+
+		let module = sample.generate().expect("Failed to generate module");
+		assert!(module.sections().iter().any(|section| matches!(section, elements::Section::DataCount(2))));
+		let data = module.data_section().expect("Data section to stay").entries();
+		assert_eq!(data[0].offset(), &Some(InitExpr::new(vec![I32Const(16), End])));
+		assert!(data[1].passive());
+		assert!(module.elements_section().expect("Element section to stay").entries()[0].passive());
+		assert_eq!(
+			module.code_section().expect("Code section to stay").bodies()[0].code().elements(),
+			&[
+				I32Const(0), I32Const(0), I32Const(3), Bulk(MemoryInit(1)),
+				Bulk(MemoryDrop(1)),
+				I32Const(0), I32Const(0), I32Const(1), Bulk(TableInit(0)),
+				Bulk(TableDrop(0)),
+				End,
+			][..]
+		);
+
+		let binary = elements::serialize(module).expect("Failed to serialize");
+		elements::deserialize_buffer::<elements::Module>(&binary).expect("Failed to deserialize");
+	}
 }
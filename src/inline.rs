@@ -0,0 +1,389 @@
+//! Inlining of small and single-use functions.
+//!
+//! Every call costs gas and a stack limiter preamble, so trampolines and helpers called
+//! from a single place are cheaper to execute when their code is pasted into the caller.
+//!
+//! The call site `call $f` is replaced with:
+//!
+//! - `set_local` of fresh caller locals for every argument of `$f`, in reverse order;
+//! - zeroing of fresh caller locals for every local of `$f`, since the call site can be
+//!   executed several times;
+//! - `block` with result type of `$f` containing the body of `$f`, where local indices
+//!   are shifted to the fresh locals and `return` becomes a branch out of the block.
+
+use crate::std::collections::BTreeSet;
+use crate::std::mem;
+use crate::std::string::String;
+use crate::std::vec::Vec;
+
+use log::trace;
+use parity_wasm::elements::{self, BlockType, ValueType};
+
+use crate::graph::{self, ExportLocal, ImportedOrDeclared, Instruction};
+use crate::pass::{self, Pass, Pipeline};
+use crate::ref_list::EntryRef;
+
+/// Inline functions with body of at most `max_size` instructions and functions
+/// which are called exactly once.
+///
+/// Functions which are exported, used as the start function or placed in a table are
+/// inlined only if they are small enough and are never removed. Other functions are removed
+/// once all their calls are inlined. Recursive functions are never inlined.
+///
+/// Names of the removed functions are dropped, names of the rest follow their functions.
+pub fn inline_functions(module: elements::Module, max_size: usize) -> Result<elements::Module, pass::Error> {
+	Pipeline::new()
+		.with_pass(Inline::new(max_size))
+		.run(&module)
+}
+
+/// Pass inlining functions, see `inline_functions`.
+pub struct Inline {
+	max_size: usize,
+}
+
+impl Inline {
+	/// New pass inlining functions with body of at most `max_size` instructions.
+	pub fn new(max_size: usize) -> Self {
+		Inline { max_size }
+	}
+}
+
+impl Pass for Inline {
+	fn name(&self) -> &str {
+		"inline"
+	}
+
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		let inlined = inline(module, self.max_size);
+		trace!("Inlined {} call sites", inlined);
+		Ok(())
+	}
+}
+
+/// Returns number of inlined call sites.
+fn inline(module: &mut graph::Module, max_size: usize) -> usize {
+	let mut kept = BTreeSet::new();
+	for export in module.exports.iter() {
+		if let ExportLocal::Func(func_ref) = &export.local {
+			kept.insert(func_ref.order().expect("Exported function is in the list"));
+		}
+	}
+	if let Some(start) = &module.start {
+		kept.insert(start.order().expect("Start function is in the list"));
+	}
+	for segment in module.elements.iter() {
+		for func_ref in segment.read().value.iter() {
+			kept.insert(func_ref.order().expect("Table function is in the list"));
+		}
+	}
+
+	let mut inlined = 0;
+	let mut removed = Vec::new();
+	for callee_index in 0..module.funcs.len() {
+		let callee = match Callee::new(module, callee_index) {
+			Some(callee) => callee,
+			None => continue,
+		};
+
+		let call_sites = module.funcs.iter()
+			.map(|func| match &func.read().origin {
+				ImportedOrDeclared::Declared(body) => body.code.iter()
+					.filter(|instruction| is_call_to(instruction, callee_index))
+					.count(),
+				ImportedOrDeclared::Imported(..) => 0,
+			})
+			.sum::<usize>();
+		let single_use = call_sites == 1 && !kept.contains(&callee_index);
+		if call_sites == 0 || (callee.size > max_size && !single_use) {
+			continue;
+		}
+
+		for caller_index in 0..module.funcs.len() {
+			if caller_index != callee_index {
+				inlined += callee.inline_into(module.funcs.get_ref(caller_index));
+			}
+		}
+		trace!("Inlined function {} at {} call sites", callee_index, call_sites);
+
+		if !kept.contains(&callee_index) {
+			removed.push(callee_index);
+		}
+	}
+
+	if !removed.is_empty() {
+		removed.into_iter()
+			.fold(module.funcs.begin_delete(), |tx, index| tx.push(index))
+			.done();
+	}
+
+	inlined
+}
+
+fn is_call_to(instruction: &Instruction, func_index: usize) -> bool {
+	match instruction {
+		Instruction::Call(func_ref) => func_ref.order() == Some(func_index),
+		_ => false,
+	}
+}
+
+fn zero(value_type: ValueType) -> elements::Instruction {
+	match value_type {
+		ValueType::I32 => elements::Instruction::I32Const(0),
+		ValueType::I64 => elements::Instruction::I64Const(0),
+		ValueType::F32 => elements::Instruction::F32Const(0),
+		ValueType::F64 => elements::Instruction::F64Const(0),
+	}
+}
+
+fn params_count(func: &graph::Func) -> u32 {
+	match **func.type_ref.read() {
+		elements::Type::Function(ref func_type) => func_type.params().len() as u32,
+	}
+}
+
+/// Everything needed to paste the function into call sites.
+struct Callee {
+	index: usize,
+	size: usize,
+	params: Vec<ValueType>,
+	locals: Vec<elements::Local>,
+	result: BlockType,
+	code: Vec<Instruction>,
+}
+
+impl Callee {
+	/// Returns `None` for imported and recursive functions.
+	fn new(module: &graph::Module, index: usize) -> Option<Self> {
+		let func = module.funcs.get_ref(index).read();
+		let body = match &func.origin {
+			ImportedOrDeclared::Declared(body) => body,
+			ImportedOrDeclared::Imported(..) => return None,
+		};
+		if body.code.iter().any(|instruction| is_call_to(instruction, index)) {
+			return None;
+		}
+
+		let elements::Type::Function(func_type) = &**func.type_ref.read();
+		Some(Callee {
+			index,
+			// Trailing `end` does not count
+			size: body.code.len().saturating_sub(1),
+			params: func_type.params().to_vec(),
+			locals: body.locals.clone(),
+			result: match func_type.results().first() {
+				Some(value_type) => BlockType::Value(*value_type),
+				None => BlockType::NoResult,
+			},
+			code: body.code.clone(),
+		})
+	}
+
+	/// Inline all calls in the function, returns number of inlined call sites.
+	fn inline_into(&self, caller: &EntryRef<graph::Func>) -> usize {
+		let (params, mut locals, code) = {
+			let mut caller = caller.write();
+			let params = params_count(&caller);
+			match &mut caller.origin {
+				ImportedOrDeclared::Declared(body) => (params, mem::take(&mut body.locals), mem::take(&mut body.code)),
+				ImportedOrDeclared::Imported(..) => return 0,
+			}
+		};
+
+		let mut inlined = 0;
+		let mut new_code = Vec::with_capacity(code.len());
+		for instruction in code {
+			if !is_call_to(&instruction, self.index) {
+				new_code.push(instruction);
+				continue;
+			}
+
+			let base = params + locals.iter().map(|group| group.count()).sum::<u32>();
+			self.paste(base, &mut new_code);
+			locals.extend(self.params.iter().map(|value_type| elements::Local::new(1, *value_type)));
+			locals.extend(self.locals.iter().cloned());
+			inlined += 1;
+		}
+
+		let mut caller = caller.write();
+		if let ImportedOrDeclared::Declared(body) = &mut caller.origin {
+			body.locals = locals;
+			body.code = new_code;
+		}
+		inlined
+	}
+
+	/// Paste the body, using caller locals starting from `base` for own locals.
+	fn paste(&self, base: u32, code: &mut Vec<Instruction>) {
+		use parity_wasm::elements::Instruction::*;
+
+		for index in (0..self.params.len() as u32).rev() {
+			code.push(Instruction::Plain(SetLocal(base + index)));
+		}
+		let mut index = base + self.params.len() as u32;
+		for group in self.locals.iter() {
+			for _ in 0..group.count() {
+				code.push(Instruction::Plain(zero(group.value_type())));
+				code.push(Instruction::Plain(SetLocal(index)));
+				index += 1;
+			}
+		}
+
+		code.push(Instruction::Plain(Block(self.result)));
+		let mut depth = 0u32;
+		for instruction in self.code.iter() {
+			let instruction = match instruction {
+				Instruction::Plain(plain) => Instruction::Plain(match plain {
+					Block(_) | Loop(_) | If(_) => { depth += 1; plain.clone() },
+					// Trailing `end` of the body closes the block
+					End => { depth = depth.saturating_sub(1); End },
+					Return => Br(depth),
+					GetLocal(local) => GetLocal(base + local),
+					SetLocal(local) => SetLocal(base + local),
+					TeeLocal(local) => TeeLocal(base + local),
+					other => other.clone(),
+				}),
+				other => other.clone(),
+			};
+			code.push(instruction);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements;
+	use super::inline_functions;
+
+	fn load_sample(wat: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(wat).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	fn inlined(module: elements::Module, max_size: usize) -> elements::Module {
+		let module = inline_functions(module, max_size).expect("Inlining to succeed");
+		let binary = elements::serialize(module.clone()).expect("Failed to serialize");
+		wabt::Module::read_binary(&binary, &Default::default())
+			.expect("Wabt failed to read final binary")
+			.validate()
+			.expect("Invalid module");
+		module
+	}
+
+	fn code(module: &elements::Module, index: usize) -> Vec<elements::Instruction> {
+		module.code_section().expect("Code section to exist").bodies()[index].code().elements().to_vec()
+	}
+
+	#[test]
+	fn single_use() {
+		use parity_wasm::elements::{BlockType, Instruction::*, ValueType};
+
+		let module = load_sample(r#"
+			(module
+				(func $helper (param i32 i32) (result i32)
+					(local i64)
+					get_local 0
+					if
+						get_local 1
+						return
+					end
+					get_local 0
+					get_local 1
+					i32.add)
+				(func (export "call") (param i32) (result i32)
+					get_local 0
+					i32.const 2
+					call $helper))
+		"#);
+
+		let module = inlined(module, 0);
+		assert_eq!(module.functions_space(), 1);
+		assert_eq!(module.code_section().unwrap().bodies()[0].locals(), &[
+			elements::Local::new(1, ValueType::I32),
+			elements::Local::new(1, ValueType::I32),
+			elements::Local::new(1, ValueType::I64),
+		]);
+		assert_eq!(code(&module, 0), vec![
+			GetLocal(0),
+			I32Const(2),
+			SetLocal(2),
+			SetLocal(1),
+			I64Const(0),
+			SetLocal(3),
+			Block(BlockType::Value(ValueType::I32)),
+			GetLocal(1),
+			If(BlockType::NoResult),
+			GetLocal(2),
+			Br(1),
+			End,
+			GetLocal(1),
+			GetLocal(2),
+			I32Add,
+			End,
+			End,
+		]);
+	}
+
+	#[test]
+	fn kept_and_small() {
+		let module = load_sample(r#"
+			(module
+				(import "env" "ext" (func $ext))
+				(func $tiny (export "tiny")
+					call $ext)
+				(func $big
+					call $ext
+					call $ext
+					call $ext)
+				(func $main (export "main")
+					call $tiny
+					call $tiny
+					call $big
+					call $big
+					call $main)
+				(table 1 anyfunc)
+				(elem (i32.const 0) $big))
+		"#);
+
+		let module = inlined(module, 2);
+		// Nothing removed: `tiny` is exported, `big` is used twice and in a table
+		assert_eq!(module.functions_space(), 4);
+
+		let calls = code(&module, 2).into_iter()
+			.filter_map(|instruction| match instruction {
+				elements::Instruction::Call(index) => Some(index),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(calls, vec![0, 0, 2, 2, 3]);
+	}
+
+	#[test]
+	fn names() {
+		let binary = wabt::Wat2Wasm::new()
+			.write_debug_names(true)
+			.convert(r#"
+				(module
+					(func $helper (param $value i32) (result i32)
+						get_local $value)
+					(func $main (export "main") (param $arg i32) (result i32)
+						get_local $arg
+						call $helper))
+			"#)
+			.expect("Failed to wat2wasm");
+		let module = elements::deserialize_buffer(binary.as_ref()).expect("Failed to deserialize the module");
+
+		let module = inlined(module, 0);
+		assert_eq!(module.functions_space(), 1);
+		let names = match module.sections().last() {
+			Some(elements::Section::Name(names)) => names,
+			other => panic!("Expected the name section, got {:?}", other),
+		};
+		let function_names = names.functions().expect("Function names to stay").names();
+		assert_eq!(function_names.get(0).map(String::as_str), Some("main"));
+		assert_eq!(function_names.get(1), None);
+		let local_names = names.locals().expect("Local names to stay").local_names();
+		assert_eq!(local_names.get(0).and_then(|locals| locals.get(0)).map(String::as_str), Some("arg"));
+		assert_eq!(local_names.get(1), None);
+	}
+}
@@ -7,33 +7,72 @@ extern crate alloc;
 pub mod rules;
 
 mod build;
+mod callgraph;
+mod check;
+mod data;
+mod dedup;
 mod ext;
 mod gas;
+mod inline;
+mod link;
+mod lint;
+mod locals;
+mod module_diff;
+mod nan;
 mod optimizer;
 mod pack;
+mod pass;
+mod peephole;
+mod remap;
 mod runtime_type;
-mod graph;
+mod size;
+mod soft_float;
+mod strip;
+pub mod graph;
 mod ref_list;
 mod symbols;
 #[cfg(feature = "std")]
 mod export_globals;
 #[cfg(feature = "cli")]
+pub mod file;
+#[cfg(feature = "cli")]
 pub mod logger;
 
 pub mod stack_height;
 
-pub use build::{build, Error as BuildError, SourceTarget};
+pub use build::{build, build_with_strip, Error as BuildError, SourceTarget};
+pub use callgraph::{CallEdge, CallGraph, CallKind, CallNode};
+pub use check::{check, Policy, Violation};
 pub use ext::{
-	externalize, externalize_mem, shrink_unknown_stack, underscore_funcs, ununderscore_funcs,
+	externalize, externalize_mem, shrink_unknown_stack, try_externalize, underscore_funcs,
+	ununderscore_funcs, Externalize,
+};
+pub use data::compact_data_segments;
+pub use dedup::deduplicate;
+pub use gas::{inject_gas_counter, GasMetering};
+pub use inline::{inline_functions, Inline};
+pub use link::{link, Error as LinkError};
+pub use lint::{lint, Finding, Location};
+pub use locals::compact_locals;
+pub use module_diff::{diff_modules, Change, CodeLine, FunctionDiff, ModuleDiff};
+pub use nan::{canonicalize_nans, CanonicalizeNans};
+pub use optimizer::{
+	optimize, optimize_with_report, optimize_with_options, Error as OptimizerError,
+	Options as OptimizerOptions, Report as OptimizerReport, Root as OptimizerRoot,
 };
-pub use gas::inject_gas_counter;
-pub use optimizer::{optimize, Error as OptimizerError};
-pub use pack::{pack_instance, Error as PackingError};
-pub use runtime_type::inject_runtime_type;
+pub use pack::{pack_instance, Error as PackingError, Pack};
+pub use pass::{Error as PassError, Pass, Pipeline};
+pub use peephole::{peephole_optimize, peephole_optimize_instructions};
+pub use remap::{remap_imports, Error as RemapError, Mapping as ImportMapping};
+pub use runtime_type::{inject_runtime_type, try_inject_runtime_type, RuntimeType};
+pub use size::{size_profile, ExportSize, ItemSize, SizeProfile};
+pub use soft_float::{lower_floats, SoftFloat};
+pub use strip::{strip_custom_sections, Options as StripOptions};
 pub use graph::{Module, parse as graph_parse, generate as graph_generate};
 pub use ref_list::{RefList, Entry, EntryRef, DeleteTransaction};
+pub use symbols::Symbol;
 #[cfg(feature = "std")]
-pub use export_globals::export_mutable_globals;
+pub use export_globals::{export_mutable_globals, try_export_mutable_globals, ExportMutableGlobals};
 pub use parity_wasm;
 
 pub struct TargetSymbols {
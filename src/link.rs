@@ -0,0 +1,481 @@
+//! Static linking of library modules into the main module.
+//!
+//! Imports of the main module are resolved to exports of the libraries: an import
+//! from the module `name` is matched with the export of the library registered under `name`.
+//! Exported functions and globals are copied into the main module together with everything
+//! they reference, while imports of the library become imports of the main module.
+//!
+//! There are no relocations: the library code works with the memory of the main module and
+//! library data segments are copied with their original offsets, so the memory layouts
+//! have to be disjoint. Tables are not linked.
+
+use crate::std::collections::BTreeMap;
+use crate::std::fmt;
+use crate::std::mem;
+use crate::std::string::String;
+use crate::std::vec::Vec;
+use crate::std::borrow::ToOwned;
+
+use log::trace;
+use parity_wasm::elements;
+
+use crate::graph::{
+	self, DataSegment, ExportLocal, Func, Global, ImportedOrDeclared, Instruction, Memory,
+	SegmentLocation,
+};
+use crate::ref_list::EntryRef;
+
+/// Linking error.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+	/// Import (module, field) is resolved to the export of the different kind, or to a table.
+	KindMismatch(String, String),
+	/// Import (module, field) has different type than the entity it is resolved to.
+	TypeMismatch(String, String),
+	/// Code copied from the library uses `call_indirect`, while tables are not linked.
+	IndirectCall(String),
+	/// Library (with the name) uses `table.init` or `elem.drop`, element segments are not copied.
+	ElementSegment(String),
+	/// Library (with the name) accesses the memory which the main module does not have.
+	MissingMemory(String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match self {
+			Error::KindMismatch(module, field) => write!(f, "Import `{}.{}` can't be linked to the export of other kind", module, field),
+			Error::TypeMismatch(module, field) => write!(f, "Import `{}.{}` has different type than the export", module, field),
+			Error::IndirectCall(library) => write!(f, "Library `{}` uses call_indirect, which is not supported", library),
+			Error::ElementSegment(library) => write!(f, "Library `{}` uses element segments, which is not supported", library),
+			Error::MissingMemory(library) => write!(f, "Library `{}` accesses memory, but the main module has none", library),
+		}
+	}
+}
+
+/// Entities of the library that are already copied into the main module.
+#[derive(Default)]
+struct Copied {
+	funcs: BTreeMap<usize, EntryRef<Func>>,
+	globals: BTreeMap<usize, EntryRef<Global>>,
+	data: Option<Vec<EntryRef<DataSegment>>>,
+}
+
+struct Linker<'a> {
+	main: &'a mut graph::Module,
+	name: &'a str,
+	library: &'a graph::Module,
+	copied: &'a mut Copied,
+	pending: Vec<(usize, EntryRef<Func>)>,
+}
+
+/// Link libraries into the main module.
+///
+/// Every library is given with the module name the main module imports it by.
+/// Libraries can import each other. Exports and the start function of libraries are ignored.
+///
+/// Returns number of resolved imports.
+pub fn link(main: &mut graph::Module, libraries: &[(&str, &graph::Module)]) -> Result<usize, Error> {
+	let mut copied = libraries.iter().map(|_| Copied::default()).collect::<Vec<_>>();
+	let mut resolved = 0;
+	loop {
+		let mut resolved_now = 0;
+		for ((name, library), copied) in libraries.iter().zip(copied.iter_mut()) {
+			let mut linker = Linker { main: &mut *main, name, library, copied, pending: Vec::new() };
+			resolved_now += linker.link()?;
+		}
+		if resolved_now == 0 {
+			break;
+		}
+		resolved += resolved_now;
+	}
+	Ok(resolved)
+}
+
+fn import_name<T>(origin: &ImportedOrDeclared<T>) -> Option<(&str, &str)> {
+	match origin {
+		ImportedOrDeclared::Imported(module, field) => Some((module, field)),
+		ImportedOrDeclared::Declared(_) => None,
+	}
+}
+
+fn order<T>(entry: &EntryRef<T>) -> usize {
+	entry.order().expect("Entries of the module are in the list")
+}
+
+impl<'a> Linker<'a> {
+	fn link(&mut self) -> Result<usize, Error> {
+		let mut funcs = Vec::new();
+		let mut globals = Vec::new();
+		let mut resolved = 0;
+
+		let imports = self.main.funcs.iter().map(|f| import_name(&f.read().origin).map(|(m, f)| (m.to_owned(), f.to_owned())))
+			.chain(self.main.globals.iter().map(|g| import_name(&g.read().origin).map(|(m, f)| (m.to_owned(), f.to_owned()))))
+			.chain(self.main.memory.iter().map(|m| import_name(&m.read().origin).map(|(m, f)| (m.to_owned(), f.to_owned()))))
+			.chain(self.main.tables.iter().map(|t| import_name(&t.read().origin).map(|(m, f)| (m.to_owned(), f.to_owned()))))
+			.collect::<Vec<_>>();
+		let (funcs_end, globals_end, memory_end) = (
+			self.main.funcs.len(),
+			self.main.funcs.len() + self.main.globals.len(),
+			self.main.funcs.len() + self.main.globals.len() + self.main.memory.len(),
+		);
+
+		for (index, import) in imports.into_iter().enumerate() {
+			let (module, field) = match import {
+				Some(import) if import.0 == self.name => import,
+				_ => continue,
+			};
+			let library = self.library;
+			let export = match library.exports.iter().find(|export| export.name == field) {
+				Some(export) => export,
+				None => continue,
+			};
+
+			match &export.local {
+				ExportLocal::Func(lib_func) if index < funcs_end => {
+					let old = self.main.funcs.clone_ref(index);
+					if **old.read().type_ref.read() != **lib_func.read().type_ref.read() {
+						return Err(Error::TypeMismatch(module, field));
+					}
+					funcs.push((old, self.copy_func(order(lib_func))?));
+				},
+				ExportLocal::Global(lib_global) if index >= funcs_end && index < globals_end => {
+					let old = self.main.globals.clone_ref(index - funcs_end);
+					{
+						let (old, lib_global) = (old.read(), lib_global.read());
+						if old.content != lib_global.content || old.is_mut != lib_global.is_mut {
+							return Err(Error::TypeMismatch(module, field));
+						}
+					}
+					globals.push((old, self.copy_global(order(lib_global))?));
+				},
+				ExportLocal::Memory(lib_memory) if index >= globals_end && index < memory_end => {
+					// Memory entry is kept, so references to it stay valid; only its origin is replaced
+					let lib_memory = lib_memory.read();
+					let mut memory = self.main.memory.get_ref(index - globals_end).write();
+					memory.limits = lib_memory.limits;
+					memory.origin = match &lib_memory.origin {
+						ImportedOrDeclared::Imported(module, field) => ImportedOrDeclared::Imported(module.clone(), field.clone()),
+						ImportedOrDeclared::Declared(()) => ImportedOrDeclared::Declared(()),
+					};
+				},
+				_ => return Err(Error::KindMismatch(module, field)),
+			}
+			trace!("Linked import {}.{}", module, field);
+			resolved += 1;
+		}
+
+		let library = self.library;
+		while let Some((lib_index, func)) = self.pending.pop() {
+			let code = match &library.funcs.get_ref(lib_index).read().origin {
+				ImportedOrDeclared::Declared(body) => self.translate(&body.code)?,
+				ImportedOrDeclared::Imported(..) => unreachable!("Only declared functions are pending"),
+			};
+			if let ImportedOrDeclared::Declared(body) = &mut func.write().origin {
+				body.code = code;
+			}
+		}
+
+		if resolved > 0 {
+			self.copy_data()?;
+			self.replace(funcs, globals);
+		}
+		Ok(resolved)
+	}
+
+	fn copy_func(&mut self, lib_index: usize) -> Result<EntryRef<Func>, Error> {
+		// Copied import is deleted, once it is linked to another library
+		if let Some(func) = self.copied.funcs.get(&lib_index).filter(|func| func.order().is_some()) {
+			return Ok(func.clone());
+		}
+
+		let library = self.library;
+		let lib_func = library.funcs.get_ref(lib_index).read();
+		let elements::Type::Function(signature) = (**lib_func.type_ref.read()).clone();
+		let func = match &lib_func.origin {
+			ImportedOrDeclared::Imported(module, field) => {
+				let existing = self.main.funcs.iter()
+					.find(|func| import_name(&func.read().origin) == Some((module, field)))
+					.cloned();
+				match existing {
+					Some(existing) => {
+						if **existing.read().type_ref.read() != elements::Type::Function(signature) {
+							return Err(Error::TypeMismatch(module.clone(), field.clone()));
+						}
+						existing
+					},
+					None => self.main.add_import_func(module, field, signature),
+				}
+			},
+			ImportedOrDeclared::Declared(body) => {
+				let func = self.main.add_func(signature, body.locals.clone(), Vec::new());
+				self.pending.push((lib_index, func.clone()));
+				func
+			},
+		};
+
+		self.copied.funcs.insert(lib_index, func.clone());
+		Ok(func)
+	}
+
+	fn copy_global(&mut self, lib_index: usize) -> Result<EntryRef<Global>, Error> {
+		if let Some(global) = self.copied.globals.get(&lib_index).filter(|global| global.order().is_some()) {
+			return Ok(global.clone());
+		}
+
+		let library = self.library;
+		let lib_global = library.globals.get_ref(lib_index).read();
+		let global = match &lib_global.origin {
+			ImportedOrDeclared::Imported(module, field) => {
+				let existing = self.main.globals.iter()
+					.find(|global| import_name(&global.read().origin) == Some((module, field)))
+					.cloned();
+				match existing {
+					Some(existing) => {
+						if existing.read().content != lib_global.content || existing.read().is_mut != lib_global.is_mut {
+							return Err(Error::TypeMismatch(module.clone(), field.clone()));
+						}
+						existing
+					},
+					None => self.main.add_import_global(module, field, lib_global.content, lib_global.is_mut),
+				}
+			},
+			ImportedOrDeclared::Declared(init) => {
+				let init = self.translate(init)?;
+				self.main.add_global(lib_global.content, lib_global.is_mut, init)
+			},
+		};
+
+		self.copied.globals.insert(lib_index, global.clone());
+		Ok(global)
+	}
+
+	/// Copy all data segments of the library, returns the copies.
+	fn copy_data(&mut self) -> Result<Vec<EntryRef<DataSegment>>, Error> {
+		if let Some(data) = &self.copied.data {
+			return Ok(data.clone());
+		}
+
+		let library = self.library;
+		let mut data = Vec::new();
+		for segment in library.data.iter() {
+			let segment = segment.read();
+			let location = match &segment.location {
+				SegmentLocation::Passive => SegmentLocation::Passive,
+				SegmentLocation::Default(code) => SegmentLocation::Default(self.translate(code)?),
+				SegmentLocation::WithIndex(index, code) => SegmentLocation::WithIndex(*index, self.translate(code)?),
+			};
+			data.push(self.main.data.push(DataSegment { location, value: segment.value.clone() }));
+		}
+		self.copied.data = Some(data.clone());
+		Ok(data)
+	}
+
+	/// Translate library code to the main module, copying referenced entities.
+	fn translate(&mut self, code: &[Instruction]) -> Result<Vec<Instruction>, Error> {
+		code.iter().map(|instruction| Ok(match instruction {
+			Instruction::Plain(plain) => Instruction::Plain(plain.clone()),
+			Instruction::Call(func) => Instruction::Call(self.copy_func(order(func))?),
+			Instruction::CallIndirect(..) => return Err(Error::IndirectCall(self.name.to_owned())),
+			Instruction::GetGlobal(global) => Instruction::GetGlobal(self.copy_global(order(global))?),
+			Instruction::SetGlobal(global) => Instruction::SetGlobal(self.copy_global(order(global))?),
+			Instruction::Memory(plain, memory) => Instruction::Memory(plain.clone(), self.memory(memory)?),
+			Instruction::CurrentMemory(memory) => Instruction::CurrentMemory(self.memory(memory)?),
+			Instruction::GrowMemory(memory) => Instruction::GrowMemory(self.memory(memory)?),
+			#[cfg(feature = "bulk")]
+			Instruction::MemoryInit(segment) => Instruction::MemoryInit(self.copy_data()?[order(segment)].clone()),
+			#[cfg(feature = "bulk")]
+			Instruction::DataDrop(segment) => Instruction::DataDrop(self.copy_data()?[order(segment)].clone()),
+			#[cfg(feature = "bulk")]
+			Instruction::TableInit(_) | Instruction::ElemDrop(_) =>
+				return Err(Error::ElementSegment(self.name.to_owned())),
+		})).collect()
+	}
+
+	/// Memory of the main module used in place of the library memory.
+	fn memory(&self, memory: &EntryRef<Memory>) -> Result<EntryRef<Memory>, Error> {
+		let index = order(memory);
+		if index >= self.main.memory.len() {
+			return Err(Error::MissingMemory(self.name.to_owned()));
+		}
+		Ok(self.main.memory.clone_ref(index))
+	}
+
+	/// Point all references to resolved imports to their replacements and delete the imports.
+	fn replace(&mut self, funcs: Vec<(EntryRef<Func>, EntryRef<Func>)>, globals: Vec<(EntryRef<Global>, EntryRef<Global>)>) {
+		let funcs = funcs.into_iter().map(|(old, new)| (order(&old), new)).collect::<BTreeMap<_, _>>();
+		let globals = globals.into_iter().map(|(old, new)| (order(&old), new)).collect::<BTreeMap<_, _>>();
+
+		let replace_func = |func: &mut EntryRef<Func>| {
+			if let Some(new) = funcs.get(&order(func)) {
+				*func = new.clone();
+			}
+		};
+		let replace_code = |code: &mut Vec<Instruction>| {
+			for instruction in code.iter_mut() {
+				match instruction {
+					Instruction::Call(func) => replace_func(func),
+					Instruction::GetGlobal(global) | Instruction::SetGlobal(global) => {
+						if let Some(new) = globals.get(&order(global)) {
+							*global = new.clone();
+						}
+					},
+					_ => { },
+				}
+			}
+		};
+		let replace_location = |location: &mut SegmentLocation| match location {
+			SegmentLocation::Default(code) | SegmentLocation::WithIndex(_, code) => replace_code(code),
+			SegmentLocation::Passive => { },
+		};
+
+		// Code is taken out of the entry, since it can reference the entry itself
+		for func in self.main.funcs.iter() {
+			let mut code = match &mut func.write().origin {
+				ImportedOrDeclared::Declared(body) => mem::take(&mut body.code),
+				ImportedOrDeclared::Imported(..) => continue,
+			};
+			replace_code(&mut code);
+			if let ImportedOrDeclared::Declared(body) = &mut func.write().origin {
+				body.code = code;
+			}
+		}
+		for global in self.main.globals.iter() {
+			let mut init = match &mut global.write().origin {
+				ImportedOrDeclared::Declared(init) => mem::take(init),
+				ImportedOrDeclared::Imported(..) => continue,
+			};
+			replace_code(&mut init);
+			if let ImportedOrDeclared::Declared(code) = &mut global.write().origin {
+				*code = init;
+			}
+		}
+		for export in self.main.exports.iter_mut() {
+			match &mut export.local {
+				ExportLocal::Func(func) => replace_func(func),
+				ExportLocal::Global(global) => {
+					if let Some(new) = globals.get(&order(global)) {
+						*global = new.clone();
+					}
+				},
+				_ => { },
+			}
+		}
+		if let Some(start) = self.main.start.as_mut() {
+			replace_func(start);
+		}
+		for segment in self.main.elements.iter() {
+			let mut segment = segment.write();
+			replace_location(&mut segment.location);
+			segment.value.iter_mut().for_each(replace_func);
+		}
+		for segment in self.main.data.iter() {
+			replace_location(&mut segment.write().location);
+		}
+
+		if !funcs.is_empty() {
+			funcs.keys().fold(self.main.funcs.begin_delete(), |tx, index| tx.push(*index)).done();
+		}
+		if !globals.is_empty() {
+			globals.keys().fold(self.main.globals.begin_delete(), |tx, index| tx.push(*index)).done();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements;
+	use crate::graph;
+	use super::*;
+
+	fn load_sample(wat: &str) -> graph::Module {
+		graph::parse(&wabt::wat2wasm(wat).expect("Failed to wat2wasm")[..])
+			.expect("Error making representation")
+	}
+
+	fn generate(module: &graph::Module) -> elements::Module {
+		let binary = graph::generate(module).expect("Failed to generate binary");
+		wabt::Module::read_binary(&binary, &Default::default())
+			.expect("Wabt failed to read final binary")
+			.validate()
+			.expect("Invalid module");
+		elements::deserialize_buffer(&binary).expect("Failed to deserialize")
+	}
+
+	fn library() -> graph::Module {
+		load_sample(r#"
+			(module
+				(import "env" "log" (func $log (param i32)))
+				(import "env" "base" (global $base i32))
+				(global $counter (mut i32) (get_global $base))
+				(memory (export "memory") 1)
+				(data (i32.const 1024) "lib")
+				(func $bump (result i32)
+					get_global $counter
+					i32.const 1
+					i32.add
+					set_global $counter
+					get_global $counter)
+				(func (export "next") (result i32)
+					call $bump
+					call $log
+					get_global $counter)
+				(func (export "unused")
+					i32.const 0
+					call $log)
+				(export "counter" (global $counter)))
+		"#)
+	}
+
+	#[test]
+	fn links() {
+		let mut main = load_sample(r#"
+			(module
+				(import "env" "log" (func $log (param i32)))
+				(import "lib" "next" (func $next (result i32)))
+				(import "lib" "missing" (func $missing))
+				(import "lib" "memory" (memory 1))
+				(data (i32.const 0) "main")
+				(func (export "call")
+					call $next
+					call $log
+					call $missing))
+		"#);
+
+		assert_eq!(link(&mut main, &[("lib", &library())]), Ok(2));
+
+		let module = generate(&main);
+		let imports = module.import_section().expect("Import section to stay").entries().iter()
+			.map(|entry| (entry.module(), entry.field()))
+			.collect::<Vec<_>>();
+		assert_eq!(imports, vec![("env", "log"), ("lib", "missing"), ("env", "base")]);
+		// `call`, `next` and `bump`, but not `unused`
+		assert_eq!(module.function_section().expect("Function section to stay").entries().len(), 3);
+		assert_eq!(module.memory_section().expect("Memory section to exist").entries().len(), 1);
+		assert_eq!(module.data_section().expect("Data section to stay").entries().len(), 2);
+		assert_eq!(
+			module.code_section().expect("Code section to stay").bodies()[0].code().elements()[0],
+			elements::Instruction::Call(3),
+		);
+	}
+
+	#[test]
+	fn errors() {
+		let mut main = load_sample(r#"
+			(module
+				(import "lib" "next" (func (result i64))))
+		"#);
+		assert_eq!(
+			link(&mut main, &[("lib", &library())]),
+			Err(Error::TypeMismatch("lib".into(), "next".into())),
+		);
+
+		let mut main = load_sample(r#"
+			(module
+				(import "lib" "counter" (func)))
+		"#);
+		assert_eq!(
+			link(&mut main, &[("lib", &library())]),
+			Err(Error::KindMismatch("lib".into(), "counter".into())),
+		);
+	}
+}
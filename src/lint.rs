@@ -0,0 +1,262 @@
+//! Lints for constructs which may execute differently on different machines.
+//!
+//! Contracts must be deterministic, so every floating point value (NaN bit patterns are not
+//! specified), `memory.grow` which may fail depending on the host and a start function calling
+//! into the host are reported.
+
+use crate::std::collections::BTreeSet;
+use crate::std::fmt;
+use crate::std::string::String;
+use crate::std::vec::Vec;
+
+use parity_wasm::elements::{self, Instruction, ValueType};
+
+use crate::callgraph::{CallGraph, function_name};
+use crate::rules::InstructionType;
+use crate::symbols::function_type;
+
+/// Place in the function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+	/// Index of the function in the function space.
+	pub function: u32,
+	/// Name of the function, from the name section or the export.
+	pub name: Option<String>,
+	/// Index of the instruction in the function body, if the finding is about an instruction.
+	pub offset: Option<usize>,
+}
+
+impl fmt::Display for Location {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match &self.name {
+			Some(name) => write!(f, "function `{}`", name)?,
+			None => write!(f, "function #{}", self.function)?,
+		}
+		match self.offset {
+			Some(offset) => write!(f, " at offset {}", offset),
+			None => Ok(()),
+		}
+	}
+}
+
+/// Non-deterministic construct.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Finding {
+	/// Instruction operating on floats.
+	FloatInstruction(Location, Instruction),
+	/// Local (with the index in the local space, parameters first) of a float type.
+	FloatLocal(Location, u32, ValueType),
+	/// Global (with the index in the global space) of a float type.
+	FloatGlobal(u32, ValueType),
+	/// Imported function (`module.field`) with floats in the signature.
+	FloatImport(String),
+	/// Exported function with floats in the signature.
+	FloatExport(String),
+	/// `memory.grow` of the memory without a declared maximum.
+	UnboundedGrow(Location),
+	/// Start function (the location) which can call the imported function (`module.field`).
+	ImpureStart(Location, String),
+}
+
+impl fmt::Display for Finding {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match self {
+			Finding::FloatInstruction(location, instruction) => write!(f, "{}: float instruction `{}`", location, instruction),
+			Finding::FloatLocal(location, index, value_type) => write!(f, "{}: local #{} is {}", location, index, value_type),
+			Finding::FloatGlobal(index, value_type) => write!(f, "global #{} is {}", index, value_type),
+			Finding::FloatImport(name) => write!(f, "import `{}` has floats in the signature", name),
+			Finding::FloatExport(name) => write!(f, "export `{}` has floats in the signature", name),
+			Finding::UnboundedGrow(location) => write!(f, "{}: memory.grow without a declared memory maximum", location),
+			Finding::ImpureStart(location, import) => write!(f, "{}: start function can call import `{}`", location, import),
+		}
+	}
+}
+
+/// Whether the instruction operates on floats, including float loads, stores and reinterpretations.
+pub(crate) fn is_float_instruction(instruction: &Instruction) -> bool {
+	use Instruction::*;
+
+	match InstructionType::op(instruction) {
+		InstructionType::Float
+		| InstructionType::FloatComparison
+		| InstructionType::FloatConst
+		| InstructionType::FloatConversion
+		| InstructionType::Reinterpretation => true,
+		_ => matches!(instruction, F32Load(..) | F64Load(..) | F32Store(..) | F64Store(..)),
+	}
+}
+
+fn is_float(value_type: &ValueType) -> bool {
+	matches!(value_type, ValueType::F32 | ValueType::F64)
+}
+
+fn has_floats(func_type: &elements::FunctionType) -> bool {
+	func_type.params().iter().chain(func_type.results()).any(is_float)
+}
+
+fn location(module: &elements::Module, function: u32, offset: Option<usize>) -> Location {
+	Location { function, name: function_name(module, function), offset }
+}
+
+/// Report every non-deterministic construct of the module, in the module order.
+pub fn lint(module: &elements::Module) -> Vec<Finding> {
+	let mut findings = Vec::new();
+
+	let mut globals = 0;
+	for entry in module.import_section().map(|section| section.entries()).unwrap_or(&[]) {
+		match entry.external() {
+			elements::External::Function(type_index) => {
+				let float_signature = match module.type_section().and_then(|section| section.types().get(*type_index as usize)) {
+					Some(elements::Type::Function(func_type)) => has_floats(func_type),
+					None => false,
+				};
+				if float_signature {
+					findings.push(Finding::FloatImport(format!("{}.{}", entry.module(), entry.field())));
+				}
+			},
+			elements::External::Global(global_type) => {
+				if is_float(&global_type.content_type()) {
+					findings.push(Finding::FloatGlobal(globals, global_type.content_type()));
+				}
+				globals += 1;
+			},
+			_ => {},
+		}
+	}
+	for global in module.global_section().map(|section| section.entries()).unwrap_or(&[]) {
+		if is_float(&global.global_type().content_type()) {
+			findings.push(Finding::FloatGlobal(globals, global.global_type().content_type()));
+		}
+		globals += 1;
+	}
+
+	let unbounded_memory = module.import_section()
+		.iter()
+		.flat_map(|section| section.entries())
+		.filter_map(|entry| match entry.external() {
+			elements::External::Memory(memory) => Some(memory.limits()),
+			_ => None,
+		})
+		.chain(module.memory_section().iter().flat_map(|section| section.entries()).map(|memory| memory.limits()))
+		.next()
+		.is_some_and(|limits| limits.maximum().is_none());
+
+	let imported = module.import_count(elements::ImportCountType::Function) as u32;
+	for (index, body) in module.code_section().map(|section| section.bodies()).unwrap_or(&[]).iter().enumerate() {
+		let function = imported + index as u32;
+
+		let params = function_type(module, function).map(|func_type| func_type.params()).unwrap_or(&[]);
+		let locals = body.locals().iter().flat_map(|local| (0..local.count()).map(move |_| local.value_type()));
+		for (local, value_type) in params.iter().cloned().chain(locals).enumerate() {
+			if is_float(&value_type) {
+				findings.push(Finding::FloatLocal(location(module, function, None), local as u32, value_type));
+			}
+		}
+
+		for (offset, instruction) in body.code().elements().iter().enumerate() {
+			if is_float_instruction(instruction) {
+				findings.push(Finding::FloatInstruction(location(module, function, Some(offset)), instruction.clone()));
+			} else if unbounded_memory && matches!(instruction, Instruction::GrowMemory(_)) {
+				findings.push(Finding::UnboundedGrow(location(module, function, Some(offset))));
+			}
+		}
+	}
+
+	for entry in module.export_section().map(|section| section.entries()).unwrap_or(&[]) {
+		if let elements::Internal::Function(index) = entry.internal() {
+			if function_type(module, *index).is_some_and(has_floats) {
+				findings.push(Finding::FloatExport(entry.field().into()));
+			}
+		}
+	}
+
+	if let Some(start) = module.start_section() {
+		let graph = CallGraph::new(module);
+		let mut visited = BTreeSet::new();
+		let mut queue = vec![start];
+		while let Some(function) = queue.pop() {
+			if !visited.insert(function) {
+				continue;
+			}
+			queue.extend(graph.callees(function).map(|edge| edge.callee));
+		}
+		for function in visited {
+			let node = match graph.nodes().get(function as usize) {
+				Some(node) if node.is_imported() => node,
+				_ => continue,
+			};
+			let name = node.name.clone().unwrap_or_else(|| format!("#{}", function));
+			findings.push(Finding::ImpureStart(location(module, start, None), name));
+		}
+	}
+
+	findings
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	#[test]
+	fn determinism() {
+		let module = parse_wat(r#"
+			(module
+				(import "env" "sqrt" (func $sqrt (param f64) (result f64)))
+				(import "env" "now" (func $now (result i32)))
+				(import "env" "scale" (global f32))
+				(memory 1)
+				(global (mut i32) (i32.const 0))
+				(global f64 (f64.const 0))
+				(func $init
+					call $setup
+				)
+				(func $setup
+					call $now
+					memory.grow
+					drop
+				)
+				(func (export "area") (param i32) (result i32)
+					(local f32)
+					local.get 0
+					f32.convert_i32_s
+					local.set 1
+					local.get 0
+				)
+				(start $init)
+			)
+		"#);
+		let at = |function, name: Option<&str>, offset| Location {
+			function,
+			name: name.map(|name| name.to_string()),
+			offset,
+		};
+		assert_eq!(
+			lint(&module),
+			vec![
+				Finding::FloatImport("env.sqrt".to_string()),
+				Finding::FloatGlobal(0, ValueType::F32),
+				Finding::FloatGlobal(2, ValueType::F64),
+				Finding::UnboundedGrow(at(3, None, Some(1))),
+				Finding::FloatLocal(at(4, Some("area"), None), 1, ValueType::F32),
+				Finding::FloatInstruction(at(4, Some("area"), Some(1)), Instruction::F32ConvertSI32),
+				Finding::ImpureStart(at(2, None, None), "env.now".to_string()),
+			],
+		);
+
+		let bounded = parse_wat(r#"
+			(module
+				(memory 1 2)
+				(func (export "grow") (result i32)
+					i32.const 1
+					memory.grow
+				)
+			)
+		"#);
+		assert_eq!(lint(&bounded), vec![]);
+	}
+}
@@ -0,0 +1,242 @@
+//! Compaction of function locals.
+//!
+//! Locals that are never read or written are removed and the rest are grouped so
+//! that each value type is declared once. Every local counts towards the stack cost
+//! computed by the stack limiter, so compaction lowers it as well as the code size.
+
+use crate::std::vec::Vec;
+
+use log::trace;
+use parity_wasm::elements::{self, ValueType};
+
+use crate::graph::{self, ImportedOrDeclared, Instruction};
+
+/// Compact locals of every declared function in the module.
+///
+/// Local names of the parsed name section follow the locals, names of removed locals
+/// are dropped. Name section which is not parsed is dropped if any local is moved.
+///
+/// Returns total number of removed locals.
+pub fn compact_locals(module: &mut graph::Module) -> usize {
+	let mut removed = 0;
+	let mut moved = Vec::new();
+	for (index, func) in module.funcs.iter().enumerate() {
+		let params = match **func.read().type_ref.read() {
+			elements::Type::Function(ref func_type) => func_type.params().len() as u32,
+		};
+		let mut func = func.write();
+		if let ImportedOrDeclared::Declared(ref mut body) = func.origin {
+			let remap = compact_body(params, body);
+			removed += remap.iter().filter(|new_index| new_index.is_none()).count();
+			if remap.iter().enumerate().any(|(declared_index, new_index)| *new_index != Some(params + declared_index as u32)) {
+				moved.push((index as u32, params, remap));
+			}
+		}
+	}
+	if !moved.is_empty() {
+		remap_local_names(module, &moved);
+	}
+	removed
+}
+
+/// Move local names of the functions to the new indices, dropping names of removed locals.
+fn remap_local_names(module: &mut graph::Module, moved: &[(u32, u32, Vec<Option<u32>>)]) {
+	// Names which can't be parsed would point at wrong locals
+	module.other.retain(|_, section| {
+		!matches!(section, elements::Section::Custom(custom) if custom.name() == "name")
+	});
+	let local_names = module.other.values_mut()
+		.filter_map(|section| match section {
+			elements::Section::Name(names) => names.locals_mut().as_mut(),
+			_ => None,
+		});
+	for local_names in local_names {
+		for (func_index, params, remap) in moved.iter() {
+			let names = match local_names.local_names_mut().remove(*func_index) {
+				Some(names) => names,
+				None => continue,
+			};
+			let names = names.into_iter()
+				.filter_map(|(index, name)| match index.checked_sub(*params) {
+					None => Some((index, name)),
+					Some(declared_index) => Some(((*remap.get(declared_index as usize)?)?, name)),
+				})
+				.collect();
+			local_names.local_names_mut().insert(*func_index, names);
+		}
+	}
+}
+
+fn local_index(instruction: &mut Instruction) -> Option<&mut u32> {
+	match instruction {
+		Instruction::Plain(elements::Instruction::GetLocal(index)) |
+		Instruction::Plain(elements::Instruction::SetLocal(index)) |
+		Instruction::Plain(elements::Instruction::TeeLocal(index)) => Some(index),
+		_ => None,
+	}
+}
+
+/// Compact locals of a single function body with `params` parameters.
+///
+/// Parameters are part of the signature and are never touched.
+///
+/// Returns new indices of the declared locals, `None` for removed ones.
+fn compact_body(params: u32, body: &mut graph::FuncBody) -> Vec<Option<u32>> {
+	let declared = body.locals.iter()
+		.flat_map(|group| (0..group.count()).map(move |_| group.value_type()))
+		.collect::<Vec<_>>();
+
+	let mut used = vec![false; declared.len()];
+	for instruction in body.code.iter_mut() {
+		if let Some(index) = local_index(instruction) {
+			if let Some(declared_index) = index.checked_sub(params) {
+				used[declared_index as usize] = true;
+			}
+		}
+	}
+
+	// Used locals grouped by their type, in order of the first appearance of the type
+	let mut groups: Vec<(ValueType, Vec<usize>)> = Vec::new();
+	for (declared_index, value_type) in declared.iter().enumerate() {
+		if !used[declared_index] { continue; }
+		match groups.iter_mut().find(|(group_type, _)| group_type == value_type) {
+			Some((_, indices)) => indices.push(declared_index),
+			None => groups.push((*value_type, vec![declared_index])),
+		}
+	}
+
+	let mut remap = vec![None; declared.len()];
+	let mut next = params;
+	for (_, indices) in groups.iter() {
+		for declared_index in indices {
+			remap[*declared_index] = Some(next);
+			next += 1;
+		}
+	}
+
+	for instruction in body.code.iter_mut() {
+		if let Some(index) = local_index(instruction) {
+			if let Some(declared_index) = index.checked_sub(params) {
+				*index = remap[declared_index as usize].expect("Used locals are remapped");
+			}
+		}
+	}
+
+	body.locals = groups.iter()
+		.map(|(value_type, indices)| elements::Local::new(indices.len() as u32, *value_type))
+		.collect();
+
+	let removed = declared.len() - (next - params) as usize;
+	if removed > 0 {
+		trace!("Removed {} unused locals", removed);
+	}
+	remap
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements::{self, Instruction::*, ValueType};
+	use crate::graph::{self, ImportedOrDeclared};
+	use super::compact_locals;
+
+	fn load_sample(wat: &str) -> graph::Module {
+		graph::parse(&wabt::wat2wasm(wat).expect("Failed to wat2wasm")[..])
+			.expect("Error making representation")
+	}
+
+	fn validate_sample(module: &graph::Module) {
+		let binary = graph::generate(module).expect("Failed to generate binary");
+		wabt::Module::read_binary(&binary, &Default::default())
+			.expect("Wabt failed to read final binary")
+			.validate()
+			.expect("Invalid module");
+	}
+
+	fn body(module: &graph::Module, index: usize) -> (Vec<elements::Local>, Vec<elements::Instruction>) {
+		match module.funcs.get_ref(index).read().origin {
+			ImportedOrDeclared::Declared(ref body) => (
+				body.locals.clone(),
+				body.code.iter().map(|instruction| match instruction {
+					graph::Instruction::Plain(plain) => plain.clone(),
+					_ => panic!("Only plain instructions are expected"),
+				}).collect(),
+			),
+			_ => panic!("Function is expected to be declared"),
+		}
+	}
+
+	#[test]
+	fn removes_and_merges() {
+		let mut module = load_sample(r#"
+			(module
+				(func (param i32) (result i64)
+					(local i32 i64 f32 i32 i64)
+					get_local 0
+					set_local 4
+					get_local 4
+					tee_local 1
+					drop
+					get_local 5))
+		"#);
+
+		assert_eq!(compact_locals(&mut module), 2);
+
+		let (locals, code) = body(&module, 0);
+		assert_eq!(locals, vec![
+			elements::Local::new(2, ValueType::I32),
+			elements::Local::new(1, ValueType::I64),
+		]);
+		assert_eq!(code, vec![GetLocal(0), SetLocal(2), GetLocal(2), TeeLocal(1), Drop, GetLocal(3), End]);
+		validate_sample(&module);
+	}
+
+	#[test]
+	fn untouched() {
+		let mut module = load_sample(r#"
+			(module
+				(import "env" "foo" (func))
+				(func (param i32)
+					(local i64)
+					get_local 1
+					drop))
+		"#);
+
+		assert_eq!(compact_locals(&mut module), 0);
+
+		let (locals, code) = body(&module, 1);
+		assert_eq!(locals, vec![elements::Local::new(1, ValueType::I64)]);
+		assert_eq!(code, vec![GetLocal(1), Drop, End]);
+		validate_sample(&module);
+	}
+
+	#[test]
+	fn local_names() {
+		let binary = wabt::Wat2Wasm::new()
+			.write_debug_names(true)
+			.convert(r#"
+				(module
+					(func (param $p i32) (result i64)
+						(local $a i32) (local $b i64) (local $c f32) (local $d i32) (local $e i64)
+						get_local $p
+						set_local $d
+						get_local $d
+						tee_local $a
+						drop
+						get_local $e))
+			"#)
+			.expect("Failed to wat2wasm");
+		let module = elements::deserialize_buffer::<elements::Module>(binary.as_ref())
+			.expect("Failed to deserialize the module")
+			.parse_names()
+			.expect("Names to be parsed");
+		let mut module = graph::Module::from_elements(&module).expect("Error making representation");
+
+		assert_eq!(compact_locals(&mut module), 2);
+
+		let module = module.generate().expect("Failed to generate the module");
+		let names = module.names_section().expect("Name section to stay");
+		let locals = names.locals().expect("Local names to stay").local_names().get(0).expect("Function locals to be named");
+		let locals = locals.iter().map(|(index, name)| (index, name.as_str())).collect::<Vec<_>>();
+		assert_eq!(locals, vec![(0, "p"), (1, "a"), (2, "d"), (3, "e")]);
+	}
+}
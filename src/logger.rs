@@ -17,3 +17,13 @@ lazy_static! {
 pub fn init() {
 	let _ = *LOG_DUMMY;
 }
+
+/// Initialize log with the given default level, which `RUST_LOG` can still override
+pub fn init_with_level(level: LevelFilter) {
+	let mut builder = Builder::new();
+	builder.filter(None, level);
+	builder.parse_default_env();
+	if builder.try_init().is_ok() {
+		trace!("logger initialized");
+	}
+}
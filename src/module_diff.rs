@@ -0,0 +1,518 @@
+//! Structural comparison of two modules.
+//!
+//! Entities are matched by name instead of index, so inserting an import or a function
+//! does not make every following function differ. Functions are named after the name
+//! section or the export, imports after `module.field`. Unnamed functions and globals are
+//! matched by their position among the declared ones (`func[N]`, `global[N]`).
+
+use crate::std::borrow::ToOwned;
+use crate::std::collections::BTreeMap;
+use crate::std::string::{String, ToString};
+use crate::std::vec::Vec;
+
+use parity_wasm::elements;
+
+use crate::callgraph::function_name;
+use crate::symbols::{Symbol, function_type, resolve_global, signature};
+
+/// Change of the item, described with a short text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+	/// Item (name, description) exists only in the new module.
+	Added(String, String),
+	/// Item (name, description) exists only in the old module.
+	Removed(String, String),
+	/// Item (name, old description, new description) differs.
+	Changed(String, String, String),
+}
+
+/// Line of the function code diff.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CodeLine {
+	/// Line present in both functions.
+	Same(String),
+	/// Line present only in the old function.
+	Removed(String),
+	/// Line present only in the new function.
+	Added(String),
+}
+
+/// Change of the declared function.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FunctionDiff {
+	/// Function (with the name) exists only in the new module.
+	Added(String),
+	/// Function (with the name) exists only in the old module.
+	Removed(String),
+	/// Function exists in both modules, but differs.
+	Changed {
+		/// Name of the function.
+		name: String,
+		/// Old and new signature, if it changed.
+		signature: Option<(String, String)>,
+		/// Locals and instructions of both functions, with references replaced by names.
+		code: Vec<CodeLine>,
+	},
+}
+
+/// Structural difference between two modules.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModuleDiff {
+	/// Added, removed and changed declared functions.
+	pub functions: Vec<FunctionDiff>,
+	/// Changed imports.
+	pub imports: Vec<Change>,
+	/// Changed exports.
+	pub exports: Vec<Change>,
+	/// Changed declared globals.
+	pub globals: Vec<Change>,
+	/// Changed memory limits.
+	pub memory: Vec<Change>,
+	/// Changed table limits.
+	pub tables: Vec<Change>,
+	/// Changed data segments.
+	pub data: Vec<Change>,
+	/// Changed element segments.
+	pub elements: Vec<Change>,
+	/// Changed start function.
+	pub start: Vec<Change>,
+}
+
+impl ModuleDiff {
+	/// Whether the modules are structurally the same.
+	pub fn is_empty(&self) -> bool {
+		self.functions.is_empty()
+			&& self.imports.is_empty()
+			&& self.exports.is_empty()
+			&& self.globals.is_empty()
+			&& self.memory.is_empty()
+			&& self.tables.is_empty()
+			&& self.data.is_empty()
+			&& self.elements.is_empty()
+			&& self.start.is_empty()
+	}
+}
+
+/// Compare two modules structurally.
+///
+/// Function names are taken from the name section, so it should be parsed beforehand
+/// (see `parity_wasm::elements::Module::parse_names`).
+pub fn diff_modules(old: &elements::Module, new: &elements::Module) -> ModuleDiff {
+	ModuleDiff {
+		functions: diff_functions(old, new),
+		imports: diff_items(imports(old), imports(new)),
+		exports: diff_items(exports(old), exports(new)),
+		globals: diff_items(globals(old), globals(new)),
+		memory: diff_items(memory(old), memory(new)),
+		tables: diff_items(tables(old), tables(new)),
+		data: diff_items(data(old), data(new)),
+		elements: diff_items(element_segments(old), element_segments(new)),
+		start: diff_items(start(old), start(new)),
+	}
+}
+
+/// Limit of old × new lines, above which function code is not aligned line by line.
+const MAX_ALIGNED_LINES: usize = 4_000_000;
+
+fn diff_items(old: Vec<(String, String)>, new: Vec<(String, String)>) -> Vec<Change> {
+	let new_items = new.iter().cloned().collect::<BTreeMap<_, _>>();
+	let old_items = old.iter().cloned().collect::<BTreeMap<_, _>>();
+
+	let mut changes = Vec::new();
+	for (name, description) in old {
+		match new_items.get(&name) {
+			None => changes.push(Change::Removed(name, description)),
+			Some(new_description) if *new_description != description =>
+				changes.push(Change::Changed(name, description, new_description.clone())),
+			Some(_) => { },
+		}
+	}
+	for (name, description) in new {
+		if !old_items.contains_key(&name) {
+			changes.push(Change::Added(name, description));
+		}
+	}
+	changes
+}
+
+fn diff_functions(old: &elements::Module, new: &elements::Module) -> Vec<FunctionDiff> {
+	let old_funcs = functions(old);
+	let new_funcs = functions(new);
+	let new_by_name = new_funcs.iter().map(|(name, index)| (name.as_str(), *index)).collect::<BTreeMap<_, _>>();
+	let old_by_name = old_funcs.iter().map(|(name, index)| (name.as_str(), *index)).collect::<BTreeMap<_, _>>();
+
+	let mut diffs = Vec::new();
+	for (name, old_index) in old_funcs.iter() {
+		let new_index = match new_by_name.get(name.as_str()) {
+			Some(new_index) => *new_index,
+			None => {
+				diffs.push(FunctionDiff::Removed(name.clone()));
+				continue;
+			},
+		};
+
+		let (old_signature, new_signature) = (function_signature(old, *old_index), function_signature(new, new_index));
+		let (old_code, new_code) = (code_lines(old, *old_index), code_lines(new, new_index));
+		if old_signature == new_signature && old_code == new_code {
+			continue;
+		}
+		diffs.push(FunctionDiff::Changed {
+			name: name.clone(),
+			signature: if old_signature != new_signature { Some((old_signature, new_signature)) } else { None },
+			code: diff_lines(&old_code, &new_code),
+		});
+	}
+	for (name, _) in new_funcs.iter() {
+		if !old_by_name.contains_key(name.as_str()) {
+			diffs.push(FunctionDiff::Added(name.clone()));
+		}
+	}
+	diffs
+}
+
+/// Aligns two sequences of lines along their longest common subsequence.
+fn diff_lines(old: &[String], new: &[String]) -> Vec<CodeLine> {
+	let prefix = old.iter().zip(new.iter()).take_while(|(old, new)| old == new).count();
+	let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(old, new)| old == new).count();
+	let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+	let mut lines = old[..prefix].iter().cloned().map(CodeLine::Same).collect::<Vec<_>>();
+	let (n, m) = (old_middle.len(), new_middle.len());
+	if n * m > MAX_ALIGNED_LINES {
+		lines.extend(old_middle.iter().cloned().map(CodeLine::Removed));
+		lines.extend(new_middle.iter().cloned().map(CodeLine::Added));
+	} else {
+		// common[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+		let mut common = vec![0u32; (n + 1) * (m + 1)];
+		for i in (0..n).rev() {
+			for j in (0..m).rev() {
+				common[i * (m + 1) + j] = if old_middle[i] == new_middle[j] {
+					common[(i + 1) * (m + 1) + j + 1] + 1
+				} else {
+					common[(i + 1) * (m + 1) + j].max(common[i * (m + 1) + j + 1])
+				};
+			}
+		}
+
+		let (mut i, mut j) = (0, 0);
+		while i < n || j < m {
+			if i < n && j < m && old_middle[i] == new_middle[j] {
+				lines.push(CodeLine::Same(old_middle[i].clone()));
+				i += 1;
+				j += 1;
+			} else if j == m || (i < n && common[(i + 1) * (m + 1) + j] >= common[i * (m + 1) + j + 1]) {
+				lines.push(CodeLine::Removed(old_middle[i].clone()));
+				i += 1;
+			} else {
+				lines.push(CodeLine::Added(new_middle[j].clone()));
+				j += 1;
+			}
+		}
+	}
+	lines.extend(old[old.len() - suffix..].iter().cloned().map(CodeLine::Same));
+	lines
+}
+
+/// Names of declared functions with their index in the function space.
+fn functions(module: &elements::Module) -> Vec<(String, u32)> {
+	let imported = module.import_count(elements::ImportCountType::Function) as u32;
+	let declared = module.function_section().map(|section| section.entries().len()).unwrap_or(0) as u32;
+	(imported..imported + declared).map(|index| (function_key(module, index), index)).collect()
+}
+
+fn function_key(module: &elements::Module, index: u32) -> String {
+	function_name(module, index).unwrap_or_else(|| {
+		format!("func[{}]", index - module.import_count(elements::ImportCountType::Function) as u32)
+	})
+}
+
+fn global_key(module: &elements::Module, index: u32) -> String {
+	match resolve_global(module, index) {
+		Symbol::Import(import_index) => {
+			let entry = &module.import_section().expect("Import section to exist").entries()[import_index];
+			format!("{}.{}", entry.module(), entry.field())
+		},
+		Symbol::Global(declared_index) => module.export_section()
+			.and_then(|section| section.entries().iter().find(|entry| {
+				matches!(entry.internal(), elements::Internal::Global(global) if *global == index)
+			}))
+			.map(|entry| entry.field().to_owned())
+			.unwrap_or_else(|| format!("global[{}]", declared_index)),
+		_ => unreachable!("resolve_global returns imports and globals"),
+	}
+}
+
+fn type_signature(module: &elements::Module, type_index: u32) -> String {
+	match module.type_section().and_then(|section| section.types().get(type_index as usize)) {
+		Some(elements::Type::Function(func_type)) => signature(func_type),
+		None => format!("type[{}]", type_index),
+	}
+}
+
+fn function_signature(module: &elements::Module, index: u32) -> String {
+	function_type(module, index).map(signature).unwrap_or_default()
+}
+
+/// Locals and instructions of the function, with indices of functions, globals and types
+/// replaced by names and signatures.
+fn code_lines(module: &elements::Module, index: u32) -> Vec<String> {
+	let declared_index = index as usize - module.import_count(elements::ImportCountType::Function);
+	let body = &module.code_section().expect("Code section to exist").bodies()[declared_index];
+
+	let locals = body.locals().iter().map(|local| format!("local {} x{}", local.value_type(), local.count()));
+	let instructions = body.code().elements().iter().map(|instruction| instruction_line(module, instruction));
+	locals.chain(instructions).collect()
+}
+
+fn instruction_line(module: &elements::Module, instruction: &elements::Instruction) -> String {
+	use parity_wasm::elements::Instruction::*;
+	match instruction {
+		Call(index) => format!("call {}", function_key(module, *index)),
+		CallIndirect(type_index, _) => format!("call_indirect {}", type_signature(module, *type_index)),
+		GetGlobal(index) => format!("get_global {}", global_key(module, *index)),
+		SetGlobal(index) => format!("set_global {}", global_key(module, *index)),
+		BrTable(table) => format!("br_table {:?} {}", table.table, table.default),
+		instruction => instruction.to_string(),
+	}
+}
+
+fn limits(limits: &elements::ResizableLimits) -> String {
+	match limits.maximum() {
+		Some(maximum) => format!("initial {}, maximum {}", limits.initial(), maximum),
+		None => format!("initial {}", limits.initial()),
+	}
+}
+
+fn init_expr(module: &elements::Module, init: &elements::InitExpr) -> String {
+	init.code().iter()
+		.filter(|instruction| **instruction != elements::Instruction::End)
+		.map(|instruction| instruction_line(module, instruction))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+fn global_type(global_type: &elements::GlobalType) -> String {
+	if global_type.is_mutable() {
+		format!("mut {}", global_type.content_type())
+	} else {
+		global_type.content_type().to_string()
+	}
+}
+
+fn imports(module: &elements::Module) -> Vec<(String, String)> {
+	module.import_section().iter().flat_map(|section| section.entries()).map(|entry| {
+		let description = match entry.external() {
+			elements::External::Function(type_index) => format!("func {}", type_signature(module, *type_index)),
+			elements::External::Global(global) => format!("global {}", global_type(global)),
+			elements::External::Memory(memory) => format!("memory {}", limits(memory.limits())),
+			elements::External::Table(table) => format!("table {}", limits(table.limits())),
+		};
+		(format!("{}.{}", entry.module(), entry.field()), description)
+	}).collect()
+}
+
+fn exports(module: &elements::Module) -> Vec<(String, String)> {
+	module.export_section().iter().flat_map(|section| section.entries()).map(|entry| {
+		let description = match entry.internal() {
+			elements::Internal::Function(index) => format!("func {}", function_signature(module, *index)),
+			elements::Internal::Global(index) => format!("global {}", global_key(module, *index)),
+			elements::Internal::Memory(_) => "memory".to_owned(),
+			elements::Internal::Table(_) => "table".to_owned(),
+		};
+		(entry.field().to_owned(), description)
+	}).collect()
+}
+
+fn globals(module: &elements::Module) -> Vec<(String, String)> {
+	let imported = module.import_count(elements::ImportCountType::Global) as u32;
+	module.global_section().iter().flat_map(|section| section.entries()).enumerate().map(|(index, entry)| {
+		let description = format!("{} = {}", global_type(entry.global_type()), init_expr(module, entry.init_expr()));
+		(global_key(module, imported + index as u32), description)
+	}).collect()
+}
+
+fn memory(module: &elements::Module) -> Vec<(String, String)> {
+	let mut memories = Vec::new();
+	for entry in module.import_section().iter().flat_map(|section| section.entries()) {
+		if let elements::External::Memory(memory) = entry.external() {
+			memories.push(limits(memory.limits()));
+		}
+	}
+	for memory in module.memory_section().iter().flat_map(|section| section.entries()) {
+		memories.push(limits(memory.limits()));
+	}
+	memories.into_iter()
+		.enumerate()
+		.map(|(index, memory)| (format!("memory[{}]", index), memory))
+		.collect()
+}
+
+fn tables(module: &elements::Module) -> Vec<(String, String)> {
+	let mut tables = Vec::new();
+	for entry in module.import_section().iter().flat_map(|section| section.entries()) {
+		if let elements::External::Table(table) = entry.external() {
+			tables.push(limits(table.limits()));
+		}
+	}
+	for table in module.table_section().iter().flat_map(|section| section.entries()) {
+		tables.push(limits(table.limits()));
+	}
+	tables.into_iter()
+		.enumerate()
+		.map(|(index, table)| (format!("table[{}]", index), table))
+		.collect()
+}
+
+/// Placement of the segment in the memory or the table with the index.
+fn placement(module: &elements::Module, index: u32, offset: &Option<elements::InitExpr>) -> String {
+	match offset {
+		Some(offset) => format!("[{}] at {}", index, init_expr(module, offset)),
+		None => "passive".to_owned(),
+	}
+}
+
+/// FNV-1a hash, to tell segments with the same length apart.
+fn checksum(bytes: &[u8]) -> u32 {
+	bytes.iter().fold(0x811c_9dc5, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193))
+}
+
+fn data(module: &elements::Module) -> Vec<(String, String)> {
+	module.data_section().iter().flat_map(|section| section.entries()).enumerate().map(|(index, segment)| {
+		let description = format!(
+			"memory{}, {} bytes, checksum {:08x}",
+			placement(module, segment.index(), segment.offset()),
+			segment.value().len(),
+			checksum(segment.value()),
+		);
+		(format!("data[{}]", index), description)
+	}).collect()
+}
+
+fn element_segments(module: &elements::Module) -> Vec<(String, String)> {
+	module.elements_section().iter().flat_map(|section| section.entries()).enumerate().map(|(index, segment)| {
+		let members = segment.members().iter().map(|member| function_key(module, *member)).collect::<Vec<_>>();
+		let description = format!(
+			"table{}: {}",
+			placement(module, segment.index(), segment.offset()),
+			members.join(", "),
+		);
+		(format!("elem[{}]", index), description)
+	}).collect()
+}
+
+fn start(module: &elements::Module) -> Vec<(String, String)> {
+	module.start_section().map(|index| ("start".to_owned(), function_key(module, index))).into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements;
+	use super::*;
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	#[test]
+	fn structural_diff() {
+		let old = parse_wat(r#"
+			(module
+				(import "env" "ext" (func (param i32)))
+				(memory 1)
+				(global (mut i32) (i32.const 0))
+				(func (export "call")
+					i32.const 1
+					call 0
+					call 2)
+				(func (export "deploy")
+					i32.const 0
+					set_global 0)
+				(func (export "removed")))
+		"#);
+		let new = parse_wat(r#"
+			(module
+				(import "env" "new" (func))
+				(import "env" "ext" (func (param i32)))
+				(memory 1 2)
+				(global (mut i32) (i32.const 0))
+				(func (export "call")
+					i32.const 2
+					call 1
+					call 3)
+				(func (export "deploy")
+					i32.const 0
+					set_global 0)
+				(func (export "added")
+					call 0))
+		"#);
+
+		let diff = diff_modules(&old, &new);
+		assert_eq!(diff.imports, vec![Change::Added("env.new".into(), "func ()".into())]);
+		assert_eq!(diff.exports, vec![
+			Change::Removed("removed".into(), "func ()".into()),
+			Change::Added("added".into(), "func ()".into()),
+		]);
+		assert!(diff.globals.is_empty());
+		assert_eq!(diff.memory, vec![
+			Change::Changed("memory[0]".into(), "initial 1".into(), "initial 1, maximum 2".into()),
+		]);
+
+		// `deploy` is the same despite shifted indices
+		assert_eq!(diff.functions, vec![
+			FunctionDiff::Changed {
+				name: "call".into(),
+				signature: None,
+				code: vec![
+					CodeLine::Removed("i32.const 1".into()),
+					CodeLine::Added("i32.const 2".into()),
+					CodeLine::Same("call env.ext".into()),
+					CodeLine::Same("call deploy".into()),
+					CodeLine::Same("end".into()),
+				],
+			},
+			FunctionDiff::Removed("removed".into()),
+			FunctionDiff::Added("added".into()),
+		]);
+		assert!(diff_modules(&new, &new).is_empty());
+	}
+
+	#[test]
+	fn segments() {
+		let old = parse_wat(r#"
+			(module
+				(import "env" "ext" (func))
+				(memory 1)
+				(table 2 anyfunc)
+				(data (i32.const 8) "\01\02")
+				(elem (i32.const 0) $first $second)
+				(func $first)
+				(func $second (export "second"))
+				(start $first))
+		"#);
+		let new = parse_wat(r#"
+			(module
+				(import "env" "ext" (func))
+				(memory 1)
+				(table 2 4 anyfunc)
+				(data (i32.const 8) "\01\03")
+				(elem (i32.const 0) $second 0)
+				(func $first)
+				(func $second (export "second"))
+				(start $second))
+		"#);
+
+		let diff = diff_modules(&old, &new);
+		assert_eq!(diff.tables, vec![
+			Change::Changed("table[0]".into(), "initial 2".into(), "initial 2, maximum 4".into()),
+		]);
+		assert_eq!(diff.data.len(), 1);
+		assert!(matches!(&diff.data[0], Change::Changed(name, _, _) if name == "data[0]"));
+		assert_eq!(diff.elements, vec![
+			Change::Changed("elem[0]".into(), "table[0] at i32.const 0: func[0], second".into(), "table[0] at i32.const 0: second, env.ext".into()),
+		]);
+		assert_eq!(diff.start, vec![Change::Changed("start".into(), "func[0]".into(), "second".into())]);
+		assert!(!diff.is_empty());
+		assert!(diff_modules(&new, &new).is_empty());
+	}
+}
@@ -0,0 +1,197 @@
+//! NaN canonicalization, making float arithmetic deterministic.
+//!
+//! WebAssembly leaves the sign and payload of NaNs produced by arithmetic to the machine, so
+//! `f32.reinterpret` or a store of such a NaN differs between hosts. Results of instructions which
+//! may produce a NaN are passed through a function replacing any NaN with the canonical quiet one.
+
+use crate::std::string::String;
+use crate::std::vec::Vec;
+
+use parity_wasm::elements::{self, Instruction, ValueType};
+
+use crate::graph;
+use crate::pass::{self, Pass, Pipeline};
+
+/// Bits of the canonical quiet NaN of `f32`.
+const CANONICAL_F32: u32 = 0x7fc0_0000;
+/// Bits of the canonical quiet NaN of `f64`.
+const CANONICAL_F64: u64 = 0x7ff8_0000_0000_0000;
+
+/// Canonicalize every NaN produced by float arithmetic, see `CanonicalizeNans`.
+pub fn canonicalize_nans(module: elements::Module) -> Result<elements::Module, pass::Error> {
+	Pipeline::new()
+		.with_pass(CanonicalizeNans)
+		.run(&module)
+}
+
+/// Pass calling a canonicalization function after every instruction which can produce a NaN.
+///
+/// The functions are added to the module only if it needs them. To charge for the
+/// canonicalization, run the pass before `GasMetering`, which then meters the calls and the
+/// added functions as any other code.
+pub struct CanonicalizeNans;
+
+/// Float type produced by the instruction, if the result can be an arithmetic NaN.
+///
+/// `abs`, `neg`, `copysign`, loads and reinterpretations only move bits, so they are deterministic.
+fn nan_result(instruction: &Instruction) -> Option<ValueType> {
+	use Instruction::*;
+
+	match instruction {
+		F32Add | F32Sub | F32Mul | F32Div | F32Sqrt | F32Min | F32Max
+		| F32Ceil | F32Floor | F32Trunc | F32Nearest | F32DemoteF64 => Some(ValueType::F32),
+		F64Add | F64Sub | F64Mul | F64Div | F64Sqrt | F64Min | F64Max
+		| F64Ceil | F64Floor | F64Trunc | F64Nearest | F64PromoteF32 => Some(ValueType::F64),
+		_ => None,
+	}
+}
+
+/// Body of the function returning its parameter, or the canonical NaN if the parameter is NaN.
+fn canonicalize_body(value_type: ValueType) -> Vec<graph::Instruction> {
+	let (canonical, is_number) = match value_type {
+		ValueType::F64 => (
+			[Instruction::I64Const(CANONICAL_F64 as i64), Instruction::F64ReinterpretI64],
+			Instruction::F64Eq,
+		),
+		_ => (
+			[Instruction::I32Const(CANONICAL_F32 as i32), Instruction::F32ReinterpretI32],
+			Instruction::F32Eq,
+		),
+	};
+	// NaN is the only value not equal to itself
+	let mut body = vec![Instruction::GetLocal(0)];
+	body.extend(canonical.iter().cloned());
+	body.extend(vec![
+		Instruction::GetLocal(0),
+		Instruction::GetLocal(0),
+		is_number,
+		Instruction::Select,
+		Instruction::End,
+	]);
+	body.into_iter().map(graph::Instruction::Plain).collect()
+}
+
+impl Pass for CanonicalizeNans {
+	fn name(&self) -> &str {
+		"canonicalize-nans"
+	}
+
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		let mut used = Vec::new();
+		for func in module.funcs.iter() {
+			if let graph::ImportedOrDeclared::Declared(body) = &func.read().origin {
+				for instruction in body.code.iter() {
+					let value_type = match instruction {
+						graph::Instruction::Plain(instruction) => nan_result(instruction),
+						_ => None,
+					};
+					if let Some(value_type) = value_type.filter(|value_type| !used.contains(value_type)) {
+						used.push(value_type);
+					}
+				}
+			}
+		}
+
+		let mut canonicalize = |value_type| if used.contains(&value_type) {
+			Some(module.add_func(
+				elements::FunctionType::new(vec![value_type], vec![value_type]),
+				Vec::new(),
+				canonicalize_body(value_type),
+			))
+		} else {
+			None
+		};
+		let canonicalize_f32 = canonicalize(ValueType::F32);
+		let canonicalize_f64 = canonicalize(ValueType::F64);
+
+		pass::for_each_body(module, |_, code| {
+			let mut result = Vec::with_capacity(code.len());
+			for instruction in code.drain(..) {
+				let helper = match &instruction {
+					graph::Instruction::Plain(instruction) => match nan_result(instruction) {
+						Some(ValueType::F32) => canonicalize_f32.as_ref(),
+						Some(_) => canonicalize_f64.as_ref(),
+						None => None,
+					},
+					_ => None,
+				};
+				result.push(instruction);
+				if let Some(helper) = helper {
+					result.push(graph::Instruction::Call(helper.clone()));
+				}
+			}
+			*code = result;
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{inject_gas_counter, rules, GasMetering};
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	fn code(module: &elements::Module, index: usize) -> &[Instruction] {
+		module.code_section().expect("Module to have code").bodies()[index].code().elements()
+	}
+
+	#[test]
+	fn canonicalize() {
+		let module = canonicalize_nans(parse_wat(r#"
+			(module
+				(func (export "div") (param f32 f32) (result f32)
+					local.get 0
+					local.get 1
+					f32.div
+					f32.neg
+				)
+			)
+		"#)).expect("Canonicalization to succeed");
+
+		// Only the f32 function is added
+		assert_eq!(module.code_section().expect("Module to have code").bodies().len(), 2);
+		assert_eq!(code(&module, 0), &[
+			Instruction::GetLocal(0),
+			Instruction::GetLocal(1),
+			Instruction::F32Div,
+			Instruction::Call(1),
+			Instruction::F32Neg,
+			Instruction::End,
+		][..]);
+		assert_eq!(code(&module, 1)[1], Instruction::I32Const(0x7fc0_0000));
+
+		let untouched = parse_wat(r#"(module (func (param f64) (result f64) local.get 0 f64.abs))"#);
+		assert_eq!(canonicalize_nans(untouched.clone()).expect("Canonicalization to succeed"), untouched);
+	}
+
+	#[test]
+	fn metered_canonicalization() {
+		let module = parse_wat(r#"
+			(module
+				(func (export "mul") (param f64) (result f64)
+					local.get 0
+					local.get 0
+					f64.mul
+				)
+			)
+		"#);
+		let rules = rules::Set::default();
+
+		let metered = Pipeline::new()
+			.with_pass(CanonicalizeNans)
+			.with_pass(GasMetering::new(&rules, "env"))
+			.run(&module)
+			.expect("Pipeline to succeed");
+		let plain = inject_gas_counter(module, &rules, "env").expect("Gas to be injected");
+
+		// The call is charged in the caller, and the added function is metered itself
+		assert_eq!(code(&plain, 0)[0], Instruction::I32Const(3));
+		assert_eq!(code(&metered, 0)[0], Instruction::I32Const(4));
+		assert_eq!(code(&metered, 1)[..3], [Instruction::I32Const(7), Instruction::Call(0), Instruction::GetLocal(0)]);
+	}
+}
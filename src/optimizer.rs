@@ -3,11 +3,14 @@ use crate::std::collections::{HashSet as Set};
 #[cfg(not(features = "std"))]
 use crate::std::collections::{BTreeSet as Set};
 use crate::std::vec::Vec;
+use crate::std::string::String;
+use crate::std::borrow::ToOwned;
+use crate::std::collections::BTreeMap;
 use crate::std::mem;
 
 use log::trace;
-use parity_wasm::elements;
-use crate::symbols::{Symbol, expand_symbols, push_code_symbols, resolve_function};
+use parity_wasm::{builder, elements};
+use crate::symbols::{Symbol, expand_symbols_with_parents, function_type, push_code_symbols, resolve_function};
 
 #[derive(Debug)]
 pub enum Error {
@@ -16,68 +19,207 @@ pub enum Error {
 	NoExportSection,
 }
 
+/// Reason why the optimizer started from the symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Root {
+	/// Export which was requested to stay.
+	Export,
+	/// Start function of the module.
+	Start,
+	/// Member or offset expression of the element segment with the given index.
+	ElementSegment(usize),
+	/// Offset expression of the data segment with the given index.
+	DataSegment(usize),
+}
+
+/// Report of what the optimizer removed and why the rest was kept.
+///
+/// All symbols use the indices of the module before optimization.
+#[derive(Debug, Default)]
+pub struct Report {
+	/// Symbols removed from the module, in the order of elimination.
+	pub removed: Vec<Symbol>,
+	roots: BTreeMap<Symbol, Root>,
+	parents: BTreeMap<Symbol, Symbol>,
+	names: BTreeMap<Symbol, String>,
+}
+
+impl Report {
+	/// Name of the symbol, if known.
+	///
+	/// Functions are named using the name section, imports and exports by their field.
+	pub fn name(&self, symbol: Symbol) -> Option<&str> {
+		self.names.get(&symbol).map(|name| name.as_str())
+	}
+
+	/// All symbols with the given name.
+	pub fn lookup(&self, name: &str) -> Vec<Symbol> {
+		self.names.iter()
+			.filter(|(_, symbol_name)| *symbol_name == name)
+			.map(|(symbol, _)| *symbol)
+			.collect()
+	}
+
+	/// Whether the symbol was kept in the module.
+	pub fn is_kept(&self, symbol: Symbol) -> bool {
+		self.roots.contains_key(&symbol) || self.parents.contains_key(&symbol)
+	}
+
+	/// Chain of references that kept the symbol alive.
+	///
+	/// Chain starts with the symbol itself, each next symbol references the previous one,
+	/// and the last one was kept for the returned reason. Returns `None` if the symbol was removed.
+	pub fn retaining_path(&self, symbol: Symbol) -> Option<(Vec<Symbol>, &Root)> {
+		let mut path = vec![symbol];
+		let mut current = symbol;
+		loop {
+			if let Some(root) = self.roots.get(&current) {
+				return Some((path, root));
+			}
+			current = *self.parents.get(&current)?;
+			path.push(current);
+		}
+	}
+}
+
+/// Optimizer options.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+	precise_indirect_calls: bool,
+	keep_custom_sections: bool,
+}
+
+impl Options {
+	/// Keep functions from element segments only if the reachable code contains
+	/// `call_indirect` with the matching signature.
+	///
+	/// Removed members of element segments are replaced with a function that traps,
+	/// so the table indices stay the same. Modules which import or export the table
+	/// keep all members, since they can be called from outside.
+	pub fn with_precise_indirect_calls(mut self) -> Self {
+		self.precise_indirect_calls = true;
+		self
+	}
+
+	/// Leave custom sections in place instead of dropping all of them.
+	///
+	/// Useful when the module is stripped separately with `strip_custom_sections`.
+	pub fn with_custom_sections_kept(mut self) -> Self {
+		self.keep_custom_sections = true;
+		self
+	}
+}
+
 pub fn optimize(
 	module: &mut elements::Module, // Module to optimize
 	used_exports: Vec<&str>,       // List of only exports that will be usable after optimization
 ) -> Result<(), Error> {
+	optimize_with_report(module, used_exports).map(|_| ())
+}
+
+/// Same as `optimize`, but also returns the report of removed and kept symbols.
+pub fn optimize_with_report(
+	module: &mut elements::Module,
+	used_exports: Vec<&str>,
+) -> Result<Report, Error> {
+	optimize_with_options(module, used_exports, &Options::default())
+}
+
+/// Same as `optimize_with_report`, but with non-default options.
+pub fn optimize_with_options(
+	module: &mut elements::Module,
+	used_exports: Vec<&str>,
+	options: &Options,
+) -> Result<Report, Error> {
 	// WebAssembly exports optimizer
 	// Motivation: emscripten compiler backend compiles in many unused exports
 	//   which in turn compile in unused imports and leaves unused functions
 
-	// try to parse name section
-	let module_temp = mem::take(module);
-	let module_temp = module_temp
-		.parse_names()
-		.unwrap_or_else(|(_err, module)| module);
-	*module = module_temp;
+	// try to parse name section, dropping subsections parity-wasm does not understand,
+	//   so that the function and local names are remapped instead of being lost
+	parse_names(module);
+
+	let mut report = Report {
+		names: symbol_names(module),
+		..Default::default()
+	};
+
+	// The host or other modules can call any member of a shared table
+	let precise_indirect_calls = options.precise_indirect_calls && !has_shared_table(module);
 
 	// Algo starts from the top, listing all items that should stay
-	let mut stay = Set::new();
 	for (index, entry) in module.export_section().ok_or(Error::NoExportSection)?.entries().iter().enumerate() {
 		if used_exports.iter().any(|e| *e == entry.field()) {
-			stay.insert(Symbol::Export(index));
+			report.roots.insert(Symbol::Export(index), Root::Export);
 		}
 	}
 
 	// If there is start function in module, it should stary
-	module.start_section().map(|ss| stay.insert(resolve_function(&module, ss)));
+	if let Some(ss) = module.start_section() {
+		report.roots.entry(resolve_function(&module, ss)).or_insert(Root::Start);
+	}
 
 	// All symbols used in data/element segments are also should be preserved
-	let mut init_symbols = Vec::new();
 	if let Some(data_section) = module.data_section() {
-		for segment in data_section.entries() {
-			push_code_symbols(
-				&module,
-				segment
-					.offset()
-					.as_ref()
-					.expect("parity-wasm is compiled without bulk-memory operations")
-					.code(),
-				&mut init_symbols,
-			);
+		for (index, segment) in data_section.entries().iter().enumerate() {
+			let mut init_symbols = Vec::new();
+			// Passive segments have no offset
+			if let Some(offset) = segment.offset() {
+				push_code_symbols(&module, offset.code(), &mut init_symbols);
+			}
+			for symbol in init_symbols.drain(..) {
+				report.roots.entry(symbol).or_insert(Root::DataSegment(index));
+			}
 		}
 	}
 	if let Some(elements_section) = module.elements_section() {
-		for segment in elements_section.entries() {
-			push_code_symbols(
-				&module,
-				segment
-					.offset()
-					.as_ref()
-					.expect("parity-wasm is compiled without bulk-memory operations")
-					.code(),
-				&mut init_symbols
-			);
-			for func_index in segment.members() {
-				stay.insert(resolve_function(&module, *func_index));
+		for (index, segment) in elements_section.entries().iter().enumerate() {
+			let mut init_symbols = Vec::new();
+			if let Some(offset) = segment.offset() {
+				push_code_symbols(&module, offset.code(), &mut init_symbols);
+			}
+			// With precise indirect calls members are added only when some reachable
+			//   code can call them (see below)
+			if !precise_indirect_calls {
+				for func_index in segment.members() {
+					init_symbols.push(resolve_function(&module, *func_index));
+				}
+			}
+			for symbol in init_symbols.drain(..) {
+				report.roots.entry(symbol).or_insert(Root::ElementSegment(index));
 			}
 		}
 	}
-	for symbol in init_symbols.drain(..) { stay.insert(symbol); }
+	let mut stay: Set<Symbol> = report.roots.keys().cloned().collect();
 
 	// Call function which will traverse the list recursively, filling stay with all symbols
 	// that are already used by those which already there
-	expand_symbols(module, &mut stay);
+	expand_symbols_with_parents(module, &mut stay, &mut report.parents);
+
+	if precise_indirect_calls {
+		// Members of element segments can be reached only through `call_indirect`, so they
+		//   stay only if their signature is called indirectly by code that stays. Newly added
+		//   members can contain indirect calls themselves, so repeat until nothing changes.
+		loop {
+			let called_types = indirectly_called_types(module, &stay);
+			let mut added = false;
+			for (index, segment) in module.elements_section().iter().flat_map(|s| s.entries()).enumerate() {
+				for func_index in segment.members() {
+					let symbol = resolve_function(module, *func_index);
+					if stay.contains(&symbol) { continue; }
+					if matches!(function_type(module, *func_index), Some(t) if called_types.contains(t)) {
+						report.roots.insert(symbol, Root::ElementSegment(index));
+						stay.insert(symbol);
+						added = true;
+					}
+				}
+			}
+			if !added { break; }
+			expand_symbols_with_parents(module, &mut stay, &mut report.parents);
+		}
+
+		replace_removed_members(module, &mut stay);
+	}
 
 	for symbol in stay.iter() {
 		trace!("symbol to stay: {:?}", symbol);
@@ -103,6 +245,7 @@ pub fn optimize(
 					.expect("If type section does not exists, the loop will break at the beginning of first iteration")
 					.types_mut().remove(index);
 				eliminated_types.push(old_index);
+				report.removed.push(Symbol::Type(old_index));
 				trace!("Eliminated type({})", old_index);
 			}
 			old_index += 1;
@@ -125,6 +268,7 @@ pub fn optimize(
 					} else {
 						remove = true;
 						eliminated_funcs.push(top_funcs);
+						report.removed.push(Symbol::Import(old_index));
 						trace!("Eliminated import({}) func({}, {})", old_index, top_funcs, imports.entries()[index].field());
 					}
 					top_funcs += 1;
@@ -135,6 +279,7 @@ pub fn optimize(
 					} else {
 						remove = true;
 						eliminated_globals.push(top_globals);
+						report.removed.push(Symbol::Import(old_index));
 						trace!("Eliminated import({}) global({}, {})", old_index, top_globals, imports.entries()[index].field());
 					}
 					top_globals += 1;
@@ -165,6 +310,7 @@ pub fn optimize(
 			} else {
 				globals.entries_mut().remove(index);
 				eliminated_globals.push(top_globals + old_index);
+				report.removed.push(Symbol::Global(old_index));
 				trace!("Eliminated global({})", top_globals + old_index);
 			}
 			old_index += 1;
@@ -185,6 +331,7 @@ pub fn optimize(
 				code_section(module).expect("Code section to exist").bodies_mut().remove(index);
 
 				eliminated_funcs.push(top_funcs + old_index);
+				report.removed.push(Symbol::Function(old_index));
 				trace!("Eliminated function({})", top_funcs + old_index);
 			}
 			old_index += 1;
@@ -204,6 +351,7 @@ pub fn optimize(
 				index += 1;
 			} else {
 				trace!("Eliminated export({}, {})", old_index, exports.entries_mut()[index].field());
+				report.removed.push(Symbol::Export(old_index));
 				exports.entries_mut().remove(index);
 			}
 			old_index += 1;
@@ -273,26 +421,16 @@ pub fn optimize(
 				},
 				elements::Section::Data(data_section) => {
 					for segment in data_section.entries_mut() {
-						update_global_index(
-							segment
-								.offset_mut()
-								.as_mut()
-								.expect("parity-wasm is compiled without bulk-memory operations")
-								.code_mut(),
-							&eliminated_globals,
-						)
+						if let Some(offset) = segment.offset_mut() {
+							update_global_index(offset.code_mut(), &eliminated_globals)
+						}
 					}
 				},
 				elements::Section::Element(elements_section) => {
 					for segment in elements_section.entries_mut() {
-						update_global_index(
-							segment
-							.offset_mut()
-							.as_mut()
-							.expect("parity-wasm is compiled without bulk-memory operations")
-							.code_mut(),
-							&eliminated_globals
-						);
+						if let Some(offset) = segment.offset_mut() {
+							update_global_index(offset.code_mut(), &eliminated_globals);
+						}
 						// update all indirect call addresses initial values
 						for func_index in segment.members_mut() {
 							let totalle = eliminated_funcs.iter().take_while(|i| (**i as u32) < *func_index).count();
@@ -301,29 +439,7 @@ pub fn optimize(
 					}
 				},
 				elements::Section::Name(name_section) => {
-					if let Some(func_name) = name_section.functions_mut() {
-						let mut func_name_map = mem::take(func_name.names_mut());
-						for index in &eliminated_funcs {
-							func_name_map.remove(*index as u32);
-						}
-						let updated_map = func_name_map.into_iter().map(|(index, value)| {
-							let totalle = eliminated_funcs.iter().take_while(|i| (**i as u32) < index).count() as u32;
-							(index - totalle, value)
-						}).collect();
-						*func_name.names_mut() = updated_map;
-					}
-
-					if let Some(local_name) = name_section.locals_mut() {
-						let mut local_names_map = mem::take(local_name.local_names_mut());
-						for index in &eliminated_funcs {
-							local_names_map.remove(*index as u32);
-						}
-						let updated_map = local_names_map.into_iter().map(|(index, value)| {
-							let totalle = eliminated_funcs.iter().take_while(|i| (**i as u32) < index).count() as u32;
-							(index - totalle, value)
-						}).collect();
-						*local_name.local_names_mut() = updated_map;
-					}
+					update_name_section(name_section, &eliminated_funcs);
 				}
 				_ => { }
 			}
@@ -331,10 +447,125 @@ pub fn optimize(
 	}
 
 	// Also drop all custom sections
-	module.sections_mut()
-		.retain(|section| if let elements::Section::Custom(_) = section { false } else { true });
+	if !options.keep_custom_sections {
+		module.sections_mut()
+			.retain(|section| if let elements::Section::Custom(_) = section { false } else { true });
+	} else if !eliminated_funcs.is_empty() {
+		// Name section that failed to parse is not remapped and would name wrong functions
+		module.sections_mut()
+			.retain(|section| !matches!(section, elements::Section::Custom(custom) if custom.name() == "name"));
+	}
+
+	Ok(report)
+}
+
+/// Signatures used by `call_indirect` instructions of the staying functions.
+fn indirectly_called_types(module: &elements::Module, stay: &Set<Symbol>) -> Vec<elements::FunctionType> {
+	let types = module.type_section().map(|section| section.types()).unwrap_or(&[]);
+	let bodies = module.code_section().map(|section| section.bodies()).unwrap_or(&[]);
+
+	let mut called_types = Vec::new();
+	for symbol in stay.iter() {
+		let body = match symbol {
+			Symbol::Function(index) => &bodies[*index],
+			_ => continue,
+		};
+		for instruction in body.code().elements() {
+			if let elements::Instruction::CallIndirect(type_index, _) = instruction {
+				if let Some(elements::Type::Function(func_type)) = types.get(*type_index as usize) {
+					if !called_types.contains(func_type) {
+						called_types.push(func_type.clone());
+					}
+				}
+			}
+		}
+	}
+	called_types
+}
 
-	Ok(())
+/// Points members of element segments that are not going to stay to a function which traps.
+/// Whether the table is imported or exported.
+fn has_shared_table(module: &elements::Module) -> bool {
+	let imported = module.import_section().iter().flat_map(|s| s.entries())
+		.any(|entry| matches!(entry.external(), elements::External::Table(_)));
+	let exported = module.export_section().iter().flat_map(|s| s.entries())
+		.any(|entry| matches!(entry.internal(), elements::Internal::Table(_)));
+	imported || exported
+}
+
+fn replace_removed_members(module: &mut elements::Module, stay: &mut Set<Symbol>) {
+	let mut removed_members = Vec::new();
+	for (index, segment) in module.elements_section().iter().flat_map(|s| s.entries()).enumerate() {
+		for (position, func_index) in segment.members().iter().enumerate() {
+			if !stay.contains(&resolve_function(module, *func_index)) {
+				removed_members.push((index, position));
+			}
+		}
+	}
+	if removed_members.is_empty() { return; }
+
+	let mut mbuilder = builder::from_module(mem::take(module));
+	let location = mbuilder.push_function(
+		builder::function()
+			.signature().build()
+			.body()
+				.with_instructions(elements::Instructions::new(vec![
+					elements::Instruction::Unreachable,
+					elements::Instruction::End,
+				]))
+				.build()
+			.build()
+	);
+	*module = mbuilder.build();
+
+	let type_ref = module
+		.function_section().expect("Function was just pushed")
+		.entries()[location.body as usize]
+		.type_ref();
+	stay.insert(Symbol::Function(location.body as usize));
+	stay.insert(Symbol::Type(type_ref as usize));
+
+	let trap_index = module.import_count(elements::ImportCountType::Function) as u32 + location.body;
+	for section in module.sections_mut() {
+		if let elements::Section::Element(elements_section) = section {
+			for (index, position) in removed_members.iter() {
+				trace!("Replaced element({}, {}) with trap function", index, position);
+				elements_section.entries_mut()[*index].members_mut()[*position] = trap_index;
+			}
+		}
+	}
+}
+
+/// Names of the module symbols known before optimization.
+fn symbol_names(module: &elements::Module) -> BTreeMap<Symbol, String> {
+	let mut names = BTreeMap::new();
+
+	let mut imported_funcs = 0;
+	if let Some(import_section) = module.import_section() {
+		for (index, entry) in import_section.entries().iter().enumerate() {
+			if let elements::External::Function(_) = entry.external() {
+				imported_funcs += 1;
+			}
+			names.insert(Symbol::Import(index), entry.field().to_owned());
+		}
+	}
+
+	if let Some(func_names) = module.names_section().and_then(|section| section.functions()) {
+		let declared_funcs = module.function_section().map(|section| section.entries().len()).unwrap_or(0);
+		for index in 0..declared_funcs {
+			if let Some(name) = func_names.names().get((imported_funcs + index) as u32) {
+				names.insert(Symbol::Function(index), name.to_owned());
+			}
+		}
+	}
+
+	if let Some(export_section) = module.export_section() {
+		for (index, entry) in export_section.entries().iter().enumerate() {
+			names.insert(Symbol::Export(index), entry.field().to_owned());
+		}
+	}
+
+	names
 }
 
 
@@ -349,6 +580,33 @@ pub fn update_call_index(instructions: &mut elements::Instructions, eliminated_i
 	}
 }
 
+/// Updates function and local names considering the _ordered_ list of eliminated function indices
+pub fn update_name_section(name_section: &mut elements::NameSection, eliminated_funcs: &[usize]) {
+	if let Some(func_name) = name_section.functions_mut() {
+		let mut func_name_map = mem::take(func_name.names_mut());
+		for index in eliminated_funcs {
+			func_name_map.remove(*index as u32);
+		}
+		let updated_map = func_name_map.into_iter().map(|(index, value)| {
+			let totalle = eliminated_funcs.iter().take_while(|i| (**i as u32) < index).count() as u32;
+			(index - totalle, value)
+		}).collect();
+		*func_name.names_mut() = updated_map;
+	}
+
+	if let Some(local_name) = name_section.locals_mut() {
+		let mut local_names_map = mem::take(local_name.local_names_mut());
+		for index in eliminated_funcs {
+			local_names_map.remove(*index as u32);
+		}
+		let updated_map = local_names_map.into_iter().map(|(index, value)| {
+			let totalle = eliminated_funcs.iter().take_while(|i| (**i as u32) < index).count() as u32;
+			(index - totalle, value)
+		}).collect();
+		*local_name.local_names_mut() = updated_map;
+	}
+}
+
 /// Updates global references considering the _ordered_ list of eliminated indices
 pub fn update_global_index(instructions: &mut Vec<elements::Instruction>, eliminated_indices: &[usize]) {
 	use parity_wasm::elements::Instruction::*;
@@ -376,6 +634,72 @@ pub fn update_type_index(instructions: &mut elements::Instructions, eliminated_i
 	}
 }
 
+/// Parses the name section in place, so that it can be updated together with the indices.
+///
+/// If the name section cannot be parsed, it stays as a custom section.
+pub fn parse_names(module: &mut elements::Module) {
+	retain_known_name_subsections(module);
+	let module_temp = mem::take(module);
+	let module_temp = module_temp
+		.parse_names()
+		.unwrap_or_else(|(_err, module)| module);
+	*module = module_temp;
+}
+
+/// Ids of the name subsections that parity-wasm is able to parse (module, function and local names)
+const KNOWN_NAME_SUBSECTIONS: [u8; 3] = [0, 1, 2];
+
+fn read_var_u32(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+	let mut result = 0u32;
+	let mut shift = 0;
+	loop {
+		let byte = *bytes.get(*pos)?;
+		*pos += 1;
+		if shift > 28 { return None; }
+		result |= ((byte & 0x7f) as u32) << shift;
+		if byte & 0x80 == 0 { return Some(result); }
+		shift += 7;
+	}
+}
+
+/// Removes name subsections that are not known to parity-wasm from the custom "name" section.
+///
+/// Recent toolchains emit extended name subsections (labels, types, globals, data segments)
+/// which make `parse_names` fail, and unparsed name section is then dropped together with
+/// other custom sections. Malformed payloads are left untouched.
+fn retain_known_name_subsections(module: &mut elements::Module) {
+	for section in module.sections_mut() {
+		let custom = match section {
+			elements::Section::Custom(custom) if custom.name() == "name" => custom,
+			_ => continue,
+		};
+
+		let payload = custom.payload();
+		let mut retained = Vec::with_capacity(payload.len());
+		let mut pos = 0;
+		let mut malformed = false;
+		while pos < payload.len() {
+			let start = pos;
+			let subsection_id = payload[pos];
+			pos += 1;
+			let end = match read_var_u32(payload, &mut pos).map(|size| pos + size as usize) {
+				Some(end) if end <= payload.len() => end,
+				_ => { malformed = true; break; }
+			};
+			if KNOWN_NAME_SUBSECTIONS.contains(&subsection_id) {
+				retained.extend_from_slice(&payload[start..end]);
+			} else {
+				trace!("Dropped name subsection {}", subsection_id);
+			}
+			pos = end;
+		}
+
+		if !malformed {
+			*custom.payload_mut() = retained;
+		}
+	}
+}
+
 pub fn import_section(module: &mut elements::Module) -> Option<&mut elements::ImportSection> {
    for section in module.sections_mut() {
 		if let elements::Section::Import(sect) = section {
@@ -664,4 +988,315 @@ mod tests {
 		}
 	}
 
+
+	fn names_module() -> elements::Module {
+		builder::module()
+			.function()
+				.signature().with_param(elements::ValueType::I32).build()
+				.body()
+					.with_instructions(elements::Instructions::new(
+						vec![
+							elements::Instruction::I32Const(0),
+							elements::Instruction::Call(2),
+							elements::Instruction::End
+						]
+					))
+					.build()
+				.build()
+			.function()
+				.signature().with_param(elements::ValueType::I32).with_param(elements::ValueType::I32).build()
+				.build()
+			.function()
+				.signature().with_param(elements::ValueType::I32).build()
+				.build()
+			.export()
+				.field("_call")
+				.internal().func(0).build()
+			.export()
+				.field("_random")
+				.internal().func(1).build()
+			.build()
+	}
+
+	fn names() -> elements::NameSection {
+		let mut functions = elements::FunctionNameSubsection::default();
+		functions.names_mut().insert(0, "call".to_owned());
+		functions.names_mut().insert(1, "random".to_owned());
+		functions.names_mut().insert(2, "helper".to_owned());
+
+		let mut locals = elements::LocalNameSubsection::default();
+		let mut random_locals = elements::NameMap::default();
+		random_locals.insert(1, "seed".to_owned());
+		locals.local_names_mut().insert(1, random_locals);
+		let mut helper_locals = elements::NameMap::default();
+		helper_locals.insert(0, "arg".to_owned());
+		locals.local_names_mut().insert(2, helper_locals);
+
+		elements::NameSection::new(
+			Some(elements::ModuleNameSubsection::new("contract")),
+			Some(functions),
+			Some(locals),
+		)
+	}
+
+	fn assert_remapped_names(module: &elements::Module) {
+		let names = module.names_section().expect("name section to be preserved");
+
+		assert_eq!(names.module().map(|m| m.name()), Some("contract"));
+
+		let functions = names.functions().expect("function names to be preserved").names();
+		assert_eq!(functions.get(0).map(|n| n.as_str()), Some("call"));
+		assert_eq!(
+			functions.get(1).map(|n| n.as_str()),
+			Some("helper"),
+			"Function #2 becomes #1 after _random is eliminated"
+		);
+		assert_eq!(functions.get(2), None);
+
+		let locals = names.locals().expect("local names to be preserved").local_names();
+		assert_eq!(locals.get(1).and_then(|l| l.get(0)).map(|n| n.as_str()), Some("arg"));
+		assert!(locals.get(2).is_none());
+	}
+
+	/// @spec 5
+	/// Imagine the unoptimized module has a name section describing all of its functions and
+	/// some of their locals. After `_random` is eliminated, names of the eliminated function
+	/// should vanish and the remaining function and local names should follow new indices.
+	#[test]
+	fn name_section() {
+		let mut module = names_module();
+		module.sections_mut().push(elements::Section::Name(names()));
+
+		optimize(&mut module, vec!["_call"]).expect("optimizer to succeed");
+
+		assert_remapped_names(&module);
+	}
+
+	/// @spec 6
+	/// Name section produced by recent toolchains contains subsections parity-wasm
+	/// cannot parse (e.g. global names). These should be dropped, while the function and
+	/// local names should be preserved and remapped.
+	#[test]
+	fn name_section_with_unknown_subsections() {
+		use parity_wasm::elements::Serialize;
+
+		let mut payload = Vec::new();
+		names().serialize(&mut payload).expect("name section to serialize");
+		// global names subsection: one entry naming global #0 "g"
+		payload.extend_from_slice(&[7, 4, 1, 0, 1, b'g']);
+
+		let mut module = names_module();
+		module.sections_mut().push(elements::Section::Custom(
+			elements::CustomSection::new("name".to_owned(), payload)
+		));
+
+		optimize(&mut module, vec!["_call"]).expect("optimizer to succeed");
+
+		assert_remapped_names(&module);
+	}
+
+	/// @spec 7
+	/// Optimizer can report what was removed and why other symbols stayed. Function `helper`
+	/// is kept because it is called by `call`, which is exported, while `random` is removed
+	/// together with its export.
+	#[test]
+	fn report() {
+		let mut module = names_module();
+		module.sections_mut().push(elements::Section::Name(names()));
+
+		let report = optimize_with_report(&mut module, vec!["_call"]).expect("optimizer to succeed");
+
+		assert_eq!(report.removed, vec![Symbol::Type(1), Symbol::Function(1), Symbol::Export(1)]);
+		assert_eq!(report.lookup("random"), vec![Symbol::Function(1)]);
+		assert!(!report.is_kept(Symbol::Function(1)));
+		assert!(report.retaining_path(Symbol::Function(1)).is_none());
+
+		let (path, root) = report.retaining_path(Symbol::Function(2)).expect("helper to be kept");
+		assert_eq!(path, vec![Symbol::Function(2), Symbol::Function(0), Symbol::Export(0)]);
+		assert_eq!(*root, Root::Export);
+		assert_eq!(report.name(Symbol::Function(2)), Some("helper"));
+		assert_eq!(report.name(Symbol::Export(0)), Some("_call"));
+	}
+
+	fn indirect_module() -> elements::Module {
+		builder::module()
+			.function()
+				.signature().build()
+				.body()
+					.with_instructions(elements::Instructions::new(
+						vec![
+							elements::Instruction::I32Const(0),
+							elements::Instruction::I32Const(0),
+							elements::Instruction::CallIndirect(1, 0),
+							elements::Instruction::End
+						]
+					))
+					.build()
+				.build()
+			.function()
+				.signature().with_param(elements::ValueType::I32).build()
+				.build()
+			.function()
+				.signature().with_result(elements::ValueType::I32).build()
+				.body()
+					.with_instructions(elements::Instructions::new(
+						vec![
+							elements::Instruction::I32Const(1),
+							elements::Instruction::End
+						]
+					))
+					.build()
+				.build()
+			.table()
+				.with_min(2)
+				.with_element(0, vec![1, 2])
+				.build()
+			.export()
+				.field("_call")
+				.internal().func(0).build()
+			.build()
+	}
+
+	/// @spec 8
+	/// By default all functions from the table stay, since any of them could be
+	/// called indirectly.
+	#[test]
+	fn table_members_stay() {
+		let mut module = indirect_module();
+
+		optimize(&mut module, vec!["_call"]).expect("optimizer to succeed");
+
+		assert_eq!(module.function_section().expect("function section to stay").entries().len(), 3);
+		assert_eq!(module.elements_section().expect("element section to stay").entries()[0].members(), &[1, 2]);
+	}
+
+	/// @spec 9
+	/// With precise indirect calls, the table member with signature that is never called
+	/// indirectly is removed and its slot in the table points to a function that traps.
+	#[test]
+	fn precise_indirect_calls() {
+		let mut module = indirect_module();
+
+		let report = optimize_with_options(
+			&mut module,
+			vec!["_call"],
+			&Options::default().with_precise_indirect_calls(),
+		).expect("optimizer to succeed");
+
+		assert!(report.removed.contains(&Symbol::Function(2)));
+		assert_eq!(
+			report.retaining_path(Symbol::Function(1)).map(|(_, root)| root.clone()),
+			Some(Root::ElementSegment(0))
+		);
+
+		assert_eq!(
+			module.function_section().expect("function section to stay").entries().len(),
+			3,
+			"Function #2 is replaced with the trap function"
+		);
+		assert_eq!(module.type_section().expect("type section to stay").types().len(), 2);
+		assert_eq!(module.elements_section().expect("element section to stay").entries()[0].members(), &[1, 2]);
+		assert_eq!(
+			module.code_section().expect("code section to stay").bodies()[2].code().elements(),
+			&[elements::Instruction::Unreachable, elements::Instruction::End]
+		);
+	}
+
+	/// With precise indirect calls, members of an exported table stay, since the host
+	/// can call them indirectly.
+	#[test]
+	fn precise_indirect_calls_exported_table() {
+		let mut module = builder::from_module(indirect_module())
+			.export()
+				.field("table")
+				.internal().table(0).build()
+			.build();
+
+		let report = optimize_with_options(
+			&mut module,
+			vec!["_call", "table"],
+			&Options::default().with_precise_indirect_calls(),
+		).expect("optimizer to succeed");
+
+		assert!(report.removed.is_empty());
+		assert_eq!(module.function_section().expect("function section to stay").entries().len(), 3);
+		assert_eq!(module.elements_section().expect("element section to stay").entries()[0].members(), &[1, 2]);
+		assert_eq!(
+			module.code_section().expect("code section to stay").bodies()[2].code().elements(),
+			&[elements::Instruction::I32Const(1), elements::Instruction::End]
+		);
+	}
+
+	#[test]
+	fn kept_custom_sections() {
+		// Payload of the name section is malformed, so it can't be parsed and remapped
+		let with_sections = |mut module: elements::Module| {
+			for name in &["name", "producers"] {
+				module.sections_mut().push(elements::Section::Custom(
+					elements::CustomSection::new(name.to_string(), vec![0xff])
+				));
+			}
+			module
+		};
+		let custom_sections = |module: &elements::Module| {
+			module.custom_sections().map(|section| section.name().to_string()).collect::<Vec<_>>()
+		};
+		let options = Options::default().with_custom_sections_kept();
+
+		let mut module = with_sections(builder::module()
+			.function()
+				.signature().build()
+				.build()
+			.function()
+				.signature().build()
+				.build()
+			.export()
+				.field("_call")
+				.internal().func(1)
+				.build()
+			.build());
+		optimize_with_options(&mut module, vec!["_call"], &options).expect("optimizer to succeed");
+		assert_eq!(custom_sections(&module), vec!["producers"]);
+
+		let mut module = with_sections(builder::module()
+			.function()
+				.signature().build()
+				.build()
+			.export()
+				.field("_call")
+				.internal().func(0)
+				.build()
+			.build());
+		optimize_with_options(&mut module, vec!["_call"], &options).expect("optimizer to succeed");
+		assert_eq!(custom_sections(&module), vec!["name", "producers"]);
+	}
+
+	#[cfg(feature = "bulk")]
+	#[test]
+	fn passive_segments() {
+		let mut module = builder::module()
+			.global()
+				.value_type().i32()
+				.build()
+			.function()
+				.signature().build()
+				.build()
+			.export()
+				.field("_call")
+				.internal().func(0)
+				.build()
+			.build();
+		module.sections_mut().push(elements::Section::Element(elements::ElementSection::with_entries(vec![
+			elements::ElementSegment::new(0, None, vec![0]),
+		])));
+		module.sections_mut().push(elements::Section::Data(elements::DataSection::with_entries(vec![
+			elements::DataSegment::new(0, None, vec![1, 2, 3]),
+		])));
+
+		optimize(&mut module, vec!["_call"]).expect("optimizer to succeed");
+
+		assert_eq!(module.global_section().map_or(0, |section| section.entries().len()), 0, "Unused global is removed");
+		assert_eq!(module.elements_section().expect("element section to stay").entries()[0].members(), &[0]);
+		assert_eq!(module.data_section().expect("data section to stay").entries()[0].value(), &[1, 2, 3]);
+	}
 }
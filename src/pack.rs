@@ -1,14 +1,14 @@
+use crate::std::cell::Cell;
 use crate::std::fmt;
 use crate::std::vec::Vec;
 use crate::std::borrow::ToOwned;
 
-use parity_wasm::elements::{
-	self, Section, DataSection, Instruction, DataSegment, InitExpr, Internal, External,
-	ImportCountType,
-};
-use parity_wasm::builder;
+use crate::std::string::{String, ToString};
+
+use parity_wasm::elements::{self, Instruction, ValueType};
 use super::TargetRuntime;
-use super::gas::update_call_index;
+use crate::graph::{self, ExportLocal, ImportedOrDeclared, SegmentLocation};
+use crate::pass::{Pass, Pipeline};
 
 /// Pack error.
 ///
@@ -44,176 +44,127 @@ impl fmt::Display for Error {
 /// If a pwasm module has an exported function matching "create" symbol we want to pack it into "constructor".
 /// `raw_module` is the actual contract code
 /// `ctor_module` is the constructor which should return `raw_module`
-pub fn pack_instance(raw_module: Vec<u8>, mut ctor_module: elements::Module, target: &TargetRuntime) -> Result<elements::Module, Error> {
-
-	// Total number of constructor module import functions
-	let ctor_import_functions = ctor_module.import_section().map(|x| x.functions()).unwrap_or(0);
-
-	// We need to find an internal ID of function which is exported as `symbols().create`
-	// in order to find it in the Code section of the module
-	let mut create_func_id = {
-		let found_entry = ctor_module.export_section().ok_or(Error::NoExportSection)?.entries().iter()
-			.find(|entry| target.symbols().create == entry.field()).ok_or_else(|| Error::NoCreateSymbol(target.symbols().create))?;
-
-		let function_index: usize = match found_entry.internal() {
-			Internal::Function(index) => *index as usize,
-			_ => { return Err(Error::InvalidCreateMember(target.symbols().create)) },
-		};
+pub fn pack_instance(raw_module: Vec<u8>, ctor_module: elements::Module, target: &TargetRuntime) -> Result<elements::Module, Error> {
+	let error = Cell::new(None);
+	let pipeline = Pipeline::new()
+		.with_pass(TypedPack { pack: Pack::new(&raw_module, target), error: &error });
+	pipeline.run(&ctor_module).map_err(|_| error.take().unwrap_or(Error::MalformedModule))
+}
 
-		// Calculates a function index within module's function section
-		let function_internal_index = function_index - ctor_import_functions;
+/// Pass packing the contract code into the constructor, see `pack_instance`.
+pub struct Pack<'a> {
+	raw_module: &'a [u8],
+	target: &'a TargetRuntime,
+}
 
-		// Constructor should be of signature `func()` (void), fail otherwise
-		let type_id = ctor_module.function_section().ok_or(Error::NoCodeSection)?
-			.entries().get(function_index - ctor_import_functions).ok_or(Error::MalformedModule)?
-			.type_ref();
+impl<'a> Pack<'a> {
+	/// New pass packing `raw_module` for the `target` runtime.
+	pub fn new(raw_module: &'a [u8], target: &'a TargetRuntime) -> Self {
+		Pack { raw_module, target }
+	}
 
-		let elements::Type::Function(func) = ctor_module.type_section().ok_or(Error::NoTypeSection)?
-			.types().get(type_id as usize).ok_or(Error::MalformedModule)?;
+	fn pack(&self, module: &mut graph::Module) -> Result<(), Error> {
+		let symbols = self.target.symbols();
 
-		// Deploy should have no arguments and also should return nothing
-		if !func.params().is_empty() {
-			return Err(Error::InvalidCreateSignature(target.symbols().create));
-		}
-		if !func.results().is_empty() {
-			return Err(Error::InvalidCreateSignature(target.symbols().create));
+		// We need to find the function which is exported as `symbols().create`
+		if module.exports.is_empty() {
+			return Err(Error::NoExportSection);
 		}
+		let create_export = module.exports.iter()
+			.position(|entry| symbols.create == entry.name)
+			.ok_or(Error::NoCreateSymbol(symbols.create))?;
+		let create_func = match &module.exports[create_export].local {
+			ExportLocal::Func(func_ref) => func_ref.clone(),
+			_ => return Err(Error::InvalidCreateMember(symbols.create)),
+		};
 
-		function_internal_index
-	};
-
-	let ret_function_id = {
-		let mut id = 0;
-		let mut found = false;
-		for entry in ctor_module.import_section().ok_or(Error::NoImportSection)?.entries().iter() {
-			if let External::Function(_) = *entry.external() {
-				if entry.field() == target.symbols().ret { found = true; break; }
-				else { id += 1; }
+		// Constructor should be of signature `func()` (void), fail otherwise
+		{
+			let func = create_func.read();
+			let elements::Type::Function(func_type) = &**func.type_ref.read();
+			if !func_type.params().is_empty() || !func_type.results().is_empty() {
+				return Err(Error::InvalidCreateSignature(symbols.create));
 			}
 		}
-		if !found {
-			let mut mbuilder = builder::from_module(ctor_module);
-			let import_sig = mbuilder.push_signature(
-				builder::signature()
-					.param().i32().param().i32()
-					.build_sig()
-				);
-
-			mbuilder.push_import(
-				builder::import()
-					.module("env")
-					.field(&target.symbols().ret)
-					.external().func(import_sig)
-					.build()
-				);
 
-			ctor_module = mbuilder.build();
-
-			let ret_func = ctor_module.import_count(ImportCountType::Function) as u32 - 1;
+		let existing_ret = module.funcs.iter()
+			.find(|func| matches!(&func.read().origin, ImportedOrDeclared::Imported(_, field) if field == symbols.ret))
+			.cloned();
+		let ret_func = match existing_ret {
+			Some(ret_func) => ret_func,
+			None => module.add_import_func(
+				"env",
+				symbols.ret,
+				elements::FunctionType::new(vec![ValueType::I32, ValueType::I32], vec![]),
+			),
+		};
 
-			for section in ctor_module.sections_mut() {
-				match section {
-					elements::Section::Code(code_section) => {
-						for func_body in code_section.bodies_mut() {
-							update_call_index(func_body.code_mut(), ret_func);
-						}
-					},
-					elements::Section::Export(export_section) => {
-						for export in export_section.entries_mut() {
-							if let elements::Internal::Function(func_index) = export.internal_mut() {
-								if *func_index >= ret_func { *func_index += 1}
-							}
-						}
-					},
-					elements::Section::Element(elements_section) => {
-						for segment in elements_section.entries_mut() {
-							// update all indirect call addresses initial values
-							for func_index in segment.members_mut() {
-								if *func_index >= ret_func { *func_index += 1}
-							}
-						}
+		// Code data address is an address where we put the contract's code (raw_module)
+		let code_data_address = match module.data.iter().last().map(|segment| segment.read()) {
+			Some(segment) => match &segment.location {
+				SegmentLocation::Default(offset) => match offset.first() {
+					Some(graph::Instruction::Plain(Instruction::I32Const(offset))) => {
+						let len = segment.value.len() as i32;
+						offset + (len + 4) - len % 4
 					},
-					_ => { }
-				}
-			}
-
-			create_func_id += 1;
-			ret_func
-		 }
-		else { id }
-	};
-
-	// If new function is put in ctor module, it will have this callable index
-	let last_function_index = ctor_module.functions_space();
-
-	// We ensure here that module has the DataSection
-	if ctor_module
-		.sections()
-		.iter()
-		.find(|section| matches!(**section, Section::Data(_)))
-		.is_none() {
-		// DataSection has to be the last non-custom section according the to the spec
-		ctor_module.sections_mut().push(Section::Data(DataSection::with_entries(vec![])));
+					_ => 0,
+				},
+				_ => 0,
+			},
+			None => 0,
+		};
+		module.data.push(graph::DataSegment {
+			location: SegmentLocation::Default(vec![
+				graph::Instruction::Plain(Instruction::I32Const(code_data_address)),
+				graph::Instruction::Plain(Instruction::End),
+			]),
+			value: self.raw_module.to_vec(),
+		});
+
+		let deploy_func = module.add_func(elements::FunctionType::default(), Vec::new(), vec![
+			graph::Instruction::Call(create_func),
+			graph::Instruction::Plain(Instruction::I32Const(code_data_address)),
+			graph::Instruction::Plain(Instruction::I32Const(self.raw_module.len() as i32)),
+			graph::Instruction::Call(ret_func),
+			graph::Instruction::Plain(Instruction::End),
+		]);
+
+		// change `create` symbol export name into default `call` symbol name.
+		let export = &mut module.exports[create_export];
+		export.name = symbols.call.to_owned();
+		export.local = ExportLocal::Func(deploy_func);
+		Ok(())
 	}
+}
 
-	// Code data address is an address where we put the contract's code (raw_module)
-	let mut code_data_address = 0i32;
+impl<'a> Pass for Pack<'a> {
+	fn name(&self) -> &str {
+		"pack"
+	}
 
-	for section in ctor_module.sections_mut() {
-		if let Section::Data(data_section) = section {
-			let (index, offset) = if let Some(entry) = data_section.entries().iter().last() {
-				let init_expr = entry
-					.offset()
-					.as_ref()
-					.expect("parity-wasm is compiled without bulk-memory operations")
-					.code();
-				if let Instruction::I32Const(offst) = init_expr[0] {
-					let len = entry.value().len() as i32;
-					let offst = offst as i32;
-					(entry.index(), offst + (len + 4) - len % 4)
-				} else {
-					(0, 0)
-				}
-			} else {
-				(0, 0)
-			};
-			let code_data = DataSegment::new(
-				index,
-				Some(InitExpr::new(vec![Instruction::I32Const(offset), Instruction::End])),
-				raw_module.clone()
-			);
-			data_section.entries_mut().push(code_data);
-			code_data_address = offset;
-		}
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		self.pack(module).map_err(|error| error.to_string())
 	}
+}
 
-	let mut new_module = builder::from_module(ctor_module)
-		.function()
-		.signature().build()
-		.body().with_instructions(elements::Instructions::new(
-			vec![
-				Instruction::Call((create_func_id + ctor_import_functions) as u32),
-				Instruction::I32Const(code_data_address),
-				Instruction::I32Const(raw_module.len() as i32),
-				Instruction::Call(ret_function_id as u32),
-				Instruction::End,
-			])).build()
-			.build()
-		.build();
+/// `Pack` keeping its error, since the pipeline reports it as text.
+struct TypedPack<'a> {
+	pack: Pack<'a>,
+	error: &'a Cell<Option<Error>>,
+}
 
-	for section in new_module.sections_mut() {
-		if let Section::Export(export_section) = section {
-			for entry in export_section.entries_mut().iter_mut() {
-				if target.symbols().create == entry.field() {
-					// change `create` symbol export name into default `call` symbol name.
-					*entry.field_mut() = target.symbols().call.to_owned();
-					*entry.internal_mut() = elements::Internal::Function(last_function_index as u32);
-				}
-			}
-		}
-	};
+impl<'a> Pass for TypedPack<'a> {
+	fn name(&self) -> &str {
+		self.pack.name()
+	}
 
-	Ok(new_module)
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		self.pack.pack(module).map_err(|error| {
+			let reason = error.to_string();
+			self.error.set(Some(error));
+			reason
+		})
+	}
 }
 
 #[cfg(test)]
@@ -335,4 +286,29 @@ mod test {
 			&target_runtime,
 		);
 	}
+
+	#[test]
+	fn shifted_names() {
+		let target_runtime = TargetRuntime::pwasm();
+		let binary = wabt::Wat2Wasm::new()
+			.write_debug_names(true)
+			.convert(r#"
+				(module
+					(import "env" "memory" (memory 1 1))
+					(func $call (export "call"))
+					(func $deploy (export "deploy")))
+			"#)
+			.expect("Failed to wat2wasm");
+		let module = elements::deserialize_buffer::<elements::Module>(binary.as_ref())
+			.expect("Failed to deserialize the module");
+
+		let raw_module = parity_wasm::serialize(module.clone()).unwrap();
+		let ctor_module = pack_instance(raw_module, module, &target_runtime).expect("Packing failed");
+
+		let names = ctor_module.names_section().expect("Name section to stay");
+		let function_names = names.functions().expect("Function names to stay").names();
+		assert_eq!(function_names.get(0), None);
+		assert_eq!(function_names.get(1).map(String::as_str), Some("call"));
+		assert_eq!(function_names.get(2).map(String::as_str), Some("deploy"));
+	}
 }
@@ -0,0 +1,301 @@
+//! Passes over the graph representation of the module.
+//!
+//! Passes transform `graph::Module`, where instructions, exports and segments hold references
+//! to entries of `RefList`s instead of raw indices. Inserting an import or a function updates
+//! every reference automatically, so passes never shift indices themselves.
+
+use crate::std::boxed::Box;
+use crate::std::fmt;
+use crate::std::mem;
+use crate::std::string::String;
+use crate::std::vec::Vec;
+
+use log::trace;
+use parity_wasm::elements;
+
+use crate::graph::{self, ImportedOrDeclared, Instruction};
+use crate::optimizer;
+use crate::ref_list::EntryRef;
+
+/// Transformation of the module.
+pub trait Pass {
+	/// Name of the pass, used in errors and logs.
+	fn name(&self) -> &str;
+
+	/// Transform the module, returning the reason of the failure.
+	fn run(&self, module: &mut graph::Module) -> Result<(), String>;
+}
+
+/// Pipeline error.
+#[derive(Debug)]
+pub enum Error {
+	/// Module can't be converted to or from the graph representation.
+	Graph(graph::Error),
+	/// Pass (with the name) failed.
+	Pass(String, String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match self {
+			Error::Graph(error) => write!(f, "Module representation error: {:?}", error),
+			Error::Pass(name, reason) => write!(f, "Pass `{}` failed: {}", name, reason),
+		}
+	}
+}
+
+/// Sequence of passes run one after another.
+#[derive(Default)]
+pub struct Pipeline<'a> {
+	passes: Vec<Box<dyn Pass + 'a>>,
+}
+
+impl<'a> Pipeline<'a> {
+	/// New empty pipeline.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Append the pass to the pipeline.
+	pub fn with_pass(mut self, pass: impl Pass + 'a) -> Self {
+		self.passes.push(Box::new(pass));
+		self
+	}
+
+	/// Run all passes over the module, stopping at the first failure.
+	///
+	/// Function names follow the functions if a pass shifts their indices, a name section
+	/// which is not parsed is dropped then.
+	pub fn run_graph(&self, module: &mut graph::Module) -> Result<(), Error> {
+		for pass in self.passes.iter() {
+			trace!("Running pass `{}`", pass.name());
+			let funcs = module.funcs.iter().cloned().collect::<Vec<_>>();
+			pass.run(module).map_err(|reason| Error::Pass(pass.name().into(), reason))?;
+			if funcs.iter().enumerate().any(|(index, func)| func.order() != Some(index)) {
+				// Names which can't be parsed would point at wrong functions
+				module.other.retain(|_, section| !is_unparsed_names(section));
+				for section in module.other.values_mut() {
+					if let elements::Section::Name(names) = section {
+						remap_names(names, &funcs);
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Run all passes over the parity-wasm module.
+	///
+	/// The name section is parsed and left in the graph, so passes can keep function and
+	/// local names in place. Other custom sections are moved to the end of the module, since
+	/// passes can add sections the module did not have, which would shift their original positions.
+	pub fn run(&self, module: &elements::Module) -> Result<elements::Module, Error> {
+		let mut graph = graph::Module::from_elements(module).map_err(Error::Graph)?;
+		let names = parsed_names(module);
+		graph.other.retain(|_, section| is_names(section));
+		if let Some(names) = &names {
+			for section in graph.other.values_mut() {
+				*section = elements::Section::Name(names.clone());
+			}
+		}
+
+		self.run_graph(&mut graph)?;
+
+		let new_names = graph.other.values().find(|section| is_names(section)).cloned();
+		graph.other.retain(|_, section| !is_names(section));
+		let mut result = graph.generate().map_err(Error::Graph)?;
+		let custom = module.sections().iter()
+			.filter(|section| is_custom(section))
+			.filter_map(|section| match &new_names {
+				_ if !is_names(section) => Some(section.clone()),
+				// Unchanged names keep subsections parity-wasm can't parse
+				Some(elements::Section::Name(new_names)) if Some(new_names) != names.as_ref() => {
+					Some(elements::Section::Name(new_names.clone()))
+				},
+				Some(_) => Some(section.clone()),
+				None => None,
+			});
+		result.sections_mut().extend(custom);
+		Ok(result)
+	}
+}
+
+/// Name section of the module, parsed if needed.
+fn parsed_names(module: &elements::Module) -> Option<elements::NameSection> {
+	if let Some(names) = module.names_section() {
+		return Some(names.clone());
+	}
+	if !module.sections().iter().any(is_unparsed_names) {
+		return None;
+	}
+	let mut parsed = module.clone();
+	optimizer::parse_names(&mut parsed);
+	parsed.names_section().cloned()
+}
+
+fn is_names(section: &elements::Section) -> bool {
+	matches!(section, elements::Section::Name(_)) || is_unparsed_names(section)
+}
+
+fn is_unparsed_names(section: &elements::Section) -> bool {
+	matches!(section, elements::Section::Custom(custom) if custom.name() == "name")
+}
+
+/// Move function and local names to the current indices of `funcs`, dropping names of removed functions.
+fn remap_names(names: &mut elements::NameSection, funcs: &[EntryRef<graph::Func>]) {
+	let order = |index: u32| funcs.get(index as usize).and_then(|func| func.order()).map(|order| order as u32);
+	if let Some(function_names) = names.functions_mut() {
+		*function_names.names_mut() = mem::take(function_names.names_mut())
+			.into_iter()
+			.filter_map(|(index, name)| Some((order(index)?, name)))
+			.collect();
+	}
+	if let Some(local_names) = names.locals_mut() {
+		*local_names.local_names_mut() = mem::take(local_names.local_names_mut())
+			.into_iter()
+			.filter_map(|(index, names)| Some((order(index)?, names)))
+			.collect();
+	}
+}
+
+fn is_custom(section: &elements::Section) -> bool {
+	matches!(
+		section,
+		elements::Section::Custom(_) | elements::Section::Name(_) | elements::Section::Reloc(_)
+	)
+}
+
+/// Transform the code of every declared function with its index.
+///
+/// The code is taken out of the function while `f` runs, so `f` can resolve references
+/// to any function, including the transformed one.
+pub(crate) fn for_each_body<F>(module: &graph::Module, mut f: F) -> Result<(), String>
+	where F: FnMut(usize, &mut Vec<Instruction>) -> Result<(), String>
+{
+	for (index, func) in module.funcs.iter().enumerate() {
+		let mut code = match &mut func.write().origin {
+			ImportedOrDeclared::Declared(body) => mem::take(&mut body.code),
+			ImportedOrDeclared::Imported(..) => continue,
+		};
+		let result = f(index, &mut code);
+		if let ImportedOrDeclared::Declared(body) = &mut func.write().origin {
+			body.code = code;
+		}
+		result?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements;
+	use crate::graph;
+	use super::*;
+
+	struct Rename(&'static str);
+
+	impl Pass for Rename {
+		fn name(&self) -> &str {
+			"rename"
+		}
+
+		fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+			let export = module.exports.first_mut().ok_or_else(|| String::from("no exports"))?;
+			export.name = self.0.into();
+			Ok(())
+		}
+	}
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	#[test]
+	fn pipeline() {
+		let mut module = parse_wat(r#"
+			(module
+				(func (export "main")))
+		"#);
+		module.sections_mut().insert(0, elements::Section::Custom(
+			elements::CustomSection::new("producers".into(), vec![])
+		));
+
+		let module = Pipeline::new()
+			.with_pass(Rename("first"))
+			.with_pass(Rename("second"))
+			.run(&module)
+			.expect("Pipeline to succeed");
+		assert_eq!(module.export_section().expect("Export section to stay").entries()[0].field(), "second");
+		assert!(matches!(module.sections().last(), Some(elements::Section::Custom(_))));
+
+		let error = Pipeline::new()
+			.with_pass(Rename("first"))
+			.run(&parse_wat("(module)"))
+			.unwrap_err();
+		assert_eq!(error.to_string(), "Pass `rename` failed: no exports");
+	}
+
+	#[test]
+	fn dangling_references() {
+		use parity_wasm::builder;
+		use parity_wasm::elements::Instruction::*;
+
+		for code in [vec![Call(5), End], vec![GetGlobal(0), Drop, End], vec![I32Const(0), I32Load(2, 0), Drop, End]] {
+			let module = builder::module()
+				.function()
+					.signature().build()
+					.body().with_instructions(elements::Instructions::new(code)).build()
+					.build()
+				.build();
+
+			let error = Pipeline::new()
+				.with_pass(Rename("first"))
+				.run(&module)
+				.unwrap_err();
+			assert!(matches!(error, Error::Graph(graph::Error::InconsistentSource)));
+		}
+	}
+
+	struct ImportLog;
+
+	impl Pass for ImportLog {
+		fn name(&self) -> &str {
+			"import-log"
+		}
+
+		fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+			module.add_import_func("env", "log", elements::FunctionType::new(vec![], vec![]));
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn shifted_names() {
+		let binary = wabt::Wat2Wasm::new()
+			.write_debug_names(true)
+			.convert(r#"
+				(module
+					(import "env" "print" (func $print))
+					(func $main (export "main") (local $counter i32)
+						call $print))
+			"#)
+			.expect("Failed to wat2wasm");
+		let module = elements::deserialize_buffer(binary.as_ref()).expect("Failed to deserialize the module");
+
+		let module = Pipeline::new()
+			.with_pass(ImportLog)
+			.run(&module)
+			.expect("Pipeline to succeed");
+		let names = match module.sections().last() {
+			Some(elements::Section::Name(names)) => names,
+			other => panic!("Expected the name section, got {:?}", other),
+		};
+		let function_names = names.functions().expect("Function names to stay");
+		assert_eq!(function_names.names().get(0).map(String::as_str), Some("print"));
+		assert_eq!(function_names.names().get(1), None);
+		assert_eq!(function_names.names().get(2).map(String::as_str), Some("main"));
+		let local_names = names.locals().expect("Local names to stay").local_names();
+		assert_eq!(local_names.get(2).and_then(|locals| locals.get(0)).map(String::as_str), Some("counter"));
+	}
+}
@@ -0,0 +1,395 @@
+//! Peephole optimizations of function code.
+//!
+//! Instrumentation passes insert code sequence by sequence and leave behind patterns
+//! that are trivially simpler: constants added one after another, adds of zero,
+//! values that are pushed just to be dropped and blocks nobody branches to.
+//! This pass works on plain instructions, so it can be run after any other pass.
+
+use crate::std::vec::Vec;
+
+use parity_wasm::elements::{self, Instruction};
+
+/// Run peephole optimizations on every function body of the module.
+pub fn peephole_optimize(module: &mut elements::Module) {
+	if let Some(code_section) = module.code_section_mut() {
+		for func_body in code_section.bodies_mut() {
+			peephole_optimize_instructions(func_body.code_mut());
+		}
+	}
+}
+
+/// Run peephole optimizations on the instruction sequence.
+///
+/// Following rewrites are applied until none of them matches:
+///
+/// - `nop` is removed;
+/// - constant pushed and dropped straight away is removed;
+/// - integer arithmetic and bitwise operations on two constants are folded into one constant;
+/// - consecutive additions or subtractions of constants are folded into a single addition,
+///   addition of zero is removed;
+/// - `get_local`/`get_global` followed by `set_local`/`set_global` of the same variable is removed;
+/// - `block` which is not a target of any branch is replaced by its body.
+///
+/// Division and remainder are never folded, since they can trap.
+pub fn peephole_optimize_instructions(instructions: &mut elements::Instructions) {
+	loop {
+		let before = instructions.elements().len();
+		fold(instructions.elements_mut());
+		unwrap_blocks(instructions.elements_mut());
+		if instructions.elements().len() == before {
+			break;
+		}
+	}
+}
+
+/// Fold constant expressions, rewriting the whole sequence once.
+fn fold(instructions: &mut Vec<Instruction>) {
+	let mut out: Vec<Instruction> = Vec::with_capacity(instructions.len());
+	for instruction in instructions.drain(..) {
+		if instruction == Instruction::Nop {
+			continue;
+		}
+		out.push(instruction);
+		while fold_tail(&mut out) { }
+	}
+	*instructions = out;
+}
+
+/// Value of the constant added by the pair `const; add` or `const; sub`.
+fn added_i32(pair: &[Instruction]) -> Option<i32> {
+	match pair {
+		[Instruction::I32Const(c), Instruction::I32Add] => Some(*c),
+		[Instruction::I32Const(c), Instruction::I32Sub] => Some(c.wrapping_neg()),
+		_ => None,
+	}
+}
+
+fn added_i64(pair: &[Instruction]) -> Option<i64> {
+	match pair {
+		[Instruction::I64Const(c), Instruction::I64Add] => Some(*c),
+		[Instruction::I64Const(c), Instruction::I64Sub] => Some(c.wrapping_neg()),
+		_ => None,
+	}
+}
+
+fn fold_i32(a: i32, b: i32, op: &Instruction) -> Option<i32> {
+	use self::Instruction::*;
+	Some(match op {
+		I32Add => a.wrapping_add(b),
+		I32Sub => a.wrapping_sub(b),
+		I32Mul => a.wrapping_mul(b),
+		I32And => a & b,
+		I32Or => a | b,
+		I32Xor => a ^ b,
+		I32Shl => a.wrapping_shl(b as u32),
+		I32ShrS => a.wrapping_shr(b as u32),
+		I32ShrU => (a as u32).wrapping_shr(b as u32) as i32,
+		I32Rotl => (a as u32).rotate_left(b as u32 % 32) as i32,
+		I32Rotr => (a as u32).rotate_right(b as u32 % 32) as i32,
+		_ => return None,
+	})
+}
+
+fn fold_i64(a: i64, b: i64, op: &Instruction) -> Option<i64> {
+	use self::Instruction::*;
+	Some(match op {
+		I64Add => a.wrapping_add(b),
+		I64Sub => a.wrapping_sub(b),
+		I64Mul => a.wrapping_mul(b),
+		I64And => a & b,
+		I64Or => a | b,
+		I64Xor => a ^ b,
+		I64Shl => a.wrapping_shl(b as u32),
+		I64ShrS => a.wrapping_shr(b as u32),
+		I64ShrU => (a as u64).wrapping_shr(b as u32) as i64,
+		I64Rotl => (a as u64).rotate_left((b as u64 % 64) as u32) as i64,
+		I64Rotr => (a as u64).rotate_right((b as u64 % 64) as u32) as i64,
+		_ => return None,
+	})
+}
+
+/// Try to simplify the end of the sequence, returns `true` if it was changed.
+fn fold_tail(out: &mut Vec<Instruction>) -> bool {
+	use self::Instruction::*;
+	let len = out.len();
+
+	if len >= 2 {
+		let remove_pair = match &out[len - 2..] {
+			[I32Const(_), Drop] | [I64Const(_), Drop] | [F32Const(_), Drop] | [F64Const(_), Drop] => true,
+			[GetLocal(a), SetLocal(b)] | [GetGlobal(a), SetGlobal(b)] => a == b,
+			[I32Const(0), I32Add] | [I32Const(0), I32Sub] => true,
+			[I64Const(0), I64Add] | [I64Const(0), I64Sub] => true,
+			_ => false,
+		};
+		if remove_pair {
+			out.truncate(len - 2);
+			return true;
+		}
+	}
+
+	if len >= 3 {
+		let folded = match &out[len - 3..] {
+			[I32Const(a), I32Const(b), op] => fold_i32(*a, *b, op).map(I32Const),
+			[I64Const(a), I64Const(b), op] => fold_i64(*a, *b, op).map(I64Const),
+			_ => None,
+		};
+		if let Some(folded) = folded {
+			out.truncate(len - 3);
+			out.push(folded);
+			return true;
+		}
+	}
+
+	if len >= 4 {
+		let (first, second) = out[len - 4..].split_at(2);
+		let folded = match (added_i32(first), added_i32(second)) {
+			(Some(a), Some(b)) => Some([I32Const(a.wrapping_add(b)), I32Add]),
+			_ => match (added_i64(first), added_i64(second)) {
+				(Some(a), Some(b)) => Some([I64Const(a.wrapping_add(b)), I64Add]),
+				_ => None,
+			},
+		};
+		if let Some(folded) = folded {
+			out.truncate(len - 4);
+			out.extend_from_slice(&folded);
+			return true;
+		}
+	}
+
+	false
+}
+
+/// Replace every `block` without branches to it by its body.
+///
+/// Branches from the bodies to the outer labels are adjusted, since there are less labels.
+/// Returns `true` if some block was unwrapped.
+fn unwrap_blocks(instructions: &mut Vec<Instruction>) -> bool {
+	use self::Instruction::*;
+
+	// Open frames with the position of `block` (`None` for `loop` and `if`) and whether it is targeted
+	let mut frames: Vec<(Option<usize>, bool)> = Vec::new();
+	let mut removed = vec![false; instructions.len()];
+	for (position, instruction) in instructions.iter().enumerate() {
+		match instruction {
+			Block(_) => frames.push((Some(position), false)),
+			Loop(_) | If(_) => frames.push((None, false)),
+			End => if let Some((Some(start), false)) = frames.pop() {
+				removed[start] = true;
+				removed[position] = true;
+			},
+			Br(label) | BrIf(label) => target(&mut frames, *label),
+			BrTable(table) => {
+				target(&mut frames, table.default);
+				for label in table.table.iter() {
+					target(&mut frames, *label);
+				}
+			},
+			_ => { },
+		}
+	}
+	if !removed.contains(&true) {
+		return false;
+	}
+
+	// Number of removed blocks among the open frames up to each of them, from the outermost
+	let mut removed_frames: Vec<u32> = Vec::new();
+	let mut result = Vec::with_capacity(instructions.len());
+	for (position, mut instruction) in instructions.drain(..).enumerate() {
+		match &mut instruction {
+			Block(_) | Loop(_) | If(_) => {
+				let outer = removed_frames.last().copied().unwrap_or(0);
+				removed_frames.push(outer + removed[position] as u32);
+			},
+			End => { removed_frames.pop(); },
+			Br(label) | BrIf(label) => *label = shifted_label(&removed_frames, *label),
+			BrTable(table) => {
+				let mut data = (**table).clone();
+				data.default = shifted_label(&removed_frames, data.default);
+				for label in data.table.iter_mut() {
+					*label = shifted_label(&removed_frames, *label);
+				}
+				*table = data.into();
+			},
+			_ => { },
+		}
+		if !removed[position] {
+			result.push(instruction);
+		}
+	}
+	*instructions = result;
+	true
+}
+
+/// Mark the frame which is the target of the branch with the label.
+fn target(frames: &mut [(Option<usize>, bool)], label: u32) {
+	if let Some(index) = frames.len().checked_sub(label as usize + 1) {
+		frames[index].1 = true;
+	}
+}
+
+/// Label of the branch after removing blocks between the branch and its target.
+fn shifted_label(removed_frames: &[u32], label: u32) -> u32 {
+	let inner = removed_frames.last().copied().unwrap_or(0);
+	let outer = match removed_frames.len().checked_sub(label as usize + 1) {
+		Some(index) => removed_frames[index],
+		// The function body is the target
+		None => 0,
+	};
+	label - (inner - outer)
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements::{self, BlockType, BrTableData, Instruction::*};
+	use super::*;
+
+	fn optimized(code: Vec<elements::Instruction>) -> Vec<elements::Instruction> {
+		let mut instructions = elements::Instructions::new(code);
+		peephole_optimize_instructions(&mut instructions);
+		instructions.elements().to_vec()
+	}
+
+	#[test]
+	fn constants() {
+		assert_eq!(
+			optimized(vec![
+				I32Const(2), I32Const(3), I32Mul, I32Const(1), I32Shl, Nop, I32Const(-1), I32Xor, Drop,
+				I64Const(7), I64Const(1), I64ShrU, Drop,
+				I32Const(1), I32Const(0), I32DivU, Drop,
+				End,
+			]),
+			vec![I32Const(1), I32Const(0), I32DivU, Drop, End],
+		);
+	}
+
+	#[test]
+	fn counter_updates() {
+		assert_eq!(
+			optimized(vec![
+				GetGlobal(0), I32Const(5), I32Add, I32Const(2), I32Sub, SetGlobal(0),
+				GetGlobal(0), I32Const(3), I32Sub, I32Const(3), I32Add, SetGlobal(0),
+				GetLocal(0), I64Const(0), I64Add, SetLocal(1),
+				End,
+			]),
+			vec![GetGlobal(0), I32Const(3), I32Add, SetGlobal(0), GetLocal(0), SetLocal(1), End],
+		);
+	}
+
+	#[test]
+	fn blocks() {
+		assert_eq!(
+			optimized(vec![
+				Block(BlockType::NoResult),
+					Block(BlockType::NoResult),
+						GetLocal(0),
+						BrIf(1),
+						GetLocal(0),
+						BrTable(Box::new(BrTableData { table: Box::new([1, 2]), default: 1 })),
+					End,
+					Block(BlockType::Value(elements::ValueType::I32)),
+						I32Const(1),
+					End,
+					Drop,
+				End,
+				End,
+			]),
+			vec![
+				Block(BlockType::NoResult),
+					GetLocal(0),
+					BrIf(0),
+					GetLocal(0),
+					BrTable(Box::new(BrTableData { table: Box::new([0, 1]), default: 0 })),
+				End,
+				End,
+			],
+		);
+	}
+
+	#[test]
+	fn nested_blocks() {
+		assert_eq!(
+			optimized(vec![
+				Block(BlockType::NoResult),
+					Block(BlockType::NoResult),
+						Loop(BlockType::NoResult),
+							Block(BlockType::NoResult),
+								GetLocal(0),
+								BrIf(3),
+								GetLocal(0),
+								BrIf(1),
+							End,
+						End,
+					End,
+				End,
+				End,
+			]),
+			vec![
+				Block(BlockType::NoResult),
+					Loop(BlockType::NoResult),
+						GetLocal(0),
+						BrIf(1),
+						GetLocal(0),
+						BrIf(0),
+					End,
+				End,
+				End,
+			],
+		);
+	}
+
+	#[test]
+	fn instrumented() {
+		let module = elements::deserialize_buffer(&wabt::wat2wasm(r#"
+			(module
+				(func $f (param i32) (result i32)
+					block
+						nop
+						get_local 0
+						i32.const 1
+						i32.add
+						drop
+					end
+					get_local 0)
+				(func (export "call")
+					i32.const 1
+					call $f
+					drop))
+		"#).expect("Failed to wat2wasm")).expect("Failed to deserialize the module");
+
+		let mut module = crate::stack_height::inject_limiter(module, 1024).expect("Failed to inject limiter");
+		peephole_optimize(&mut module);
+		let bodies = module.code_section().expect("Code section to exist").bodies()
+			.iter()
+			.map(|body| body.code().elements().to_vec())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			bodies,
+			vec![
+				vec![GetLocal(0), I32Const(1), I32Add, Drop, GetLocal(0), End],
+				vec![
+					I32Const(1),
+					GetGlobal(0), I32Const(2), I32Add, SetGlobal(0),
+					GetGlobal(0), I32Const(1024), I32GtU, If(BlockType::NoResult), Unreachable, End,
+					Call(0),
+					GetGlobal(0), I32Const(2), I32Sub, SetGlobal(0),
+					Drop,
+					End,
+				],
+				vec![
+					GetGlobal(0), I32Const(1), I32Add, SetGlobal(0),
+					GetGlobal(0), I32Const(1024), I32GtU, If(BlockType::NoResult), Unreachable, End,
+					Call(1),
+					GetGlobal(0), I32Const(1), I32Sub, SetGlobal(0),
+					End,
+				],
+			],
+		);
+
+		let binary = elements::serialize(module).expect("Failed to serialize");
+
+		wabt::Module::read_binary(&binary, &Default::default())
+			.expect("Wabt failed to read final binary")
+			.validate()
+			.expect("Invalid module");
+	}
+}
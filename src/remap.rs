@@ -0,0 +1,288 @@
+//! Remapping of imports to other modules and fields.
+//!
+//! Host APIs get renamed between versions (e.g. `env.ext_return` becomes `seal0.seal_return`),
+//! so contracts can be migrated by rewriting their import entries.
+
+use crate::std::collections::BTreeMap;
+use crate::std::fmt;
+use crate::std::string::String;
+use crate::std::vec::Vec;
+use crate::std::borrow::ToOwned;
+
+use log::trace;
+use parity_wasm::elements::{self, ValueType};
+
+/// Remapping error.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+	/// Import is not mentioned in the mapping, while the mapping is strict.
+	Unmapped(String, String),
+	/// Import has different signature than the mapping expects.
+	SignatureMismatch(String, String),
+	/// Mapping expects a signature, but the import is not a function.
+	NotAFunction(String, String),
+	/// Line of the mapping file can't be parsed.
+	Parse(usize, String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match self {
+			Error::Unmapped(module, field) => write!(f, "Import `{}.{}` is not mapped", module, field),
+			Error::SignatureMismatch(module, field) => write!(f, "Import `{}.{}` has unexpected signature", module, field),
+			Error::NotAFunction(module, field) => write!(f, "Import `{}.{}` is not a function", module, field),
+			Error::Parse(line, msg) => write!(f, "Mapping line {}: {}", line, msg),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+struct Target {
+	module: String,
+	field: String,
+	signature: Option<elements::FunctionType>,
+}
+
+/// Mapping of imports from (module, field) to (module, field).
+///
+/// The mapping can be built with methods or parsed from text with `Mapping::parse`.
+#[derive(Debug, Default, Clone)]
+pub struct Mapping {
+	targets: BTreeMap<(String, String), Target>,
+	strict: bool,
+}
+
+impl Mapping {
+	/// Rename import `from_module.from_field` to `to_module.to_field`.
+	pub fn with_import(mut self, from_module: &str, from_field: &str, to_module: &str, to_field: &str) -> Self {
+		self.targets.insert(
+			(from_module.to_owned(), from_field.to_owned()),
+			Target { module: to_module.to_owned(), field: to_field.to_owned(), signature: None },
+		);
+		self
+	}
+
+	/// Require imported function `module.field` (the name before remapping) to have the signature.
+	///
+	/// Import is kept as is, if it was not renamed with `with_import`.
+	pub fn with_signature(mut self, module: &str, field: &str, signature: elements::FunctionType) -> Self {
+		self.targets.entry((module.to_owned(), field.to_owned()))
+			.or_insert_with(|| Target { module: module.to_owned(), field: field.to_owned(), signature: None })
+			.signature = Some(signature);
+		self
+	}
+
+	/// Fail on imports which are not in the mapping.
+	pub fn strict(mut self) -> Self {
+		self.strict = true;
+		self
+	}
+
+	/// Parse the mapping from text.
+	///
+	/// Every line maps one import, optionally followed by the expected signature:
+	///
+	/// ```text
+	/// # comment
+	/// env.ext_return = seal0.seal_return : (i32, i32)
+	/// env.ext_address = seal0.seal_address : (i32, i32) -> ()
+	/// env.ext_balance = seal0.seal_balance
+	/// env.memcpy = env.memcpy : (i32, i32, i32) -> i32
+	/// ```
+	///
+	/// Module name is everything before the first dot.
+	pub fn parse(source: &str) -> Result<Self, Error> {
+		let mut mapping = Mapping::default();
+		for (number, line) in source.lines().enumerate() {
+			let number = number + 1;
+			let line = match line.find('#') {
+				Some(comment) => &line[..comment],
+				None => line,
+			}.trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			let (names, signature) = match line.find(':') {
+				Some(colon) => (&line[..colon], Some(&line[colon + 1..])),
+				None => (line, None),
+			};
+			let mut names = names.splitn(2, '=');
+			let from = parse_name(number, names.next().unwrap_or(""))?;
+			let to = parse_name(number, names.next().ok_or_else(|| Error::Parse(number, "expected `=`".into()))?)?;
+
+			mapping = mapping.with_import(from.0, from.1, to.0, to.1);
+			if let Some(signature) = signature {
+				mapping = mapping.with_signature(from.0, from.1, parse_signature(number, signature)?);
+			}
+		}
+		Ok(mapping)
+	}
+}
+
+fn parse_name(line: usize, name: &str) -> Result<(&str, &str), Error> {
+	let name = name.trim();
+	match name.find('.') {
+		Some(dot) if dot > 0 && dot + 1 < name.len() => Ok((&name[..dot], &name[dot + 1..])),
+		_ => Err(Error::Parse(line, format!("expected `module.field`, got `{}`", name))),
+	}
+}
+
+fn parse_value_type(line: usize, value_type: &str) -> Result<ValueType, Error> {
+	match value_type.trim() {
+		"i32" => Ok(ValueType::I32),
+		"i64" => Ok(ValueType::I64),
+		"f32" => Ok(ValueType::F32),
+		"f64" => Ok(ValueType::F64),
+		other => Err(Error::Parse(line, format!("unknown value type `{}`", other))),
+	}
+}
+
+/// Parse `(params) -> result`, where the result is optional.
+fn parse_signature(line: usize, signature: &str) -> Result<elements::FunctionType, Error> {
+	let signature = signature.trim();
+	let (params, results) = match signature.find("->") {
+		Some(arrow) => (signature[..arrow].trim(), signature[arrow + 2..].trim()),
+		None => (signature, "()"),
+	};
+
+	let list = |types: &str| -> Result<Vec<ValueType>, Error> {
+		let inner = match types.strip_prefix('(').and_then(|types| types.strip_suffix(')')) {
+			Some(inner) => inner,
+			None if !types.is_empty() => return Ok(vec![parse_value_type(line, types)?]),
+			None => return Err(Error::Parse(line, "expected `(types)`".into())),
+		};
+		inner.split(',')
+			.filter(|value_type| !value_type.trim().is_empty())
+			.map(|value_type| parse_value_type(line, value_type))
+			.collect()
+	};
+
+	Ok(elements::FunctionType::new(list(params)?, list(results)?))
+}
+
+/// Rename imports of the module according to the mapping.
+///
+/// The module is left untouched if any import fails the checks.
+///
+/// Returns number of renamed imports.
+pub fn remap_imports(module: &mut elements::Module, mapping: &Mapping) -> Result<usize, Error> {
+	let types = module.type_section().map(|section| section.types()).unwrap_or(&[]);
+	let entries = module.import_section().map(|section| section.entries()).unwrap_or(&[]);
+
+	let mut renames = Vec::new();
+	for (index, entry) in entries.iter().enumerate() {
+		let key = (entry.module().to_owned(), entry.field().to_owned());
+		let target = match mapping.targets.get(&key) {
+			Some(target) => target,
+			None if mapping.strict => return Err(Error::Unmapped(key.0, key.1)),
+			None => continue,
+		};
+
+		if let Some(signature) = &target.signature {
+			let type_ref = match entry.external() {
+				elements::External::Function(type_ref) => *type_ref,
+				_ => return Err(Error::NotAFunction(key.0, key.1)),
+			};
+			match types.get(type_ref as usize) {
+				Some(elements::Type::Function(func_type)) if func_type == signature => { },
+				_ => return Err(Error::SignatureMismatch(key.0, key.1)),
+			}
+		}
+
+		if target.module != key.0 || target.field != key.1 {
+			trace!("Remapped import {}.{} to {}.{}", key.0, key.1, target.module, target.field);
+			renames.push((index, target));
+		}
+	}
+
+	if let Some(import_section) = module.import_section_mut() {
+		for (index, target) in renames.iter() {
+			let entry = &mut import_section.entries_mut()[*index];
+			*entry.module_mut() = target.module.clone();
+			*entry.field_mut() = target.field.clone();
+		}
+	}
+	Ok(renames.len())
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements::{self, ValueType};
+	use super::*;
+
+	fn sample() -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(r#"
+			(module
+				(import "env" "ext_return" (func (param i32 i32)))
+				(import "env" "ext_balance" (func (param i32) (result i64)))
+				(import "env" "memory" (memory 1)))
+		"#).expect("Failed to wat2wasm")).expect("Failed to deserialize the module")
+	}
+
+	fn imports(module: &elements::Module) -> Vec<(&str, &str)> {
+		module.import_section().expect("Import section to stay").entries().iter()
+			.map(|entry| (entry.module(), entry.field()))
+			.collect()
+	}
+
+	#[test]
+	fn parse() {
+		let mapping = Mapping::parse(r#"
+			# Renames for the new host API
+			env.ext_return = seal0.seal_return : (i32, i32)
+			env.ext_balance = seal0.seal_balance : (i32) -> i64
+
+			env.memory = env.memory
+		"#).expect("Mapping to parse");
+
+		let mut module = sample();
+		assert_eq!(remap_imports(&mut module, &mapping.strict()), Ok(2));
+		assert_eq!(imports(&module), vec![
+			("seal0", "seal_return"),
+			("seal0", "seal_balance"),
+			("env", "memory"),
+		]);
+
+		assert_eq!(
+			Mapping::parse("env.ext_return = seal0").unwrap_err(),
+			Error::Parse(1, "expected `module.field`, got `seal0`".into()),
+		);
+		assert_eq!(
+			Mapping::parse("\nenv.a = env.b : (u32)").unwrap_err(),
+			Error::Parse(2, "unknown value type `u32`".into()),
+		);
+	}
+
+	#[test]
+	fn errors() {
+		let mapping = Mapping::default()
+			.with_import("env", "ext_return", "seal0", "seal_return");
+		assert_eq!(remap_imports(&mut sample(), &mapping), Ok(1));
+		assert_eq!(
+			remap_imports(&mut sample(), &mapping.clone().strict()),
+			Err(Error::Unmapped("env".into(), "ext_balance".into())),
+		);
+
+		let mapping = mapping.with_signature(
+			"env",
+			"ext_balance",
+			elements::FunctionType::new(vec![ValueType::I32], vec![ValueType::I32]),
+		);
+		assert_eq!(
+			remap_imports(&mut sample(), &mapping),
+			Err(Error::SignatureMismatch("env".into(), "ext_balance".into())),
+		);
+
+		let mut module = sample();
+		assert!(remap_imports(&mut module, &mapping).is_err());
+		assert_eq!(imports(&module)[0], ("env", "ext_return"));
+
+		let mapping = Mapping::default()
+			.with_signature("env", "memory", elements::FunctionType::default());
+		assert_eq!(
+			remap_imports(&mut sample(), &mapping),
+			Err(Error::NotAFunction("env".into(), "memory".into())),
+		);
+	}
+}
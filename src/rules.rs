@@ -66,6 +66,8 @@ pub enum InstructionType {
 	Nop,
 	CurrentMemory,
 	GrowMemory,
+	#[cfg(feature = "bulk")]
+	Bulk,
 }
 
 impl FromStr for InstructionType {
@@ -93,6 +95,8 @@ impl FromStr for InstructionType {
 			"nop" => Ok(InstructionType::Nop),
 			"current_mem" => Ok(InstructionType::CurrentMemory),
 			"grow_mem" => Ok(InstructionType::GrowMemory),
+			#[cfg(feature = "bulk")]
+			"bulk" => Ok(InstructionType::Bulk),
 			_ => Err(UnknownInstruction),
 		}
 	}
@@ -291,6 +295,9 @@ impl InstructionType {
 			I64ReinterpretF64 => InstructionType::Reinterpretation,
 			F32ReinterpretI32 => InstructionType::Reinterpretation,
 			F64ReinterpretI64 => InstructionType::Reinterpretation,
+
+			#[cfg(feature = "bulk")]
+			Bulk(_) => InstructionType::Bulk,
 		}
 	}
 }
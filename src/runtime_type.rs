@@ -1,33 +1,62 @@
-use parity_wasm::{elements, builder};
-use self::elements::{ Module, GlobalEntry, External, ExportEntry, GlobalType, ValueType, InitExpr, Instruction, Internal };
-use byteorder::{ LittleEndian, ByteOrder };
+use crate::std::string::String;
 
+use parity_wasm::elements::{Instruction, Module, ValueType};
+use byteorder::{LittleEndian, ByteOrder};
+
+use crate::graph::{self, Export, ExportLocal};
+use crate::pass::{self, Pass, Pipeline};
+
+/// Export the runtime type and version as globals `RUNTIME_TYPE` and `RUNTIME_VERSION`.
+///
+/// # Panics
+///
+/// Panics if the module references missing items, use `try_inject_runtime_type` to get the error instead.
 pub fn inject_runtime_type(module: Module, runtime_type: [u8; 4], runtime_version: u32) -> Module {
-	let runtime_type: u32 = LittleEndian::read_u32(&runtime_type);
-	let globals_count: u32 = match module.global_section() {
-		Some(section) => section.entries().len() as u32,
-		None => 0
-	};
-	let imported_globals_count: u32 = match module.import_section() {
-		Some(section) => section
-			.entries()
-			.iter()
-			.filter(|e| matches!(*e.external(), External::Global(_)))
-			.count() as u32,
-		None => 0
-	};
-	let total_globals_count: u32 = globals_count + imported_globals_count;
-
-	builder::from_module(module)
-		.with_global(GlobalEntry::new(GlobalType::new(ValueType::I32, false), InitExpr::new(vec![Instruction::I32Const(runtime_type as i32), Instruction::End])))
-		.with_export(ExportEntry::new("RUNTIME_TYPE".into(), Internal::Global(total_globals_count)))
-		.with_global(GlobalEntry::new(GlobalType::new(ValueType::I32, false), InitExpr::new(vec![Instruction::I32Const(runtime_version as i32), Instruction::End])))
-		.with_export(ExportEntry::new("RUNTIME_VERSION".into(), Internal::Global(total_globals_count + 1)))
-	.build()
+	try_inject_runtime_type(module, runtime_type, runtime_version).expect("Failed to inject runtime type")
+}
+
+/// Same as `inject_runtime_type`, but returns the error instead of panicking.
+pub fn try_inject_runtime_type(module: Module, runtime_type: [u8; 4], runtime_version: u32) -> Result<Module, pass::Error> {
+	Pipeline::new()
+		.with_pass(RuntimeType::new(runtime_type, runtime_version))
+		.run(&module)
+}
+
+/// Pass adding globals exported as `RUNTIME_TYPE` and `RUNTIME_VERSION`.
+pub struct RuntimeType {
+	runtime_type: [u8; 4],
+	runtime_version: u32,
+}
+
+impl RuntimeType {
+	/// New pass exporting `runtime_type` and `runtime_version`.
+	pub fn new(runtime_type: [u8; 4], runtime_version: u32) -> Self {
+		RuntimeType { runtime_type, runtime_version }
+	}
+}
+
+impl Pass for RuntimeType {
+	fn name(&self) -> &str {
+		"runtime-type"
+	}
+
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		let runtime_type = LittleEndian::read_u32(&self.runtime_type);
+		for (name, value) in [("RUNTIME_TYPE", runtime_type), ("RUNTIME_VERSION", self.runtime_version)].iter() {
+			let global_ref = module.add_global(ValueType::I32, false, vec![
+				graph::Instruction::Plain(Instruction::I32Const(*value as i32)),
+				graph::Instruction::Plain(Instruction::End),
+			]);
+			module.exports.push(Export { name: (*name).into(), local: ExportLocal::Global(global_ref) });
+		}
+		Ok(())
+	}
 }
 
 #[cfg(test)]
 mod tests {
+	use parity_wasm::builder;
+	use parity_wasm::elements::{GlobalEntry, GlobalType, InitExpr, Instruction, ValueType};
 	use super::*;
 	#[test]
 	fn it_injects() {
@@ -0,0 +1,216 @@
+//! Size profile of the module: which sections, functions, data segments, imports and exports
+//! the bytes of the binary belong to.
+
+use crate::std::collections::BTreeSet as Set;
+use crate::std::string::{String, ToString};
+use crate::std::vec::Vec;
+
+use parity_wasm::elements::{self, Serialize};
+
+use crate::callgraph::function_name;
+use crate::symbols::{Symbol, expand_symbols, push_code_symbols, resolve_function};
+
+/// Encoded size of the item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemSize {
+	/// Name of the item.
+	pub name: String,
+	/// Size in bytes.
+	pub size: usize,
+}
+
+/// Encoded size of the export.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportSize {
+	/// Name of the export.
+	pub name: String,
+	/// Size of the export entry in bytes.
+	pub size: usize,
+	/// Bytes `optimize` would remove if the export was dropped: the export entry and
+	/// everything only it references.
+	pub retained: usize,
+}
+
+/// Size profile of the module.
+#[derive(Clone, Debug, Default)]
+pub struct SizeProfile {
+	/// Size of the whole module.
+	pub total: usize,
+	/// Sections, in the module order.
+	pub sections: Vec<ItemSize>,
+	/// Declared functions: the body together with the function section entry.
+	pub functions: Vec<ItemSize>,
+	/// Data segments.
+	pub data: Vec<ItemSize>,
+	/// Import entries, named `module.field`.
+	pub imports: Vec<ItemSize>,
+	/// Export entries.
+	pub exports: Vec<ExportSize>,
+}
+
+/// Profile where the bytes of the module go.
+///
+/// Function names are taken from the name section, so it should be parsed beforehand
+/// (see `parity_wasm::elements::Module::parse_names`).
+pub fn size_profile(module: &elements::Module) -> SizeProfile {
+	let mut profile = SizeProfile {
+		total: encoded_size(module),
+		..Default::default()
+	};
+
+	for section in module.sections() {
+		profile.sections.push(ItemSize { name: section_name(section), size: encoded_size(section) });
+	}
+
+	let imported = module.import_count(elements::ImportCountType::Function) as u32;
+	let declared = module.function_section().map(|section| section.entries().len()).unwrap_or(0);
+	for index in 0..declared {
+		let func_index = imported + index as u32;
+		profile.functions.push(ItemSize {
+			name: function_name(module, func_index).unwrap_or_else(|| format!("func[{}]", func_index)),
+			size: symbol_size(module, Symbol::Function(index)),
+		});
+	}
+
+	for (index, segment) in module.data_section().iter().flat_map(|section| section.entries()).enumerate() {
+		profile.data.push(ItemSize { name: format!("data[{}]", index), size: encoded_size(segment) });
+	}
+
+	for entry in module.import_section().iter().flat_map(|section| section.entries()) {
+		profile.imports.push(ItemSize {
+			name: format!("{}.{}", entry.module(), entry.field()),
+			size: encoded_size(entry),
+		});
+	}
+
+	let roots = roots(module);
+	let kept = reachable_size(module, roots.clone());
+	for (index, entry) in module.export_section().iter().flat_map(|section| section.entries()).enumerate() {
+		let mut without_export = roots.clone();
+		without_export.remove(&Symbol::Export(index));
+		profile.exports.push(ExportSize {
+			name: entry.field().to_string(),
+			size: encoded_size(entry),
+			retained: kept - reachable_size(module, without_export),
+		});
+	}
+
+	profile
+}
+
+/// Symbols `optimize` keeps when all exports are used.
+fn roots(module: &elements::Module) -> Set<Symbol> {
+	let mut roots = Set::new();
+	let exports = module.export_section().map(|section| section.entries().len()).unwrap_or(0);
+	roots.extend((0..exports).map(Symbol::Export));
+
+	if let Some(start) = module.start_section() {
+		roots.insert(resolve_function(module, start));
+	}
+
+	let mut init_symbols = Vec::new();
+	for segment in module.data_section().iter().flat_map(|section| section.entries()) {
+		if let Some(offset) = segment.offset() {
+			push_code_symbols(module, offset.code(), &mut init_symbols);
+		}
+	}
+	for segment in module.elements_section().iter().flat_map(|section| section.entries()) {
+		if let Some(offset) = segment.offset() {
+			push_code_symbols(module, offset.code(), &mut init_symbols);
+		}
+		init_symbols.extend(segment.members().iter().map(|func| resolve_function(module, *func)));
+	}
+	roots.extend(init_symbols);
+	roots
+}
+
+/// Total size of the symbols reachable from the roots.
+fn reachable_size(module: &elements::Module, mut symbols: Set<Symbol>) -> usize {
+	expand_symbols(module, &mut symbols);
+	symbols.into_iter().map(|symbol| symbol_size(module, symbol)).sum()
+}
+
+/// Size of the entries the symbol is encoded with.
+fn symbol_size(module: &elements::Module, symbol: Symbol) -> usize {
+	match symbol {
+		Symbol::Type(index) => encoded_size(&module.type_section().expect("Type section to exist").types()[index]),
+		Symbol::Import(index) => encoded_size(&module.import_section().expect("Import section to exist").entries()[index]),
+		Symbol::Global(index) => encoded_size(&module.global_section().expect("Global section to exist").entries()[index]),
+		Symbol::Function(index) => {
+			encoded_size(&module.function_section().expect("Function section to exist").entries()[index])
+				+ encoded_size(&module.code_section().expect("Code section to exist").bodies()[index])
+		},
+		Symbol::Export(index) => encoded_size(&module.export_section().expect("Export section to exist").entries()[index]),
+	}
+}
+
+fn encoded_size<T: Serialize<Error = elements::Error> + Clone>(item: &T) -> usize {
+	let mut buffer = Vec::new();
+	item.clone().serialize(&mut buffer).expect("Serialization to a vector does not fail");
+	buffer.len()
+}
+
+fn section_name(section: &elements::Section) -> String {
+	use parity_wasm::elements::Section::*;
+	match section {
+		Unparsed { id, .. } => format!("unknown({})", id),
+		Custom(custom) => format!("custom({})", custom.name()),
+		Type(_) => "type".to_string(),
+		Import(_) => "import".to_string(),
+		Function(_) => "function".to_string(),
+		Table(_) => "table".to_string(),
+		Memory(_) => "memory".to_string(),
+		Global(_) => "global".to_string(),
+		Export(_) => "export".to_string(),
+		Start(_) => "start".to_string(),
+		Element(_) => "element".to_string(),
+		DataCount(_) => "datacount".to_string(),
+		Code(_) => "code".to_string(),
+		Data(_) => "data".to_string(),
+		Name(_) => "custom(name)".to_string(),
+		Reloc(reloc) => format!("custom({})", reloc.name()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::elements;
+	use super::*;
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	#[test]
+	fn retained_size() {
+		let module = parse_wat(r#"
+			(module
+				(import "env" "ext" (func))
+				(memory 1)
+				(data (i32.const 0) "abc")
+				(func $shared
+					call 0)
+				(func $only_a
+					call $shared)
+				(func (export "a")
+					call $only_a
+					call $shared)
+				(func (export "b")
+					call $shared))
+		"#);
+
+		let profile = size_profile(&module);
+		assert_eq!(profile.total, elements::serialize(module.clone()).expect("Module to serialize").len());
+		assert_eq!(profile.total, 8 + profile.sections.iter().map(|section| section.size).sum::<usize>());
+		assert_eq!(profile.imports[0].name, "env.ext");
+		assert_eq!(profile.data.len(), 1);
+
+		let names = profile.functions.iter().map(|func| func.name.as_str()).collect::<Vec<_>>();
+		assert_eq!(names, vec!["func[1]", "func[2]", "a", "b"]);
+		let (a, b) = (&profile.exports[0], &profile.exports[1]);
+		// `shared` and the import are still used by `b`
+		assert_eq!(a.retained, a.size + profile.functions[1].size + profile.functions[2].size);
+		assert_eq!(b.retained, b.size + profile.functions[3].size);
+	}
+}
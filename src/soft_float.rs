@@ -0,0 +1,351 @@
+//! Lowering of float instructions to calls of software float routines.
+//!
+//! Every float arithmetic, comparison and conversion instruction becomes a call of the routine
+//! named after the instruction, e.g. `f32.add` calls `f32_add` of type `(f32, f32) -> f32` and
+//! `i32.trunc_f64_s` calls `i32_trunc_f64_s`. Float constants become integer constants
+//! reinterpreted as floats. The routines are either imported from the host or linked from a
+//! library module, which has to implement them with integer operations. No such library is
+//! bundled, the caller provides it.
+
+use crate::std::string::{String, ToString};
+use crate::std::vec::Vec;
+
+use parity_wasm::elements::{self, Instruction, ValueType};
+
+use crate::graph::{self, Func, ImportedOrDeclared};
+use crate::link::link;
+use crate::pass::{self, Pass, Pipeline};
+use crate::ref_list::EntryRef;
+use crate::rules::InstructionType;
+
+/// Module name the routines are imported by before the library is linked.
+const LIBRARY_MODULE: &str = "soft-float";
+
+/// Replace float instructions with calls of routines imported from `module_name`,
+/// see `SoftFloat`.
+pub fn lower_floats(module: elements::Module, module_name: &str) -> Result<elements::Module, pass::Error> {
+	Pipeline::new()
+		.with_pass(SoftFloat::imported(module_name))
+		.run(&module)
+}
+
+/// Provider of the routines.
+enum Routines<'a> {
+	Imported(&'a str),
+	Linked(&'a graph::Module),
+}
+
+/// Pass replacing float instructions with calls of software float routines.
+///
+/// The module is left without instructions forbidden by `Policy::with_forbidden_floats`,
+/// the pass fails if a linked routine uses them itself.
+pub struct SoftFloat<'a> {
+	routines: Routines<'a>,
+}
+
+impl<'a> SoftFloat<'a> {
+	/// Routines imported from the module with the name.
+	pub fn imported(module_name: &'a str) -> Self {
+		SoftFloat { routines: Routines::Imported(module_name) }
+	}
+
+	/// Routines copied from the library supplied by the caller, which exports every used routine.
+	pub fn linked(library: &'a graph::Module) -> Self {
+		SoftFloat { routines: Routines::Linked(library) }
+	}
+}
+
+/// Name and signature of the routine replacing the instruction.
+fn routine(instruction: &Instruction) -> Option<(&'static str, &'static [ValueType], ValueType)> {
+	use Instruction::*;
+	use ValueType::{F32, F64, I32, I64};
+
+	const F32_1: &[ValueType] = &[F32];
+	const F32_2: &[ValueType] = &[F32, F32];
+	const F64_1: &[ValueType] = &[F64];
+	const F64_2: &[ValueType] = &[F64, F64];
+	const I32_1: &[ValueType] = &[I32];
+	const I64_1: &[ValueType] = &[I64];
+
+	Some(match instruction {
+		F32Eq => ("f32_eq", F32_2, I32),
+		F32Ne => ("f32_ne", F32_2, I32),
+		F32Lt => ("f32_lt", F32_2, I32),
+		F32Gt => ("f32_gt", F32_2, I32),
+		F32Le => ("f32_le", F32_2, I32),
+		F32Ge => ("f32_ge", F32_2, I32),
+		F64Eq => ("f64_eq", F64_2, I32),
+		F64Ne => ("f64_ne", F64_2, I32),
+		F64Lt => ("f64_lt", F64_2, I32),
+		F64Gt => ("f64_gt", F64_2, I32),
+		F64Le => ("f64_le", F64_2, I32),
+		F64Ge => ("f64_ge", F64_2, I32),
+
+		F32Abs => ("f32_abs", F32_1, F32),
+		F32Neg => ("f32_neg", F32_1, F32),
+		F32Ceil => ("f32_ceil", F32_1, F32),
+		F32Floor => ("f32_floor", F32_1, F32),
+		F32Trunc => ("f32_trunc", F32_1, F32),
+		F32Nearest => ("f32_nearest", F32_1, F32),
+		F32Sqrt => ("f32_sqrt", F32_1, F32),
+		F32Add => ("f32_add", F32_2, F32),
+		F32Sub => ("f32_sub", F32_2, F32),
+		F32Mul => ("f32_mul", F32_2, F32),
+		F32Div => ("f32_div", F32_2, F32),
+		F32Min => ("f32_min", F32_2, F32),
+		F32Max => ("f32_max", F32_2, F32),
+		F32Copysign => ("f32_copysign", F32_2, F32),
+		F64Abs => ("f64_abs", F64_1, F64),
+		F64Neg => ("f64_neg", F64_1, F64),
+		F64Ceil => ("f64_ceil", F64_1, F64),
+		F64Floor => ("f64_floor", F64_1, F64),
+		F64Trunc => ("f64_trunc", F64_1, F64),
+		F64Nearest => ("f64_nearest", F64_1, F64),
+		F64Sqrt => ("f64_sqrt", F64_1, F64),
+		F64Add => ("f64_add", F64_2, F64),
+		F64Sub => ("f64_sub", F64_2, F64),
+		F64Mul => ("f64_mul", F64_2, F64),
+		F64Div => ("f64_div", F64_2, F64),
+		F64Min => ("f64_min", F64_2, F64),
+		F64Max => ("f64_max", F64_2, F64),
+		F64Copysign => ("f64_copysign", F64_2, F64),
+
+		I32TruncSF32 => ("i32_trunc_f32_s", F32_1, I32),
+		I32TruncUF32 => ("i32_trunc_f32_u", F32_1, I32),
+		I32TruncSF64 => ("i32_trunc_f64_s", F64_1, I32),
+		I32TruncUF64 => ("i32_trunc_f64_u", F64_1, I32),
+		I64TruncSF32 => ("i64_trunc_f32_s", F32_1, I64),
+		I64TruncUF32 => ("i64_trunc_f32_u", F32_1, I64),
+		I64TruncSF64 => ("i64_trunc_f64_s", F64_1, I64),
+		I64TruncUF64 => ("i64_trunc_f64_u", F64_1, I64),
+		F32ConvertSI32 => ("f32_convert_i32_s", I32_1, F32),
+		F32ConvertUI32 => ("f32_convert_i32_u", I32_1, F32),
+		F32ConvertSI64 => ("f32_convert_i64_s", I64_1, F32),
+		F32ConvertUI64 => ("f32_convert_i64_u", I64_1, F32),
+		F32DemoteF64 => ("f32_demote_f64", F64_1, F32),
+		F64ConvertSI32 => ("f64_convert_i32_s", I32_1, F64),
+		F64ConvertUI32 => ("f64_convert_i32_u", I32_1, F64),
+		F64ConvertSI64 => ("f64_convert_i64_s", I64_1, F64),
+		F64ConvertUI64 => ("f64_convert_i64_u", I64_1, F64),
+		F64PromoteF32 => ("f64_promote_f32", F32_1, F64),
+
+		_ => return None,
+	})
+}
+
+fn is_forbidden(instruction: &Instruction) -> bool {
+	matches!(
+		InstructionType::op(instruction),
+		InstructionType::Float | InstructionType::FloatComparison
+			| InstructionType::FloatConst | InstructionType::FloatConversion
+	)
+}
+
+impl<'a> Pass for SoftFloat<'a> {
+	fn name(&self) -> &str {
+		"soft-float"
+	}
+
+	fn run(&self, module: &mut graph::Module) -> Result<(), String> {
+		let module_name = match self.routines {
+			Routines::Imported(module_name) => module_name,
+			Routines::Linked(_) => LIBRARY_MODULE,
+		};
+
+		// Routines in the order of the first use
+		let mut used = Vec::new();
+		for func in module.funcs.iter() {
+			if let ImportedOrDeclared::Declared(body) = &func.read().origin {
+				for instruction in body.code.iter() {
+					let routine = match instruction {
+						graph::Instruction::Plain(instruction) => routine(instruction),
+						_ => None,
+					};
+					if let Some(routine) = routine.filter(|routine| !used.contains(routine)) {
+						used.push(routine);
+					}
+				}
+			}
+		}
+		let imports = used.into_iter()
+			.map(|(name, params, result)| {
+				let signature = elements::FunctionType::new(params.to_vec(), vec![result]);
+				(name, module.add_import_func(module_name, name, signature))
+			})
+			.collect::<Vec<(&str, EntryRef<Func>)>>();
+
+		pass::for_each_body(module, |_, code| {
+			let mut result = Vec::with_capacity(code.len());
+			for instruction in code.drain(..) {
+				let plain = match &instruction {
+					graph::Instruction::Plain(plain) => plain,
+					_ => {
+						result.push(instruction);
+						continue;
+					},
+				};
+				match plain {
+					Instruction::F32Const(bits) => result.extend(vec![
+						graph::Instruction::Plain(Instruction::I32Const(*bits as i32)),
+						graph::Instruction::Plain(Instruction::F32ReinterpretI32),
+					]),
+					Instruction::F64Const(bits) => result.extend(vec![
+						graph::Instruction::Plain(Instruction::I64Const(*bits as i64)),
+						graph::Instruction::Plain(Instruction::F64ReinterpretI64),
+					]),
+					plain => match routine(plain) {
+						Some((name, _, _)) => {
+							let import = imports.iter()
+								.find(|(import_name, _)| *import_name == name)
+								.expect("Every used routine is imported above");
+							result.push(graph::Instruction::Call(import.1.clone()));
+						},
+						None => result.push(instruction),
+					},
+				}
+			}
+			*code = result;
+			Ok(())
+		})?;
+
+		if let Routines::Linked(library) = self.routines {
+			link(module, &[(LIBRARY_MODULE, library)]).map_err(|error| error.to_string())?;
+			for func in module.funcs.iter() {
+				if let ImportedOrDeclared::Imported(import_module, field) = &func.read().origin {
+					if import_module == LIBRARY_MODULE {
+						return Err(format!("Library does not export `{}`", field));
+					}
+				}
+			}
+		}
+
+		// Linked routines must not bring floats back
+		pass::for_each_body(module, |index, code| {
+			for instruction in code.iter() {
+				if let graph::Instruction::Plain(instruction) = instruction {
+					if is_forbidden(instruction) {
+						return Err(format!("Function {} still uses the float instruction `{}`", index, instruction));
+					}
+				}
+			}
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::check::{check, Policy, Violation};
+
+	fn parse_wat(source: &str) -> elements::Module {
+		elements::deserialize_buffer(&wabt::wat2wasm(source).expect("Failed to wat2wasm"))
+			.expect("Failed to deserialize the module")
+	}
+
+	fn import_names(module: &elements::Module) -> Vec<(&str, &str)> {
+		module.import_section().map(|section| section.entries()).unwrap_or(&[]).iter()
+			.map(|entry| (entry.module(), entry.field()))
+			.collect()
+	}
+
+	const AVERAGE: &str = r#"
+		(module
+			(import "env" "log" (func (param i32)))
+			(func (export "average") (param f32 f32) (result i32)
+				local.get 0
+				local.get 1
+				f32.add
+				f32.const 0.5
+				f32.mul
+				f32.const 10
+				f32.lt
+			)
+		)
+	"#;
+
+	#[test]
+	fn imported_routines() {
+		let module = lower_floats(parse_wat(AVERAGE), "softfloat").expect("Lowering to succeed");
+
+		assert_eq!(
+			import_names(&module),
+			vec![("env", "log"), ("softfloat", "f32_add"), ("softfloat", "f32_mul"), ("softfloat", "f32_lt")],
+		);
+		let code = module.code_section().expect("Module to have code").bodies()[0].code().elements();
+		assert_eq!(code[..6], [
+			Instruction::GetLocal(0),
+			Instruction::GetLocal(1),
+			Instruction::Call(1),
+			Instruction::I32Const(0x3f00_0000),
+			Instruction::F32ReinterpretI32,
+			Instruction::Call(2),
+		]);
+
+		let policy = Policy::new("env")
+			.with_import("log", None)
+			.with_forbidden_floats();
+		let violations = check(&module, &policy);
+		assert!(violations.iter().all(|violation| matches!(violation, Violation::ForeignImport(..))));
+	}
+
+	#[test]
+	fn linked_routines() {
+		// Stub routines, only the absence of float instructions matters
+		let library = graph::parse(&wabt::wat2wasm(r#"
+			(module
+				(func $bits (param f32 f32) (result f32)
+					local.get 0
+					i32.reinterpret_f32
+					local.get 1
+					i32.reinterpret_f32
+					i32.xor
+					f32.reinterpret_i32
+				)
+				(export "f32_add" (func $bits))
+				(export "f32_mul" (func $bits))
+				(func (export "f32_lt") (param f32 f32) (result i32)
+					i32.const 0
+				)
+			)
+		"#).expect("Failed to wat2wasm")).expect("Library to be parsed");
+
+		let module = Pipeline::new()
+			.with_pass(SoftFloat::linked(&library))
+			.run(&parse_wat(AVERAGE))
+			.expect("Routines to be linked");
+		assert_eq!(import_names(&module), vec![("env", "log")]);
+		let policy = Policy::new("env").with_import("log", None).with_forbidden_floats();
+		assert_eq!(check(&module, &policy), vec![]);
+
+		let float_library = graph::parse(&wabt::wat2wasm(r#"
+			(module
+				(func (export "f32_add") (param f32 f32) (result f32)
+					local.get 0
+					local.get 1
+					f32.add
+				)
+			)
+		"#).expect("Failed to wat2wasm")).expect("Library to be parsed");
+		let error = Pipeline::new()
+			.with_pass(SoftFloat::linked(&float_library))
+			.run(&parse_wat(AVERAGE))
+			.unwrap_err();
+		assert_eq!(error.to_string(), "Pass `soft-float` failed: Library does not export `f32_mul`");
+
+		let module = parse_wat(r#"
+			(module
+				(func (export "add") (param f32 f32) (result f32)
+					local.get 0
+					local.get 1
+					f32.add
+				)
+			)
+		"#);
+		let error = Pipeline::new()
+			.with_pass(SoftFloat::linked(&float_library))
+			.run(&module)
+			.unwrap_err();
+		assert_eq!(error.to_string(), "Pass `soft-float` failed: Function 1 still uses the float instruction `f32.add`");
+	}
+}
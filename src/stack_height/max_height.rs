@@ -370,6 +370,18 @@ pub(crate) fn compute(func_idx: u32, module: &elements::Module) -> Result<u32, E
 				stack.push_values(1)?;
 			}
 
+			#[cfg(feature = "bulk")]
+			Bulk(bulk) => {
+				use parity_wasm::elements::BulkInstruction::*;
+				match bulk {
+					// These instructions pop the destination, the source and the length.
+					MemoryInit(_) | MemoryCopy | MemoryFill | TableInit(_) | TableCopy => {
+						stack.pop_values(3)?;
+					}
+					MemoryDrop(_) | TableDrop(_) => {}
+				}
+			}
+
 			I32Const(_) | I64Const(_) | F32Const(_) | F64Const(_) => {
 				// These instructions just push the single literal value onto the stack.
 				stack.push_values(1)?;
@@ -49,6 +49,7 @@
 //!   between the frames.
 //! - upon entry into the function entire stack frame is allocated.
 
+use crate::std::fmt;
 use crate::std::string::String;
 use crate::std::vec::Vec;
 
@@ -92,6 +93,12 @@ mod thunk;
 #[derive(Debug)]
 pub struct Error(String);
 
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		f.write_str(&self.0)
+	}
+}
+
 pub(crate) struct Context {
 	stack_height_global_idx: u32,
 	func_stack_costs: Vec<u32>,
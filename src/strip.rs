@@ -0,0 +1,127 @@
+//! Stripping of custom sections.
+//!
+//! Toolchains leave debug info (`.debug_*`), `producers`, `target_features` and
+//! linker sections (`reloc.*`, `linking`) in the module, none of which is needed to run it.
+
+use crate::std::string::String;
+use crate::std::vec::Vec;
+
+use log::trace;
+use parity_wasm::elements;
+
+/// Which custom sections survive stripping.
+///
+/// By default every custom section is removed. A custom section is kept if its name
+/// matches the allow-list and does not match the deny-list. Patterns are either exact
+/// names or prefixes ending with `*`, so `*` alone matches every section.
+///
+/// The name section is controlled only by `with_names_kept`.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+	allowed: Vec<String>,
+	denied: Vec<String>,
+	keep_names: bool,
+}
+
+impl Options {
+	/// Keep custom sections matching the pattern.
+	pub fn with_allowed(mut self, pattern: &str) -> Self {
+		self.allowed.push(pattern.into());
+		self
+	}
+
+	/// Remove custom sections matching the pattern, even if they are allowed.
+	pub fn with_denied(mut self, pattern: &str) -> Self {
+		self.denied.push(pattern.into());
+		self
+	}
+
+	/// Keep the name section.
+	pub fn with_names_kept(mut self) -> Self {
+		self.keep_names = true;
+		self
+	}
+
+	fn keeps(&self, name: &str) -> bool {
+		if name == "name" {
+			return self.keep_names;
+		}
+		let matches = |pattern: &String| match pattern.strip_suffix('*') {
+			Some(prefix) => name.starts_with(prefix),
+			None => name == pattern,
+		};
+		self.allowed.iter().any(matches) && !self.denied.iter().any(matches)
+	}
+}
+
+/// Remove custom sections according to options.
+///
+/// Returns number of removed sections.
+pub fn strip_custom_sections(module: &mut elements::Module, options: &Options) -> usize {
+	let before = module.sections().len();
+	module.sections_mut().retain(|section| {
+		let name = match section {
+			elements::Section::Custom(custom) => custom.name(),
+			elements::Section::Reloc(reloc) => reloc.name(),
+			elements::Section::Name(_) => "name",
+			_ => return true,
+		};
+		let keep = options.keeps(name);
+		if !keep {
+			trace!("Stripped custom section `{}`", name);
+		}
+		keep
+	});
+	before - module.sections().len()
+}
+
+#[cfg(test)]
+mod tests {
+	use parity_wasm::{builder, elements};
+	use super::*;
+
+	fn sample() -> elements::Module {
+		let mut module = builder::module()
+			.function()
+				.signature().build()
+				.body().build()
+				.build()
+			.build();
+		for name in &["name", ".debug_info", ".debug_line", "producers", "target_features", "sourceMappingURL"] {
+			module.sections_mut().push(elements::Section::Custom(
+				elements::CustomSection::new(name.to_string(), vec![])
+			));
+		}
+		module
+	}
+
+	fn custom_sections(module: &elements::Module) -> Vec<&str> {
+		module.custom_sections().map(|section| section.name()).collect()
+	}
+
+	#[test]
+	fn everything() {
+		let mut module = sample();
+		assert_eq!(strip_custom_sections(&mut module, &Options::default()), 6);
+		assert!(custom_sections(&module).is_empty());
+		assert_eq!(module.sections().len(), 3);
+	}
+
+	#[test]
+	fn lists() {
+		let mut module = sample();
+		let options = Options::default()
+			.with_allowed("*")
+			.with_denied(".debug_*")
+			.with_denied("producers");
+		assert_eq!(strip_custom_sections(&mut module, &options), 4);
+		assert_eq!(custom_sections(&module), vec!["target_features", "sourceMappingURL"]);
+
+		let mut module = sample();
+		let options = Options::default()
+			.with_allowed("producers")
+			.with_names_kept();
+		assert_eq!(strip_custom_sections(&mut module, &options), 4);
+		assert_eq!(custom_sections(&module), vec!["name", "producers"]);
+	}
+}
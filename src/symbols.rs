@@ -2,17 +2,28 @@
 use crate::std::collections::{HashSet as Set};
 #[cfg(not(features = "std"))]
 use crate::std::collections::{BTreeSet as Set};
+use crate::std::string::{String, ToString};
 use crate::std::vec::Vec;
+use crate::std::collections::BTreeMap;
 
 use log::trace;
 use parity_wasm::elements;
 
+/// Item of the module which can be referenced by other items.
+///
+/// Indices are positions within the corresponding section (i.e. `Function`
+/// does not include imported functions, `Import` is an index of the import entry).
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
 pub enum Symbol {
+	/// Function signature in the type section.
 	Type(usize),
+	/// Entry of the import section.
 	Import(usize),
+	/// Global declared in the global section.
 	Global(usize),
+	/// Function declared in the function section.
 	Function(usize),
+	/// Entry of the export section.
 	Export(usize),
 }
 
@@ -32,6 +43,33 @@ pub fn resolve_function(module: &elements::Module, index: u32) -> Symbol {
 	Symbol::Function(index as usize - functions as usize)
 }
 
+/// Signature of the function with the given index in the function space.
+pub fn function_type(module: &elements::Module, func_index: u32) -> Option<&elements::FunctionType> {
+	let type_index = match resolve_function(module, func_index) {
+		Symbol::Import(index) => match module.import_section()?.entries().get(index)?.external() {
+			elements::External::Function(type_index) => *type_index,
+			_ => return None,
+		},
+		Symbol::Function(index) => module.function_section()?.entries().get(index)?.type_ref(),
+		_ => return None,
+	};
+	match module.type_section()?.types().get(type_index as usize)? {
+		elements::Type::Function(func_type) => Some(func_type),
+	}
+}
+
+/// Signature in the form of `(i32, i32) -> i64`.
+pub(crate) fn signature(func_type: &elements::FunctionType) -> String {
+	let params = func_type.params().iter().map(|param| param.to_string()).collect::<Vec<_>>().join(", ");
+	match func_type.results() {
+		[] => format!("({})", params),
+		results => {
+			let results = results.iter().map(|result| result.to_string()).collect::<Vec<_>>().join(", ");
+			format!("({}) -> {}", params, results)
+		},
+	}
+}
+
 pub fn resolve_global(module: &elements::Module, index: u32) -> Symbol {
 	let mut globals = 0;
 	if let Some(import_section) = module.import_section() {
@@ -67,7 +105,18 @@ pub fn push_code_symbols(module: &elements::Module, instructions: &[elements::In
 	}
 }
 
+/// Expands the set with all symbols referenced by symbols in it (recursively).
 pub fn expand_symbols(module: &elements::Module, set: &mut Set<Symbol>) {
+	expand_symbols_with_parents(module, set, &mut BTreeMap::new());
+}
+
+/// Same as `expand_symbols`, also recording for every newly discovered symbol
+/// which symbol referenced it first.
+pub fn expand_symbols_with_parents(
+	module: &elements::Module,
+	set: &mut Set<Symbol>,
+	parents: &mut BTreeMap<Symbol, Symbol>,
+) {
 	use self::Symbol::*;
 
 	// symbols that were already processed
@@ -90,14 +139,18 @@ pub fn expand_symbols(module: &elements::Module, set: &mut Set<Symbol>) {
 						if !stop.contains(&symbol) {
 							fringe.push(symbol);
 						}
-						set.insert(symbol);
+						if set.insert(symbol) {
+							parents.insert(symbol, next);
+						}
 					},
 					elements::Internal::Global(global_idx) => {
 						let symbol = resolve_global(module, *global_idx);
 						if !stop.contains(&symbol) {
 							fringe.push(symbol);
 						}
-						set.insert(symbol);
+						if set.insert(symbol) {
+							parents.insert(symbol, next);
+						}
 					},
 					_ => {}
 				}
@@ -109,7 +162,9 @@ pub fn expand_symbols(module: &elements::Module, set: &mut Set<Symbol>) {
 					if !stop.contains(&type_symbol) {
 						fringe.push(type_symbol);
 					}
-					set.insert(type_symbol);
+					if set.insert(type_symbol) {
+						parents.insert(type_symbol, next);
+					}
 				}
 			},
 			Function(idx) => {
@@ -120,7 +175,9 @@ pub fn expand_symbols(module: &elements::Module, set: &mut Set<Symbol>) {
 					if !stop.contains(&symbol) {
 						fringe.push(symbol);
 					}
-					set.insert(symbol);
+					if set.insert(symbol) {
+						parents.insert(symbol, next);
+					}
 				}
 
 				let signature = &module.function_section().expect("Functions section to exist").entries()[idx];
@@ -128,7 +185,9 @@ pub fn expand_symbols(module: &elements::Module, set: &mut Set<Symbol>) {
 				if !stop.contains(&type_symbol) {
 					fringe.push(type_symbol);
 				}
-				set.insert(type_symbol);
+				if set.insert(type_symbol) {
+					parents.insert(type_symbol, next);
+				}
 			},
 			Global(idx) => {
 				let entry = &module.global_section().expect("Global section to exist").entries()[idx];
@@ -138,7 +197,9 @@ pub fn expand_symbols(module: &elements::Module, set: &mut Set<Symbol>) {
 					if !stop.contains(&symbol) {
 						fringe.push(symbol);
 					}
-					set.insert(symbol);
+					if set.insert(symbol) {
+						parents.insert(symbol, next);
+					}
 				}
 			}
 			_ => {}